@@ -1,5 +1,7 @@
 mod ed25519_scalar_mul;
 mod read_xdr;
+mod storage_map_ops;
 
 pub(crate) use ed25519_scalar_mul::*;
 pub(crate) use read_xdr::*;
+pub(crate) use storage_map_ops::*;
@@ -0,0 +1,46 @@
+use crate::common::HostCostMeasurement;
+use rand::{rngs::StdRng, seq::SliceRandom};
+use soroban_env_host::{
+    budget::AsBudget,
+    cost_runner::{PersistentStorageMapInsertRun, PersistentStorageMapInsertSample},
+    storage::StorageMap,
+    xdr::{ContractDataDurability, LedgerKey, LedgerKeyContractData, ScAddress, ScVal},
+    Host, PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION,
+};
+use std::rc::Rc;
+
+pub(crate) struct PersistentStorageMapInsertMeasure;
+
+// Measures the costs of inserting new keys into the persistent storage maps of
+// varying sizes, relative to the `MemCpy` input charged for the insertion. The
+// result is meant to be compared against the `MemCpy` model, which covers the
+// tree search and the path copy of the persistent map. The input value is the
+// size of the map.
+impl HostCostMeasurement for PersistentStorageMapInsertMeasure {
+    type Runner = PersistentStorageMapInsertRun;
+
+    fn new_random_case(
+        host: &Host,
+        rng: &mut StdRng,
+        input: u64,
+    ) -> PersistentStorageMapInsertSample {
+        let size = 1 + input * Self::STEP_SIZE;
+        let key = |i: u64| {
+            Rc::new(LedgerKey::ContractData(LedgerKeyContractData {
+                contract: ScAddress::Contract([0; 32].into()),
+                key: ScVal::U64(i),
+                durability: ContractDataDurability::Persistent,
+            }))
+        };
+        // The map holds the even keys, while the odd ones are inserted.
+        let entries = (0..size).map(|i| (key(2 * i), None)).collect();
+        let map = StorageMap::from_map(entries, host.as_budget())
+            .and_then(|map| {
+                map.for_protocol(PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION, host.as_budget())
+            })
+            .unwrap();
+        let mut keys: Vec<_> = (0..size).map(|i| key(2 * i + 1)).collect();
+        keys.shuffle(rng);
+        PersistentStorageMapInsertSample { map, keys }
+    }
+}
@@ -54,6 +54,7 @@ pub(crate) fn for_each_experimental_cost_measurement<B: Benchmark>(
     call_bench::<B, Ed25519ScalarMulMeasure>(&mut params)?;
     call_bench::<B, VerifyEd25519SigMeasure>(&mut params)?;
     call_bench::<B, ReadXdrByteArrayMeasure>(&mut params)?;
+    call_bench::<B, PersistentStorageMapInsertMeasure>(&mut params)?;
     Ok(params)
 }
 
@@ -1,37 +1,627 @@
-// This is a test application that embeds and runs the host. It exists at the
-// moment just to provide a target for the `cackle` API-checker to observe the
+// This is a command-line application that embeds and runs the host. It can be
+// used to reproduce an invocation of a host function (for example, a failing
+// transaction fetched from the network) locally, given all of its inputs in
+// XDR form.
+//
+// It also provides a target for the `cackle` API-checker to observe the
 // linking of soroban-env-host as a dependency, and thus check API uses inside
 // soroban-env-host (this is probably a limitation of the `cackle` tool but at
 // the moment I haven't figured out a workaround).
-//
-// In the future this might also provide some other top-level host functionality
-// that users or developers might wish to run on the command-line.
-
-use soroban_env_host::{budget::Budget, e2e_invoke::invoke_host_function, LedgerInfo};
-
-fn main() {
-    let budget = Budget::default();
-    let enable_diagnostics = true;
-    let encoded_host_fn = &[0u8];
-    let encoded_resources = &[0u8];
-    let encoded_source_account = &[0u8];
-    let encoded_auth_entries = [[0u8]].iter();
-    let ledger_info = LedgerInfo::default();
-    let encoded_ledger_entries = [[0u8]].iter();
-    let encoded_ttl_entries = [[0u8]].iter();
-    let base_prng_seed = &[0u8];
-    let mut diagnostic_events = Vec::new();
-    let _ = invoke_host_function(
+
+use std::{fmt::Display, process::ExitCode, rc::Rc};
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use soroban_env_host::{
+    budget::Budget,
+    e2e_invoke::{
+        invoke_host_function, simulate_invoke_host_function, InvokeHostFunctionResult,
+        LedgerEntryChange, SimulateInvokeHostFunctionResult,
+    },
+    invocation_bundle::{InvocationBundle, InvocationOutcome},
+    snapshot_source::FileSnapshotSource,
+    storage::SnapshotSource,
+    xdr::{
+        AccountId, ContractEvent, DiagnosticEvent, Hash, HostFunction, LedgerEntry, LedgerKey,
+        ReadXdr, ScVal, SorobanAuthorizationEntry, SorobanResources, TtlEntry, WriteXdr,
+    },
+    HostError, LedgerInfo, DEFAULT_XDR_RW_LIMITS,
+};
+
+const USAGE: &str = "\
+Invokes a host function given its inputs in XDR form and prints the outcome.
+
+USAGE:
+    soroban-env-host --bundle <FILE>
+    soroban-env-host --host-fn <FILE> --resources <FILE> --source-account <FILE>
+                     --ledger-info <FILE> [--auth-entry <FILE>]...
+                     [--ledger-entry <FILE>]... [--ttl-entry <FILE>]...
+                     [--snapshot <PATH>] [--config <PATH>]
+                     [--prng-seed <HEX>] [--no-diagnostics]
+    soroban-env-host --simulate --snapshot <PATH> --host-fn <FILE>
+                     --source-account <FILE> --ledger-info <FILE>
+                     [--config <PATH>] [--prng-seed <HEX>] [--no-diagnostics]
+                     [--validate-spec]
+
+With `--bundle`, all the inputs (including the budget configuration) are
+loaded from an invocation bundle file written by
+`InvocationBundle::write_to`, so it can't be combined with any other argument.
+When the bundle contains the recorded outcome of the invocation, the outcome
+of the replay is compared to it and the exit status reflects whether they are
+identical.
+
+With `--snapshot`, the ledger entries of the footprint are loaded from a
+snapshot file (or a directory of snapshot files) instead of `--ledger-entry`
+and `--ttl-entry` files. The snapshot files contain `LedgerEntry` XDR
+(including the TTL entries), either as base64 lines, or as a JSON array.
+
+With `--simulate`, the host function is run in recording mode against the
+snapshot, which computes the footprint and the authorization entries instead
+of requiring the resources to be provided. With `--validate-spec`, the
+arguments of the invoked contract function are also checked against its spec.
+
+The budget is built from the network configuration setting entries
+(`ContractComputeV0`, `ContractCostParamsCpuInstructions` and
+`ContractCostParamsMemoryBytes`) loaded from the `--config` snapshot, or from
+the `--snapshot` when `--config` is not set. The CPU limit is the
+`instructions` value of the transaction resources (the network
+per-transaction limit in simulation). Without the configuration settings,
+the default cost parameters of this host are used.
+
+XDR files may contain either raw binary XDR or its base64 encoding.
+
+The ledger info file contains a JSON object with the following fields:
+`protocol_version`, `sequence_number`, `timestamp`, `network_id` (hex) or
+`network_passphrase`, `base_reserve`, `min_temp_entry_ttl`,
+`min_persistent_entry_ttl` and `max_entry_ttl`.
+";
+
+const FLAGS_WITH_VALUE: &[&str] = &[
+    "--bundle",
+    "--host-fn",
+    "--resources",
+    "--source-account",
+    "--ledger-info",
+    "--auth-entry",
+    "--ledger-entry",
+    "--ttl-entry",
+    "--prng-seed",
+    "--snapshot",
+    "--config",
+];
+
+/// All the inputs of `invoke_host_function`, validated and re-encoded as
+/// binary XDR.
+struct Invocation {
+    host_fn: Vec<u8>,
+    resources: Vec<u8>,
+    source_account: Vec<u8>,
+    auth_entries: Vec<Vec<u8>>,
+    ledger_info: LedgerInfo,
+    ledger_entries: Vec<Vec<u8>>,
+    ttl_entries: Vec<Vec<u8>>,
+    base_prng_seed: [u8; 32],
+}
+
+/// Command-line options that are not part of the invocation inputs.
+struct Options {
+    enable_diagnostics: bool,
+    snapshot: Option<String>,
+    config: Option<String>,
+    simulate: bool,
+    validate_contract_spec: bool,
+}
+
+enum Command {
+    /// Replay the invocation bundle at the given path.
+    Bundle(String),
+    Invoke(Invocation, Options),
+}
+
+fn err(msg: impl Display) -> String {
+    msg.to_string()
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("failed to read '{path}': {e}"))
+}
+
+/// Decodes XDR of type `T` from either binary XDR or base64 and returns it
+/// re-encoded as binary XDR.
+fn decode_xdr<T: ReadXdr + WriteXdr>(data: &[u8], what: &str) -> Result<Vec<u8>, String> {
+    let trimmed = String::from_utf8(data.to_vec())
+        .ok()
+        .map(|s| s.trim().to_string());
+    let value = match trimmed.and_then(|s| T::from_xdr_base64(s, DEFAULT_XDR_RW_LIMITS).ok()) {
+        Some(v) => v,
+        None => T::from_xdr(data, DEFAULT_XDR_RW_LIMITS)
+            .map_err(|e| format!("failed to decode {what}: {e}"))?,
+    };
+    value
+        .to_xdr(DEFAULT_XDR_RW_LIMITS)
+        .map_err(|e| format!("failed to encode {what}: {e}"))
+}
+
+fn decode_hex_32(s: &str, what: &str) -> Result<[u8; 32], String> {
+    hex::decode(s.trim())
+        .ok()
+        .and_then(|v| v.try_into().ok())
+        .ok_or_else(|| format!("{what} must be 32 hex-encoded bytes"))
+}
+
+fn json_field<'a>(obj: &'a Value, name: &str) -> Result<&'a Value, String> {
+    obj.get(name)
+        .ok_or_else(|| format!("missing field '{name}'"))
+}
+
+fn json_str<'a>(obj: &'a Value, name: &str) -> Result<&'a str, String> {
+    json_field(obj, name)?
+        .as_str()
+        .ok_or_else(|| format!("field '{name}' must be a string"))
+}
+
+fn json_u64(obj: &Value, name: &str) -> Result<u64, String> {
+    json_field(obj, name)?
+        .as_u64()
+        .ok_or_else(|| format!("field '{name}' must be an unsigned integer"))
+}
+
+fn json_u32(obj: &Value, name: &str) -> Result<u32, String> {
+    u32::try_from(json_u64(obj, name)?).map_err(|_| format!("field '{name}' must fit into u32"))
+}
+
+fn parse_ledger_info(obj: &Value) -> Result<LedgerInfo, String> {
+    let network_id = match (obj.get("network_id"), obj.get("network_passphrase")) {
+        (Some(_), None) => decode_hex_32(json_str(obj, "network_id")?, "network_id")?,
+        (None, Some(_)) => Sha256::digest(json_str(obj, "network_passphrase")?.as_bytes()).into(),
+        _ => {
+            return Err(err(
+                "exactly one of 'network_id' and 'network_passphrase' must be set",
+            ))
+        }
+    };
+    Ok(LedgerInfo {
+        protocol_version: json_u32(obj, "protocol_version")?,
+        sequence_number: json_u32(obj, "sequence_number")?,
+        timestamp: json_u64(obj, "timestamp")?,
+        network_id,
+        base_reserve: json_u32(obj, "base_reserve")?,
+        min_temp_entry_ttl: json_u32(obj, "min_temp_entry_ttl")?,
+        min_persistent_entry_ttl: json_u32(obj, "min_persistent_entry_ttl")?,
+        max_entry_ttl: json_u32(obj, "max_entry_ttl")?,
+    })
+}
+
+fn parse_json(data: &[u8], what: &str) -> Result<Value, String> {
+    serde_json::from_slice(data).map_err(|e| format!("failed to parse {what}: {e}"))
+}
+
+fn parse_args(args: Vec<String>) -> Result<Command, String> {
+    let mut bundle = None;
+    let mut has_invocation_args = false;
+    let mut host_fn = None;
+    let mut resources = None;
+    let mut source_account = None;
+    let mut ledger_info = None;
+    let mut auth_entries = vec![];
+    let mut ledger_entries = vec![];
+    let mut ttl_entries = vec![];
+    let mut base_prng_seed = None;
+    let mut options = Options {
+        enable_diagnostics: true,
+        snapshot: None,
+        config: None,
+        simulate: false,
+        validate_contract_spec: false,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            return Err(err(USAGE));
+        }
+        if arg != "--bundle" {
+            has_invocation_args = true;
+        }
+        if arg == "--no-diagnostics" {
+            options.enable_diagnostics = false;
+            continue;
+        }
+        if arg == "--simulate" {
+            options.simulate = true;
+            continue;
+        }
+        if arg == "--validate-spec" {
+            options.validate_contract_spec = true;
+            continue;
+        }
+        if !FLAGS_WITH_VALUE.contains(&arg.as_str()) {
+            return Err(format!("unknown argument '{arg}'\n\n{USAGE}"));
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for '{arg}'"))?;
+        match arg.as_str() {
+            "--bundle" => bundle = Some(value),
+            "--host-fn" => host_fn = Some(decode_xdr::<HostFunction>(&read_file(&value)?, &value)?),
+            "--resources" => {
+                resources = Some(decode_xdr::<SorobanResources>(&read_file(&value)?, &value)?)
+            }
+            "--source-account" => {
+                source_account = Some(decode_xdr::<AccountId>(&read_file(&value)?, &value)?)
+            }
+            "--ledger-info" => {
+                ledger_info = Some(parse_ledger_info(&parse_json(
+                    &read_file(&value)?,
+                    &value,
+                )?)?)
+            }
+            "--auth-entry" => auth_entries.push(decode_xdr::<SorobanAuthorizationEntry>(
+                &read_file(&value)?,
+                &value,
+            )?),
+            "--ledger-entry" => {
+                ledger_entries.push(decode_xdr::<LedgerEntry>(&read_file(&value)?, &value)?)
+            }
+            "--ttl-entry" => ttl_entries.push(decode_xdr::<TtlEntry>(&read_file(&value)?, &value)?),
+            "--prng-seed" => base_prng_seed = Some(decode_hex_32(&value, "PRNG seed")?),
+            "--snapshot" => options.snapshot = Some(value),
+            "--config" => options.config = Some(value),
+            _ => unreachable!(),
+        }
+    }
+    if let Some(bundle) = bundle {
+        if has_invocation_args {
+            return Err(format!(
+                "'--bundle' can't be combined with other arguments\n\n{USAGE}"
+            ));
+        }
+        return Ok(Command::Bundle(bundle));
+    }
+    if options.snapshot.is_some() && !(ledger_entries.is_empty() && ttl_entries.is_empty()) {
+        return Err(err(
+            "'--snapshot' can't be combined with '--ledger-entry' and '--ttl-entry'",
+        ));
+    }
+    if options.simulate && options.snapshot.is_none() {
+        return Err(format!("'--simulate' requires '--snapshot'\n\n{USAGE}"));
+    }
+    if options.validate_contract_spec && !options.simulate {
+        return Err(format!(
+            "'--validate-spec' requires '--simulate'\n\n{USAGE}"
+        ));
+    }
+
+    let missing = |name: &str| format!("missing required argument '{name}'\n\n{USAGE}");
+    let invocation = Invocation {
+        host_fn: host_fn.ok_or_else(|| missing("--host-fn"))?,
+        // Resources are not used (and thus not required) in simulation.
+        resources: match resources {
+            Some(resources) => resources,
+            None if options.simulate => vec![],
+            None => return Err(missing("--resources")),
+        },
+        source_account: source_account.ok_or_else(|| missing("--source-account"))?,
+        auth_entries,
+        ledger_info: ledger_info.ok_or_else(|| missing("--ledger-info"))?,
+        ledger_entries,
+        ttl_entries,
+        base_prng_seed: base_prng_seed.unwrap_or([0; 32]),
+    };
+    Ok(Command::Invoke(invocation, options))
+}
+
+/// Replaces the ledger entries of the invocation with the entries of its
+/// footprint found in the snapshot.
+fn load_footprint_entries(
+    invocation: &mut Invocation,
+    snapshot: &FileSnapshotSource,
+) -> Result<(), String> {
+    let resources =
+        SorobanResources::from_xdr(&invocation.resources, DEFAULT_XDR_RW_LIMITS).map_err(err)?;
+    invocation.ledger_entries.clear();
+    invocation.ttl_entries.clear();
+    for key in resources
+        .footprint
+        .read_only
+        .iter()
+        .chain(resources.footprint.read_write.iter())
+    {
+        let key = Rc::new(key.clone());
+        if !snapshot.has(&key).map_err(err)? {
+            continue;
+        }
+        let (entry, live_until_ledger) = snapshot.get(&key).map_err(err)?;
+        // Entries without TTL are accompanied by empty TTL buffers.
+        let encoded_ttl_entry = match live_until_ledger {
+            Some(live_until_ledger) => {
+                let encoded_key = key.to_xdr(DEFAULT_XDR_RW_LIMITS).map_err(err)?;
+                TtlEntry {
+                    key_hash: Hash(Sha256::digest(encoded_key).into()),
+                    live_until_ledger_seq: live_until_ledger,
+                }
+                .to_xdr(DEFAULT_XDR_RW_LIMITS)
+                .map_err(err)?
+            }
+            None => vec![],
+        };
+        invocation
+            .ledger_entries
+            .push(entry.to_xdr(DEFAULT_XDR_RW_LIMITS).map_err(err)?);
+        invocation.ttl_entries.push(encoded_ttl_entry);
+    }
+    Ok(())
+}
+
+fn load_snapshot(path: &str) -> Result<FileSnapshotSource, String> {
+    FileSnapshotSource::from_path(path)
+        .map_err(|e| format!("failed to load snapshot '{path}': {e}"))
+}
+
+/// Builds the budget from the network configuration settings found in
+/// `config` and the given CPU limit (the network per-transaction limit when
+/// `None`).
+fn build_budget(
+    config: Option<&FileSnapshotSource>,
+    cpu_limit: Option<u64>,
+) -> Result<Budget, String> {
+    let network_budget = match config {
+        Some(config) => Budget::try_from_snapshot_source(config)
+            .map_err(|e| format!("failed to load the network configuration settings: {e}"))?,
+        None => {
+            eprintln!(
+                "warning: no network configuration settings provided, using the default cost parameters"
+            );
+            Budget::default()
+        }
+    };
+    let Some(cpu_limit) = cpu_limit else {
+        return Ok(network_budget);
+    };
+    Budget::try_from_configs(
+        cpu_limit,
+        network_budget.get_mem_bytes_remaining().map_err(err)?,
+        network_budget.get_cpu_cost_params().map_err(err)?,
+        network_budget.get_mem_cost_params().map_err(err)?,
+    )
+    .map_err(err)
+}
+
+fn print_result(
+    res: &InvokeHostFunctionResult,
+    diagnostic_events: &[DiagnosticEvent],
+    budget: &Budget,
+) -> Result<(), String> {
+    match &res.encoded_invoke_result {
+        Ok(encoded) => {
+            let val = ScVal::from_xdr(encoded, DEFAULT_XDR_RW_LIMITS).map_err(err)?;
+            println!("Result: {val:#?}");
+        }
+        Err(e) => println!("Result: failed with {e:?}"),
+    }
+
+    print_keys(
+        "Footprint entries that have not been accessed",
+        &res.encoded_unused_keys,
+    )?;
+    print_keys(
+        "Read-write footprint entries that have only been read",
+        &res.encoded_unwritten_read_write_keys,
+    )?;
+
+    print_ledger_changes(&res.ledger_changes)?;
+    print_events(&res.encoded_contract_events, diagnostic_events)?;
+    println!("\nResources: {:#?}", res.resources);
+    println!("\nBudget:\n{budget}");
+    Ok(())
+}
+
+fn print_keys(title: &str, encoded_keys: &[Vec<u8>]) -> Result<(), String> {
+    if !encoded_keys.is_empty() {
+        println!("\n{title} ({}):", encoded_keys.len());
+        for encoded in encoded_keys {
+            let key = LedgerKey::from_xdr(encoded, DEFAULT_XDR_RW_LIMITS).map_err(err)?;
+            println!("- {key:#?}");
+        }
+    }
+    Ok(())
+}
+
+fn print_simulation_result(
+    res: &SimulateInvokeHostFunctionResult,
+    diagnostic_events: &[DiagnosticEvent],
+    budget: &Budget,
+) -> Result<(), String> {
+    match &res.encoded_invoke_result {
+        Ok(encoded) => {
+            let val = ScVal::from_xdr(encoded, DEFAULT_XDR_RW_LIMITS).map_err(err)?;
+            println!("Result: {val:#?}");
+        }
+        Err(e) => println!("Result: failed with {e:?}"),
+    }
+    println!("\nFootprint: {:#?}", res.footprint);
+    print_keys(
+        "Archived entries that have to be restored",
+        &res.encoded_archived_keys,
+    )?;
+    println!("\nAuthorization entries ({}):", res.auth_entries.len());
+    for entry in &res.auth_entries {
+        println!("- {entry:#?}");
+    }
+    print_ledger_changes(&res.ledger_changes)?;
+    print_events(&res.encoded_contract_events, diagnostic_events)?;
+    println!("\nResources: {:#?}", res.resources);
+    println!("\nBudget:\n{budget}");
+    Ok(())
+}
+
+fn print_ledger_changes(ledger_changes: &[LedgerEntryChange]) -> Result<(), String> {
+    println!("\nLedger changes ({}):", ledger_changes.len());
+    for change in ledger_changes {
+        let key = LedgerKey::from_xdr(&change.encoded_key, DEFAULT_XDR_RW_LIMITS).map_err(err)?;
+        println!(
+            "- key ({}): {key:#?}",
+            if change.read_only {
+                "read-only"
+            } else {
+                "read-write"
+            }
+        );
+        println!("  old entry size: {} bytes", change.old_entry_size_bytes);
+        match &change.encoded_new_value {
+            Some(encoded) => {
+                let entry = LedgerEntry::from_xdr(encoded, DEFAULT_XDR_RW_LIMITS).map_err(err)?;
+                println!("  new entry ({} bytes): {entry:#?}", encoded.len());
+            }
+            None if !change.read_only => println!("  new entry: removed"),
+            None => (),
+        }
+        if let Some(ttl_change) = &change.ttl_change {
+            println!(
+                "  live until ledger: {} -> {}",
+                ttl_change.old_live_until_ledger, ttl_change.new_live_until_ledger
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_events(
+    encoded_contract_events: &[Vec<u8>],
+    diagnostic_events: &[DiagnosticEvent],
+) -> Result<(), String> {
+    println!("\nContract events ({}):", encoded_contract_events.len());
+    for encoded in encoded_contract_events {
+        let event = ContractEvent::from_xdr(encoded, DEFAULT_XDR_RW_LIMITS).map_err(err)?;
+        println!("- {event:#?}");
+    }
+
+    println!("\nDiagnostic events ({}):", diagnostic_events.len());
+    for event in diagnostic_events {
+        println!("- {event:#?}");
+    }
+    Ok(())
+}
+
+fn run() -> Result<bool, String> {
+    match parse_args(std::env::args().skip(1).collect())? {
+        Command::Bundle(path) => run_bundle(&path),
+        Command::Invoke(invocation, options) => run_invocation(invocation, options),
+    }
+}
+
+fn run_bundle(path: &str) -> Result<bool, String> {
+    let bundle = InvocationBundle::read_from(read_file(path)?.as_slice())
+        .map_err(|e| format!("failed to load bundle '{path}': {e}"))?;
+    let budget = bundle.budget_config.to_budget().map_err(err)?;
+    let mut diagnostic_events = vec![];
+    let res = bundle.invoke_with_budget(&budget, &mut diagnostic_events);
+    let succeeded = match &res {
+        Ok(res) => {
+            print_result(res, &diagnostic_events, &budget)?;
+            res.encoded_invoke_result.is_ok()
+        }
+        Err(e) => print_failure(e, &diagnostic_events, &budget)?,
+    };
+    if bundle.outcome.is_none() {
+        return Ok(succeeded);
+    }
+    let report = bundle.compare_outcome(InvocationOutcome::from_invoke_result(
+        &res,
+        &diagnostic_events,
+    ));
+    if report.host_version_differs {
+        println!(
+            "\nThe bundle has been captured by host {} ({})",
+            bundle.host_version, bundle.host_revision
+        );
+    }
+    if report.is_identical() {
+        println!("\nReplay: identical to the recorded outcome");
+    } else {
+        println!("\nReplay: differs from the recorded outcome:");
+        for mismatch in &report.mismatches {
+            println!("- {mismatch}");
+        }
+    }
+    Ok(report.is_identical())
+}
+
+fn run_invocation(mut invocation: Invocation, options: Options) -> Result<bool, String> {
+    let snapshot = options.snapshot.as_deref().map(load_snapshot).transpose()?;
+    let config = options.config.as_deref().map(load_snapshot).transpose()?;
+    let config = config.as_ref().or(snapshot.as_ref());
+    let mut diagnostic_events = vec![];
+    if let Some(snapshot) = &snapshot {
+        if options.simulate {
+            let budget = build_budget(config, None)?;
+            let res = simulate_invoke_host_function(
+                &budget,
+                options.enable_diagnostics,
+                options.validate_contract_spec,
+                &invocation.host_fn,
+                &invocation.source_account,
+                invocation.ledger_info,
+                Rc::new(snapshot.clone()),
+                &invocation.base_prng_seed.to_vec(),
+                &mut diagnostic_events,
+            );
+            return match res {
+                Ok(res) => {
+                    print_simulation_result(&res, &diagnostic_events, &budget)?;
+                    Ok(res.encoded_invoke_result.is_ok())
+                }
+                Err(e) => print_failure(&e, &diagnostic_events, &budget),
+            };
+        }
+        load_footprint_entries(&mut invocation, snapshot)?;
+    }
+    let resources =
+        SorobanResources::from_xdr(&invocation.resources, DEFAULT_XDR_RW_LIMITS).map_err(err)?;
+    let budget = build_budget(config, Some(resources.instructions as u64))?;
+    let res = invoke_host_function(
         &budget,
-        enable_diagnostics,
-        encoded_host_fn,
-        encoded_resources,
-        encoded_source_account,
-        encoded_auth_entries,
-        ledger_info,
-        encoded_ledger_entries,
-        encoded_ttl_entries,
-        base_prng_seed,
+        options.enable_diagnostics,
+        &invocation.host_fn,
+        &invocation.resources,
+        &invocation.source_account,
+        invocation.auth_entries.iter(),
+        invocation.ledger_info,
+        invocation.ledger_entries.iter(),
+        invocation.ttl_entries.iter(),
+        &invocation.base_prng_seed.to_vec(),
         &mut diagnostic_events,
     );
+    match res {
+        Ok(res) => {
+            print_result(&res, &diagnostic_events, &budget)?;
+            Ok(res.encoded_invoke_result.is_ok())
+        }
+        Err(e) => print_failure(&e, &diagnostic_events, &budget),
+    }
+}
+
+fn print_failure(
+    e: &HostError,
+    diagnostic_events: &[DiagnosticEvent],
+    budget: &Budget,
+) -> Result<bool, String> {
+    println!("Invocation failed: {e:?}");
+    println!("\nDiagnostic events ({}):", diagnostic_events.len());
+    for event in diagnostic_events {
+        println!("- {event:#?}");
+    }
+    println!("\nBudget:\n{budget}");
+    Ok(false)
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::from(2)
+        }
+    }
 }
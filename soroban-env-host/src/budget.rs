@@ -1,9 +1,14 @@
+mod charge_histogram;
+mod config_settings;
+mod cost_tree;
 mod dimension;
 mod limits;
 mod model;
 mod util;
 mod wasmi_helper;
 
+pub use charge_histogram::{ChargeHistogram, RepricedUsage};
+pub use cost_tree::{CostFrame, CostTreeCharge, CostTreeNode, FoldedStackWeight, FoldedStacks};
 pub(crate) use limits::DepthLimiter;
 pub use limits::{DEFAULT_HOST_DEPTH_LIMIT, DEFAULT_XDR_RW_LIMITS};
 pub use model::COST_MODEL_LIN_TERM_SCALE_BITS;
@@ -20,9 +25,10 @@ use crate::{
     Host, HostError,
 };
 
+use cost_tree::CostTreeRecorder;
 use dimension::{BudgetDimension, IsCpu, IsShadowMode};
 use model::ScaledU64;
-use wasmi_helper::FuelConfig;
+pub(crate) use wasmi_helper::FuelConfig;
 
 #[derive(Clone, Default)]
 pub struct CostTracker {
@@ -119,6 +125,9 @@ pub(crate) struct BudgetImpl {
     is_in_shadow_mode: bool,
     fuel_config: FuelConfig,
     depth_limit: u32,
+    /// For reporting only, like the `tracker`
+    cost_tree: Option<CostTreeRecorder>,
+    charge_histogram: Option<ChargeHistogram>,
 }
 
 impl BudgetImpl {
@@ -136,6 +145,8 @@ impl BudgetImpl {
             is_in_shadow_mode: false,
             fuel_config: Default::default(),
             depth_limit: DEFAULT_HOST_DEPTH_LIMIT,
+            cost_tree: None,
+            charge_histogram: None,
         };
 
         b.cpu_insns.reset(cpu_limit);
@@ -165,6 +176,9 @@ impl BudgetImpl {
                 // internal logic error, a wrong cost type has been passed in
                 _ => return Err((ScErrorType::Budget, ScErrorCode::InternalError).into()),
             };
+            if let Some(charge_histogram) = self.charge_histogram.as_mut() {
+                charge_histogram.record(ty, iterations, input);
+            }
         }
 
         let cpu_charged = self.cpu_insns.charge(
@@ -177,19 +191,31 @@ impl BudgetImpl {
         if !self.is_in_shadow_mode {
             tracker.cpu = tracker.cpu.saturating_add(cpu_charged);
         }
-        self.cpu_insns
-            .check_budget_limit(IsShadowMode(self.is_in_shadow_mode))?;
-
-        let mem_charged = self.mem_bytes.charge(
-            ty,
-            iterations,
-            input,
-            IsCpu(false),
-            IsShadowMode(self.is_in_shadow_mode),
-        )?;
+        let cpu_limit_res = self
+            .cpu_insns
+            .check_budget_limit(IsShadowMode(self.is_in_shadow_mode));
+
+        // Memory is not charged once the CPU limit has been exceeded.
+        let mem_charged = if cpu_limit_res.is_ok() {
+            self.mem_bytes.charge(
+                ty,
+                iterations,
+                input,
+                IsCpu(false),
+                IsShadowMode(self.is_in_shadow_mode),
+            )?
+        } else {
+            0
+        };
         if !self.is_in_shadow_mode {
             tracker.mem = tracker.mem.saturating_add(mem_charged);
+            // Record the charge before the limit checks, so that the charge
+            // that has exceeded the budget shows up in the cost tree.
+            if let Some(cost_tree) = self.cost_tree.as_mut() {
+                cost_tree.record(ty, iterations, cpu_charged, mem_charged);
+            }
         }
+        cpu_limit_res?;
         self.mem_bytes
             .check_budget_limit(IsShadowMode(self.is_in_shadow_mode))
     }
@@ -228,6 +254,8 @@ impl Default for BudgetImpl {
             is_in_shadow_mode: false,
             fuel_config: Default::default(),
             depth_limit: DEFAULT_HOST_DEPTH_LIMIT,
+            cost_tree: None,
+            charge_histogram: None,
         };
 
         for ct in ContractCostType::variants() {
@@ -264,6 +292,11 @@ impl Default for BudgetImpl {
                 // avoid having anything that can be zero cost and approximate
                 // whatever function call, arg-shuffling, spills, reloads or
                 // other flotsam accumulates around a typical memory copy.
+                // Starting from `PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION` the
+                // tree search and path copy of the storage maps are charged
+                // as memcpy as well. This model has not been recalibrated for
+                // them; the `PersistentStorageMapInsert` experimental
+                // measurement can be used for comparing the two.
                 ContractCostType::MemCpy => {
                     cpu.const_term = 42;
                     cpu.lin_term = ScaledU64::from_unscaled_u64(1).safe_div(8);
@@ -505,6 +538,16 @@ impl Debug for BudgetImpl {
             )?;
         }
         writeln!(f, "{:=<165}", "")?;
+        if let Some(cost_tree) = &self.cost_tree {
+            for (weight, name) in [
+                (FoldedStackWeight::CpuInsns, "cpu_insns"),
+                (FoldedStackWeight::MemBytes, "mem_bytes"),
+            ] {
+                writeln!(f, "Folded stacks ({}):", name)?;
+                write!(f, "{}", cost_tree.root().folded_stacks(weight))?;
+            }
+            writeln!(f, "{:=<165}", "")?;
+        }
         writeln!(
             f,
             "Internal details (diagnostics info, does not affect fees) "
@@ -707,6 +750,42 @@ impl Budget {
         Ok(())
     }
 
+    /// Runs a user provided closure with the limits lowered such that at most
+    /// `cpu` instructions and `mem` bytes can be consumed by it, and restores
+    /// the limits afterwards. The lowered limits never exceed the current
+    /// ones.
+    ///
+    /// The outer `Err` means the restored limits are exceeded, while the inner
+    /// result is the one returned by the closure, i.e. an inner budget error
+    /// means that only the lowered limits have been exceeded.
+    #[cfg(feature = "next")]
+    pub(crate) fn with_sub_limits<T, F>(
+        &self,
+        cpu: u64,
+        mem: u64,
+        f: F,
+    ) -> Result<Result<T, HostError>, HostError>
+    where
+        F: FnOnce() -> Result<T, HostError>,
+    {
+        let (cpu_limit, mem_limit) = self.with_mut_budget(|mut b| {
+            let limits = (b.cpu_insns.limit, b.mem_bytes.limit);
+            b.cpu_insns.limit = b.cpu_insns.total_count.saturating_add(cpu).min(limits.0);
+            b.mem_bytes.limit = b.mem_bytes.total_count.saturating_add(mem).min(limits.1);
+            Ok(limits)
+        })?;
+
+        let res = f();
+
+        self.with_mut_budget(|mut b| {
+            b.cpu_insns.limit = cpu_limit;
+            b.mem_bytes.limit = mem_limit;
+            b.cpu_insns.check_budget_limit(IsShadowMode(false))?;
+            b.mem_bytes.check_budget_limit(IsShadowMode(false))
+        })?;
+        Ok(res)
+    }
+
     pub fn get_tracker(&self, ty: ContractCostType) -> Result<CostTracker, HostError> {
         self.0
             .try_borrow_or_err()?
@@ -737,6 +816,10 @@ impl Budget {
         self.0.try_borrow_mut_or_err()?.get_wasmi_fuel_remaining()
     }
 
+    pub(crate) fn fuel_config(&self) -> Result<FuelConfig, HostError> {
+        Ok(self.0.try_borrow_or_err()?.fuel_config.clone())
+    }
+
     // generate a wasmi fuel cost schedule based on our calibration
     pub(crate) fn wasmi_fuel_costs(&self) -> Result<wasmi::FuelCosts, HostError> {
         let config = &self.0.try_borrow_or_err()?.fuel_config;
@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    host::error::TryBorrowOrErr,
+    xdr::{ContractCostParams, ContractCostType, ScErrorCode, ScErrorType},
+    HostError,
+};
+
+use super::{dimension::BudgetDimension, model::HostCostModel, Budget};
+
+/// Histogram of the charges made to the budget while
+/// [Budget::enable_charge_histogram] is on, keyed by the cost type and the
+/// input of the charge (`None` for the constant cost types), with the number
+/// of charged iterations as the value.
+///
+/// The histogram holds everything the budget needs to price the charges, so
+/// it can be re-evaluated under different cost parameters (see
+/// [ChargeHistogram::reprice]) without re-running the contracts.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ChargeHistogram(pub BTreeMap<(ContractCostType, Option<u64>), u64>);
+
+/// Budget usage of a [ChargeHistogram] priced under a set of cost parameters.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RepricedUsage {
+    pub cpu_insns: u64,
+    pub mem_bytes: u64,
+    /// The usage broken down by cost type, as `(cpu_insns, mem_bytes)`.
+    pub by_cost_type: BTreeMap<ContractCostType, (u64, u64)>,
+}
+
+impl ChargeHistogram {
+    pub(crate) fn record(&mut self, ty: ContractCostType, iterations: u64, input: Option<u64>) {
+        let count = self.0.entry((ty, input)).or_default();
+        *count = count.saturating_add(iterations);
+    }
+
+    /// Adds the charges of `other` to this histogram, e.g. in order to price
+    /// the charges of many transactions at once.
+    pub fn merge(&mut self, other: &ChargeHistogram) {
+        for (&(ty, input), &iterations) in other.0.iter() {
+            self.record(ty, iterations, input);
+        }
+    }
+
+    /// Evaluates the recorded charges under the given cost parameters, the
+    /// same way the budget would have charged them. The limits are not
+    /// applied, so the result may exceed any budget.
+    pub fn reprice(
+        &self,
+        cpu_cost_params: ContractCostParams,
+        mem_cost_params: ContractCostParams,
+    ) -> Result<RepricedUsage, HostError> {
+        let cpu_insns = BudgetDimension::try_from_config(cpu_cost_params)?;
+        let mem_bytes = BudgetDimension::try_from_config(mem_cost_params)?;
+        let mut usage = RepricedUsage::default();
+        for (&(ty, input), &iterations) in self.0.iter() {
+            let (Some(cpu_model), Some(mem_model)) =
+                (cpu_insns.get_cost_model(ty), mem_bytes.get_cost_model(ty))
+            else {
+                return Err((ScErrorType::Budget, ScErrorCode::InternalError).into());
+            };
+            let cpu = cpu_model.evaluate(input)?.saturating_mul(iterations);
+            let mem = mem_model.evaluate(input)?.saturating_mul(iterations);
+            usage.cpu_insns = usage.cpu_insns.saturating_add(cpu);
+            usage.mem_bytes = usage.mem_bytes.saturating_add(mem);
+            let by_cost_type = usage.by_cost_type.entry(ty).or_default();
+            by_cost_type.0 = by_cost_type.0.saturating_add(cpu);
+            by_cost_type.1 = by_cost_type.1.saturating_add(mem);
+        }
+        Ok(usage)
+    }
+}
+
+impl Budget {
+    /// Enables recording the [ChargeHistogram] of the charges made from now
+    /// on, or discards the recorded histogram when `enabled` is false. The
+    /// recording is not metered and doesn't affect the charges.
+    pub fn enable_charge_histogram(&self, enabled: bool) -> Result<(), HostError> {
+        self.0.try_borrow_mut_or_err()?.charge_histogram = enabled.then(ChargeHistogram::default);
+        Ok(())
+    }
+
+    /// Returns the recorded charge histogram, if the recording is enabled.
+    pub fn charge_histogram(&self) -> Result<Option<ChargeHistogram>, HostError> {
+        Ok(self.0.try_borrow_or_err()?.charge_histogram.clone())
+    }
+}
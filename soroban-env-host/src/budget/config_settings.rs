@@ -0,0 +1,108 @@
+use std::rc::Rc;
+
+use crate::{
+    host::error::TryBorrowOrErr,
+    storage::SnapshotSource,
+    xdr::{
+        ConfigSettingEntry, ConfigSettingId, ContractCostParamEntry, ContractCostParams,
+        ContractCostType, LedgerEntryData, LedgerKey, LedgerKeyConfigSetting, ScErrorCode,
+        ScErrorType,
+    },
+    HostError,
+};
+
+use super::{dimension::BudgetDimension, Budget};
+
+/// The network configuration settings the budget is built from.
+const BUDGET_CONFIG_SETTING_IDS: [ConfigSettingId; 3] = [
+    ConfigSettingId::ContractComputeV0,
+    ConfigSettingId::ContractCostParamsCpuInstructions,
+    ConfigSettingId::ContractCostParamsMemoryBytes,
+];
+
+impl Budget {
+    /// Initializes the budget from the network configuration setting entries.
+    /// The per-transaction CPU and memory limits come from the
+    /// `ContractComputeV0` setting, and the cost parameters from the
+    /// `ContractCostParamsCpuInstructions` and `ContractCostParamsMemoryBytes`
+    /// settings. All three settings must be present; other settings are
+    /// ignored.
+    pub fn try_from_config_settings<'a>(
+        settings: impl IntoIterator<Item = &'a ConfigSettingEntry>,
+    ) -> Result<Self, HostError> {
+        let mut limits = None;
+        let mut cpu_cost_params = None;
+        let mut mem_cost_params = None;
+        for setting in settings {
+            match setting {
+                ConfigSettingEntry::ContractComputeV0(compute) => {
+                    let cpu_limit = u64::try_from(compute.tx_max_instructions).map_err(|_| {
+                        HostError::from((ScErrorType::Budget, ScErrorCode::InvalidInput))
+                    })?;
+                    limits = Some((cpu_limit, compute.tx_memory_limit as u64));
+                }
+                ConfigSettingEntry::ContractCostParamsCpuInstructions(params) => {
+                    cpu_cost_params = Some(params.clone())
+                }
+                ConfigSettingEntry::ContractCostParamsMemoryBytes(params) => {
+                    mem_cost_params = Some(params.clone())
+                }
+                _ => (),
+            }
+        }
+        let (Some((cpu_limit, mem_limit)), Some(cpu_cost_params), Some(mem_cost_params)) =
+            (limits, cpu_cost_params, mem_cost_params)
+        else {
+            return Err((ScErrorType::Budget, ScErrorCode::MissingValue).into());
+        };
+        Self::try_from_configs(cpu_limit, mem_limit, cpu_cost_params, mem_cost_params)
+    }
+
+    /// Initializes the budget from the network configuration setting entries
+    /// loaded from `source`, see [Budget::try_from_config_settings].
+    pub fn try_from_snapshot_source<T: SnapshotSource + ?Sized>(
+        source: &T,
+    ) -> Result<Self, HostError> {
+        let mut settings = vec![];
+        for config_setting_id in BUDGET_CONFIG_SETTING_IDS {
+            let key = Rc::new(LedgerKey::ConfigSetting(LedgerKeyConfigSetting {
+                config_setting_id,
+            }));
+            if !source.has(&key)? {
+                return Err((ScErrorType::Budget, ScErrorCode::MissingValue).into());
+            }
+            match &source.get(&key)?.0.data {
+                LedgerEntryData::ConfigSetting(setting) => settings.push(setting.clone()),
+                _ => return Err((ScErrorType::Budget, ScErrorCode::UnexpectedType).into()),
+            }
+        }
+        Self::try_from_config_settings(settings.iter())
+    }
+
+    /// Returns the current CPU cost model parameters, e.g. in order to propose
+    /// calibrated parameters as a network configuration upgrade.
+    pub fn get_cpu_cost_params(&self) -> Result<ContractCostParams, HostError> {
+        cost_params(&self.0.try_borrow_or_err()?.cpu_insns)
+    }
+
+    /// Returns the current memory cost model parameters, see
+    /// [Budget::get_cpu_cost_params].
+    pub fn get_mem_cost_params(&self) -> Result<ContractCostParams, HostError> {
+        cost_params(&self.0.try_borrow_or_err()?.mem_bytes)
+    }
+}
+
+fn cost_params(dimension: &BudgetDimension) -> Result<ContractCostParams, HostError> {
+    let entries = ContractCostType::variants()
+        .iter()
+        .map(|ty| {
+            let Some(cost_model) = dimension.get_cost_model(*ty) else {
+                return Err((ScErrorType::Budget, ScErrorCode::InternalError).into());
+            };
+            ContractCostParamEntry::try_from(cost_model)
+        })
+        .collect::<Result<Vec<_>, HostError>>()?;
+    Ok(ContractCostParams(entries.try_into().map_err(|_| {
+        HostError::from((ScErrorType::Budget, ScErrorCode::InternalError))
+    })?))
+}
@@ -0,0 +1,325 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    host::error::TryBorrowOrErr,
+    xdr::{ContractCostType, Hash},
+    HostError,
+};
+
+use super::Budget;
+
+/// Budget charged under a single [ContractCostType] at a [CostTreeNode].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CostTreeCharge {
+    pub iterations: u64,
+    pub cpu: u64,
+    pub mem: u64,
+}
+
+impl CostTreeCharge {
+    fn add(&mut self, other: &CostTreeCharge) {
+        self.iterations = self.iterations.saturating_add(other.iterations);
+        self.cpu = self.cpu.saturating_add(other.cpu);
+        self.mem = self.mem.saturating_add(other.mem);
+    }
+}
+
+/// The context frame a [CostTreeNode] has been recorded for.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CostFrame {
+    /// Id of the contract, `None` for the host functions, i.e. both the
+    /// top-level host functions (such as Wasm upload) and the host functions
+    /// called by the contracts.
+    pub contract_id: Option<Hash>,
+    pub function: String,
+}
+
+impl fmt::Display for CostFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.function)?;
+        if let Some(id) = &self.contract_id {
+            write!(f, " in contract {}", stellar_strkey::Contract(id.0))?;
+        }
+        Ok(())
+    }
+}
+
+/// A node of the cost call tree recorded when [Budget::enable_cost_tree] is
+/// on. Every node corresponds to a single context frame, and its children to
+/// the frames pushed while it has been on top of the context stack, in order.
+/// The host function calls made by a contract frame are represented by one
+/// child node per called host function, which is the parent of the frames
+/// pushed by these calls (e.g. by `call`).
+///
+/// Note that a contract is instantiated before its frame is pushed, so the
+/// instantiation is charged to the node of the caller.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CostTreeNode {
+    /// The frame of the node, `None` for the root that holds the charges made
+    /// outside of any frame.
+    pub frame: Option<CostFrame>,
+    /// The charges made while the node has been on top of the context stack,
+    /// excluding the charges of its children.
+    pub exclusive: BTreeMap<ContractCostType, CostTreeCharge>,
+    pub children: Vec<CostTreeNode>,
+}
+
+impl CostTreeNode {
+    /// Returns the charges of the node including the charges of all its
+    /// descendants.
+    pub fn inclusive(&self) -> BTreeMap<ContractCostType, CostTreeCharge> {
+        let mut res = self.exclusive.clone();
+        for child in self.children.iter() {
+            for (ty, charge) in child.inclusive() {
+                res.entry(ty).or_default().add(&charge);
+            }
+        }
+        res
+    }
+
+    pub fn exclusive_cpu_insns(&self) -> u64 {
+        total(&self.exclusive, |c| c.cpu)
+    }
+
+    pub fn exclusive_mem_bytes(&self) -> u64 {
+        total(&self.exclusive, |c| c.mem)
+    }
+
+    pub fn inclusive_cpu_insns(&self) -> u64 {
+        self.children
+            .iter()
+            .fold(self.exclusive_cpu_insns(), |acc, c| {
+                acc.saturating_add(c.inclusive_cpu_insns())
+            })
+    }
+
+    pub fn inclusive_mem_bytes(&self) -> u64 {
+        self.children
+            .iter()
+            .fold(self.exclusive_mem_bytes(), |acc, c| {
+                acc.saturating_add(c.inclusive_mem_bytes())
+            })
+    }
+
+    /// Returns the folded stacks of the charges of the tree with the given
+    /// weight. Every line is a `;`-separated stack of the frames down to the
+    /// node, ending with the cost type charged at the node, followed by the
+    /// charged amount, e.g. `<contract>;<function>;<host function>;<cost type>
+    /// <amount>`. The contract frames span two stack entries: the contract
+    /// strkey and the function name. Zero charges are omitted.
+    pub fn folded_stacks(&self, weight: FoldedStackWeight) -> FoldedStacks<'_> {
+        FoldedStacks { root: self, weight }
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = 2 * depth;
+        match &self.frame {
+            Some(frame) => write!(f, "{:indent$}{}", "", frame)?,
+            None => write!(f, "<root>")?,
+        }
+        writeln!(
+            f,
+            ": cpu {} ({} exclusive), mem {} ({} exclusive)",
+            self.inclusive_cpu_insns(),
+            self.exclusive_cpu_insns(),
+            self.inclusive_mem_bytes(),
+            self.exclusive_mem_bytes()
+        )?;
+        for child in self.children.iter() {
+            child.fmt_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CostTreeNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+/// The weight stream of the folded stacks, see [CostTreeNode::folded_stacks].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FoldedStackWeight {
+    CpuInsns,
+    MemBytes,
+}
+
+/// The charges of a cost call tree in the folded stack format consumed by the
+/// flamegraph tools, see [CostTreeNode::folded_stacks].
+pub struct FoldedStacks<'a> {
+    root: &'a CostTreeNode,
+    weight: FoldedStackWeight,
+}
+
+impl FoldedStacks<'_> {
+    fn write_node(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        node: &CostTreeNode,
+        stack: &mut Vec<String>,
+    ) -> fmt::Result {
+        let depth = stack.len();
+        if let Some(frame) = &node.frame {
+            if let Some(id) = &frame.contract_id {
+                stack.push(stellar_strkey::Contract(id.0).to_string());
+            }
+            stack.push(frame.function.clone());
+        }
+        for (ty, charge) in node.exclusive.iter() {
+            let weight = match self.weight {
+                FoldedStackWeight::CpuInsns => charge.cpu,
+                FoldedStackWeight::MemBytes => charge.mem,
+            };
+            if weight == 0 {
+                continue;
+            }
+            for segment in stack.iter() {
+                write!(f, "{};", segment)?;
+            }
+            writeln!(f, "{:?} {}", ty, weight)?;
+        }
+        for child in node.children.iter() {
+            self.write_node(f, child, stack)?;
+        }
+        stack.truncate(depth);
+        Ok(())
+    }
+}
+
+impl fmt::Display for FoldedStacks<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_node(f, self.root, &mut vec![])
+    }
+}
+
+fn total(
+    charges: &BTreeMap<ContractCostType, CostTreeCharge>,
+    dim: impl Fn(&CostTreeCharge) -> u64,
+) -> u64 {
+    charges
+        .values()
+        .fold(0, |acc, c| acc.saturating_add(dim(c)))
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct CostTreeRecorder {
+    root: CostTreeNode,
+    // Child indices leading from the root to the current node, and whether
+    // the node is a host function call node.
+    path: Vec<(usize, bool)>,
+}
+
+impl CostTreeRecorder {
+    fn current(&mut self) -> &mut CostTreeNode {
+        let mut node = &mut self.root;
+        for &(i, _) in self.path.iter() {
+            node = &mut node.children[i];
+        }
+        node
+    }
+
+    pub(crate) fn root(&self) -> &CostTreeNode {
+        &self.root
+    }
+
+    pub(crate) fn record(&mut self, ty: ContractCostType, iterations: u64, cpu: u64, mem: u64) {
+        self.current()
+            .exclusive
+            .entry(ty)
+            .or_default()
+            .add(&CostTreeCharge {
+                iterations,
+                cpu,
+                mem,
+            });
+    }
+}
+
+impl Budget {
+    /// Enables recording the [CostTreeNode] call tree of the charges made
+    /// from now on, or discards the recorded tree when `enabled` is false.
+    /// The recording is not metered and doesn't affect the charges.
+    pub fn enable_cost_tree(&self, enabled: bool) -> Result<(), HostError> {
+        self.0.try_borrow_mut_or_err()?.cost_tree = enabled.then(CostTreeRecorder::default);
+        Ok(())
+    }
+
+    pub fn is_cost_tree_enabled(&self) -> Result<bool, HostError> {
+        Ok(self.0.try_borrow_or_err()?.cost_tree.is_some())
+    }
+
+    /// Returns the root of the recorded cost call tree, if the recording is
+    /// enabled.
+    pub fn cost_tree(&self) -> Result<Option<CostTreeNode>, HostError> {
+        Ok(self
+            .0
+            .try_borrow_or_err()?
+            .cost_tree
+            .as_ref()
+            .map(|recorder| recorder.root.clone()))
+    }
+
+    // Starts a new child of the current node, which becomes the current one.
+    pub(crate) fn enter_cost_frame(&self, frame: CostFrame) -> Result<(), HostError> {
+        if let Some(recorder) = self.0.try_borrow_mut_or_err()?.cost_tree.as_mut() {
+            let current = recorder.current();
+            current.children.push(CostTreeNode {
+                frame: Some(frame),
+                ..Default::default()
+            });
+            let index = current.children.len() - 1;
+            recorder.path.push((index, false));
+        }
+        Ok(())
+    }
+
+    // Makes the parent of the current node the current one. The frames that
+    // have been entered before the recording was enabled have no nodes, so
+    // their charges stay with the root. Any host function call nodes left
+    // over by the calls that have failed before exiting are exited as well.
+    pub(crate) fn exit_cost_frame(&self) -> Result<(), HostError> {
+        if let Some(recorder) = self.0.try_borrow_mut_or_err()?.cost_tree.as_mut() {
+            while let Some((_, true)) = recorder.path.last() {
+                recorder.path.pop();
+            }
+            recorder.path.pop();
+        }
+        Ok(())
+    }
+
+    // Makes the node of the calls to the host function `name` made from the
+    // current node the current one. All the calls to the same host function
+    // share a single node.
+    pub(crate) fn enter_host_function_cost_frame(&self, name: &str) -> Result<(), HostError> {
+        if let Some(recorder) = self.0.try_borrow_mut_or_err()?.cost_tree.as_mut() {
+            let current = recorder.current();
+            let index = match current.children.iter().position(
+                |c| matches!(&c.frame, Some(f) if f.contract_id.is_none() && f.function == name),
+            ) {
+                Some(index) => index,
+                None => {
+                    current.children.push(CostTreeNode {
+                        frame: Some(CostFrame {
+                            contract_id: None,
+                            function: name.to_string(),
+                        }),
+                        ..Default::default()
+                    });
+                    current.children.len() - 1
+                }
+            };
+            recorder.path.push((index, true));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn exit_host_function_cost_frame(&self) -> Result<(), HostError> {
+        if let Some(recorder) = self.0.try_borrow_mut_or_err()?.cost_tree.as_mut() {
+            if let Some((_, true)) = recorder.path.last() {
+                recorder.path.pop();
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    xdr::{ContractCostParamEntry, ScErrorCode, ScErrorType},
+    xdr::{ContractCostParamEntry, ExtensionPoint, ScErrorCode, ScErrorType},
     HostError,
 };
 use core::fmt::{Debug, Display};
@@ -106,6 +106,24 @@ impl TryFrom<ContractCostParamEntry> for MeteredCostComponent {
     }
 }
 
+impl TryFrom<&MeteredCostComponent> for ContractCostParamEntry {
+    type Error = HostError;
+
+    fn try_from(cost: &MeteredCostComponent) -> Result<Self, Self::Error> {
+        let (Ok(const_term), Ok(linear_term)) = (
+            i64::try_from(cost.const_term),
+            i64::try_from(cost.lin_term.0),
+        ) else {
+            return Err((ScErrorType::Context, ScErrorCode::InvalidInput).into());
+        };
+        Ok(ContractCostParamEntry {
+            ext: ExtensionPoint::V0,
+            const_term,
+            linear_term,
+        })
+    }
+}
+
 impl HostCostModel for MeteredCostComponent {
     fn evaluate(&self, input: Option<u64>) -> Result<u64, HostError> {
         let const_term = self.const_term;
@@ -0,0 +1,308 @@
+//! This module contains the [ContractSpec] type, which provides typed access
+//! to the contract spec stored in the `contractspecv0` custom section of the
+//! contract Wasm, and allows checking the contract call arguments against the
+//! declared function parameter types.
+//!
+//! The spec is not a part of the protocol: it is only used to provide better
+//! error reporting in tests and during the simulation (see
+//! [Host::enable_contract_spec_validation](crate::Host::enable_contract_spec_validation)).
+
+use std::{collections::BTreeMap, fmt, io::Cursor};
+
+use crate::{
+    xdr::{
+        Limited, ReadXdr, ScError, ScSpecEntry, ScSpecFunctionV0, ScSpecTypeDef,
+        ScSpecUdtUnionCaseV0, ScVal,
+    },
+    HostError, Vm, DEFAULT_XDR_RW_LIMITS,
+};
+
+/// Name of the Wasm custom section that contains the contract spec.
+pub const CONTRACT_SPEC_SECTION_NAME: &str = "contractspecv0";
+
+/// Parsed contract spec: the function and user-defined type (UDT) definitions
+/// keyed by their names.
+#[derive(Clone, Debug, Default)]
+pub struct ContractSpec {
+    functions: BTreeMap<String, ScSpecFunctionV0>,
+    udts: BTreeMap<String, ScSpecEntry>,
+}
+
+/// A mismatch between the contract call arguments and the [ContractSpec].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ContractSpecMismatch {
+    ArgCount {
+        function: String,
+        expected: usize,
+        got: usize,
+    },
+    /// Argument at zero-based `index` has an unexpected type.
+    ArgType {
+        function: String,
+        index: usize,
+        expected: String,
+        got: String,
+    },
+}
+
+impl fmt::Display for ContractSpecMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContractSpecMismatch::ArgCount {
+                function,
+                expected,
+                got,
+            } => write!(f, "`{function}` expected {expected} args, got {got}"),
+            ContractSpecMismatch::ArgType {
+                function,
+                index,
+                expected,
+                got,
+            } => write!(
+                f,
+                "arg {index} of `{function}` expected {expected}, got {got}"
+            ),
+        }
+    }
+}
+
+impl ContractSpec {
+    /// Parses the contents of the `contractspecv0` section, which is a
+    /// sequence of `ScSpecEntry` XDR values.
+    pub fn from_xdr(section: &[u8]) -> Result<Self, HostError> {
+        let mut limits = DEFAULT_XDR_RW_LIMITS;
+        limits.len = section.len();
+        let mut cursor = Limited::new(Cursor::new(section), limits);
+        let mut spec = Self::default();
+        for entry in ScSpecEntry::read_xdr_iter(&mut cursor) {
+            let entry = entry?;
+            match &entry {
+                ScSpecEntry::FunctionV0(f) => {
+                    spec.functions
+                        .insert(f.name.0.to_utf8_string_lossy(), f.clone());
+                }
+                ScSpecEntry::UdtStructV0(s) => {
+                    spec.udts.insert(s.name.to_utf8_string_lossy(), entry);
+                }
+                ScSpecEntry::UdtUnionV0(u) => {
+                    spec.udts.insert(u.name.to_utf8_string_lossy(), entry);
+                }
+                ScSpecEntry::UdtEnumV0(e) => {
+                    spec.udts.insert(e.name.to_utf8_string_lossy(), entry);
+                }
+                ScSpecEntry::UdtErrorEnumV0(e) => {
+                    spec.udts.insert(e.name.to_utf8_string_lossy(), entry);
+                }
+            }
+        }
+        Ok(spec)
+    }
+
+    /// Parses the spec of the contract loaded into `vm`, or returns `None`
+    /// if the contract has no spec section.
+    pub fn from_vm(vm: &Vm) -> Result<Option<Self>, HostError> {
+        vm.custom_section(CONTRACT_SPEC_SECTION_NAME)
+            .map(Self::from_xdr)
+            .transpose()
+    }
+
+    /// Returns the definition of the function with the given name.
+    pub fn function(&self, name: &str) -> Option<&ScSpecFunctionV0> {
+        self.functions.get(name)
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &ScSpecFunctionV0> {
+        self.functions.values()
+    }
+
+    /// Returns the definition of the user-defined type with the given name.
+    pub fn udt(&self, name: &str) -> Option<&ScSpecEntry> {
+        self.udts.get(name)
+    }
+
+    /// Checks the call arguments of `function` against its declared parameter
+    /// types. Functions missing from the spec are not checked.
+    pub fn check_args(&self, function: &str, args: &[ScVal]) -> Result<(), ContractSpecMismatch> {
+        let Some(f) = self.function(function) else {
+            return Ok(());
+        };
+        if f.inputs.len() != args.len() {
+            return Err(ContractSpecMismatch::ArgCount {
+                function: function.to_string(),
+                expected: f.inputs.len(),
+                got: args.len(),
+            });
+        }
+        for (index, (input, arg)) in f.inputs.iter().zip(args).enumerate() {
+            if !self.matches(&input.type_, arg) {
+                return Err(ContractSpecMismatch::ArgType {
+                    function: function.to_string(),
+                    index,
+                    expected: type_name(&input.type_),
+                    got: val_type_name(arg).to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn matches_all<'a>(
+        &self,
+        types: impl ExactSizeIterator<Item = &'a ScSpecTypeDef>,
+        vals: &[ScVal],
+    ) -> bool {
+        types.len() == vals.len() && types.zip(vals).all(|(ty, v)| self.matches(ty, v))
+    }
+
+    fn matches(&self, ty: &ScSpecTypeDef, val: &ScVal) -> bool {
+        use ScSpecTypeDef as T;
+        match (ty, val) {
+            (T::Val, _)
+            | (T::Bool, ScVal::Bool(_))
+            | (T::Void, ScVal::Void)
+            | (T::Error, ScVal::Error(_))
+            | (T::U32, ScVal::U32(_))
+            | (T::I32, ScVal::I32(_))
+            | (T::U64, ScVal::U64(_))
+            | (T::I64, ScVal::I64(_))
+            | (T::Timepoint, ScVal::Timepoint(_))
+            | (T::Duration, ScVal::Duration(_))
+            | (T::U128, ScVal::U128(_))
+            | (T::I128, ScVal::I128(_))
+            | (T::U256, ScVal::U256(_))
+            | (T::I256, ScVal::I256(_))
+            | (T::Bytes, ScVal::Bytes(_))
+            | (T::String, ScVal::String(_))
+            | (T::Symbol, ScVal::Symbol(_))
+            | (T::Address, ScVal::Address(_)) => true,
+            (T::Option(_), ScVal::Void) => true,
+            (T::Option(o), v) => self.matches(&o.value_type, v),
+            (T::Result(r), v) => self.matches(&r.ok_type, v) || self.matches(&r.error_type, v),
+            (T::Vec(t), ScVal::Vec(Some(v))) => v.iter().all(|e| self.matches(&t.element_type, e)),
+            (T::Map(t), ScVal::Map(Some(m))) => m
+                .iter()
+                .all(|e| self.matches(&t.key_type, &e.key) && self.matches(&t.value_type, &e.val)),
+            (T::Tuple(t), ScVal::Vec(Some(v))) => self.matches_all(t.value_types.iter(), v),
+            (T::BytesN(n), ScVal::Bytes(b)) => b.len() == n.n as usize,
+            (T::Udt(udt), v) => self.matches_udt(&udt.name.to_utf8_string_lossy(), v),
+            _ => false,
+        }
+    }
+
+    // UDTs are matched according to their conversion to `ScVal` in the SDK.
+    // UDTs that are not defined in the spec are not checked.
+    fn matches_udt(&self, name: &str, val: &ScVal) -> bool {
+        match (self.udt(name), val) {
+            (None, _) => true,
+            (Some(ScSpecEntry::UdtStructV0(s)), ScVal::Vec(Some(v)))
+                if s.fields
+                    .iter()
+                    .all(|f| f.name.first().map_or(false, u8::is_ascii_digit)) =>
+            {
+                self.matches_all(s.fields.iter().map(|f| &f.type_), v)
+            }
+            (Some(ScSpecEntry::UdtStructV0(s)), ScVal::Map(Some(m))) => {
+                s.fields.len() == m.len()
+                    && s.fields.iter().zip(m.iter()).all(|(f, e)| {
+                        matches!(&e.key, ScVal::Symbol(k) if k.as_slice() == f.name.as_slice())
+                            && self.matches(&f.type_, &e.val)
+                    })
+            }
+            (Some(ScSpecEntry::UdtUnionV0(u)), ScVal::Vec(Some(v))) => {
+                let Some((ScVal::Symbol(case_name), case_vals)) = v.split_first() else {
+                    return false;
+                };
+                u.cases.iter().any(|case| match case {
+                    ScSpecUdtUnionCaseV0::VoidV0(c) => {
+                        c.name.as_slice() == case_name.as_slice() && case_vals.is_empty()
+                    }
+                    ScSpecUdtUnionCaseV0::TupleV0(c) => {
+                        c.name.as_slice() == case_name.as_slice()
+                            && self.matches_all(c.type_.iter(), case_vals)
+                    }
+                })
+            }
+            (Some(ScSpecEntry::UdtEnumV0(e)), ScVal::U32(v)) => {
+                e.cases.iter().any(|case| case.value == *v)
+            }
+            (Some(ScSpecEntry::UdtErrorEnumV0(e)), ScVal::Error(ScError::Contract(v))) => {
+                e.cases.iter().any(|case| case.value == *v)
+            }
+            _ => false,
+        }
+    }
+}
+
+// Returns the Rust-like name of the spec type.
+fn type_name(ty: &ScSpecTypeDef) -> String {
+    use ScSpecTypeDef as T;
+    match ty {
+        T::Val => "val".to_string(),
+        T::Bool => "bool".to_string(),
+        T::Void => "void".to_string(),
+        T::Error => "error".to_string(),
+        T::U32 => "u32".to_string(),
+        T::I32 => "i32".to_string(),
+        T::U64 => "u64".to_string(),
+        T::I64 => "i64".to_string(),
+        T::Timepoint => "timepoint".to_string(),
+        T::Duration => "duration".to_string(),
+        T::U128 => "u128".to_string(),
+        T::I128 => "i128".to_string(),
+        T::U256 => "u256".to_string(),
+        T::I256 => "i256".to_string(),
+        T::Bytes => "bytes".to_string(),
+        T::String => "string".to_string(),
+        T::Symbol => "symbol".to_string(),
+        T::Address => "address".to_string(),
+        T::Option(o) => format!("option<{}>", type_name(&o.value_type)),
+        T::Result(r) => format!(
+            "result<{}, {}>",
+            type_name(&r.ok_type),
+            type_name(&r.error_type)
+        ),
+        T::Vec(v) => format!("vec<{}>", type_name(&v.element_type)),
+        T::Map(m) => format!(
+            "map<{}, {}>",
+            type_name(&m.key_type),
+            type_name(&m.value_type)
+        ),
+        T::Tuple(t) => format!(
+            "({})",
+            t.value_types
+                .iter()
+                .map(type_name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        T::BytesN(b) => format!("bytesn<{}>", b.n),
+        T::Udt(u) => u.name.to_utf8_string_lossy(),
+    }
+}
+
+fn val_type_name(val: &ScVal) -> &'static str {
+    match val {
+        ScVal::Bool(_) => "bool",
+        ScVal::Void => "void",
+        ScVal::Error(_) => "error",
+        ScVal::U32(_) => "u32",
+        ScVal::I32(_) => "i32",
+        ScVal::U64(_) => "u64",
+        ScVal::I64(_) => "i64",
+        ScVal::Timepoint(_) => "timepoint",
+        ScVal::Duration(_) => "duration",
+        ScVal::U128(_) => "u128",
+        ScVal::I128(_) => "i128",
+        ScVal::U256(_) => "u256",
+        ScVal::I256(_) => "i256",
+        ScVal::Bytes(_) => "bytes",
+        ScVal::String(_) => "string",
+        ScVal::Symbol(_) => "symbol",
+        ScVal::Vec(_) => "vec",
+        ScVal::Map(_) => "map",
+        ScVal::Address(_) => "address",
+        ScVal::LedgerKeyContractInstance => "ledger key contract instance",
+        ScVal::LedgerKeyNonce(_) => "ledger key nonce",
+        ScVal::ContractInstance(_) => "contract instance",
+    }
+}
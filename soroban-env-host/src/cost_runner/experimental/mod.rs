@@ -1,8 +1,10 @@
 mod ed25519_scalar_mut;
 mod read_xdr;
+mod storage_map_ops;
 
 pub use ed25519_scalar_mut::*;
 pub use read_xdr::*;
+pub use storage_map_ops::*;
 
 use crate::xdr::Name;
 use core::fmt;
@@ -11,6 +13,7 @@ use core::fmt;
 pub enum ExperimentalCostType {
     EdwardsPointCurve25519ScalarMul,
     ReadXdrByteArray,
+    PersistentStorageMapInsert,
 }
 
 impl Name for ExperimentalCostType {
@@ -20,6 +23,7 @@ impl Name for ExperimentalCostType {
                 "EdwardsPointCurve25519ScalarMul"
             }
             ExperimentalCostType::ReadXdrByteArray => "ReadXdrByteArray",
+            ExperimentalCostType::PersistentStorageMapInsert => "PersistentStorageMapInsert",
         }
     }
 }
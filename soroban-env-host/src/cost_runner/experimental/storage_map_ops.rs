@@ -0,0 +1,53 @@
+use std::{hint::black_box, rc::Rc};
+
+use crate::{
+    budget::{AsBudget, CostTracker},
+    cost_runner::{
+        experimental::ExperimentalCostType::PersistentStorageMapInsert, CostRunner, CostType,
+    },
+    storage::StorageMap,
+    xdr::{ContractCostType::MemCpy, LedgerKey},
+};
+
+pub struct PersistentStorageMapInsertRun;
+
+#[derive(Clone)]
+pub struct PersistentStorageMapInsertSample {
+    pub map: StorageMap,
+    pub keys: Vec<Rc<LedgerKey>>,
+}
+
+impl CostRunner for PersistentStorageMapInsertRun {
+    // experimental cost type that identifies this component. Used purely for result aggregation
+    // purpose. Internally there is no budget component associated with this type, and you'll
+    // have to override the get_tracker method to return the correct inputs.
+    const COST_TYPE: CostType = CostType::Experimental(PersistentStorageMapInsert);
+
+    const RUN_ITERATIONS: u64 = 1000;
+
+    type SampleType = PersistentStorageMapInsertSample;
+
+    type RecycledType = (Option<StorageMap>, Self::SampleType);
+
+    fn run_iter(host: &crate::Host, iter: u64, sample: Self::SampleType) -> Self::RecycledType {
+        let key = Rc::clone(&sample.keys[iter as usize % sample.keys.len()]);
+        let map = black_box(sample.map.insert(key, None, host.as_budget()).unwrap());
+        (Some(map), sample)
+    }
+
+    fn run_baseline_iter(
+        host: &crate::Host,
+        _iter: u64,
+        sample: Self::SampleType,
+    ) -> Self::RecycledType {
+        black_box(host.charge_budget(MemCpy, Some(0)).unwrap());
+        black_box((None, sample))
+    }
+
+    fn get_tracker(host: &crate::Host) -> CostTracker {
+        // internally the tree search and the path copy are charged under
+        // `MemCpy` (along with `MemAlloc` and the key comparisons), so the
+        // fitted model is the cpu cost per byte of the `MemCpy` input.
+        host.as_budget().get_tracker(MemCpy).unwrap()
+    }
+}
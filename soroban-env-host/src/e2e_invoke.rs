@@ -2,37 +2,61 @@
 /// environments using a clean host instance.
 /// Also contains helpers for processing the ledger changes caused by these
 /// host functions.
-use std::{cmp::max, rc::Rc};
+use std::{collections::BTreeMap, rc::Rc};
+
+use sha2::{Digest, Sha256};
 
 use soroban_env_common::{
     xdr::{
-        AccountId, ContractDataDurability, ContractEventType, DiagnosticEvent, HostFunction,
-        LedgerEntry, LedgerEntryData, LedgerFootprint, LedgerKey, LedgerKeyAccount,
-        LedgerKeyContractCode, LedgerKeyContractData, LedgerKeyTrustLine, ScErrorCode, ScErrorType,
-        SorobanAuthorizationEntry, SorobanResources, TtlEntry,
+        AccountId, ContractDataDurability, ContractEventType, DiagnosticEvent,
+        ExtendFootprintTtlOp, Hash, HostFunction, LedgerEntry, LedgerEntryData, LedgerFootprint,
+        LedgerKey, LedgerKeyAccount, LedgerKeyConfigSetting, LedgerKeyContractCode,
+        LedgerKeyContractData, LedgerKeyTrustLine, ReadXdr, RestoreFootprintOp, ScAddress, ScBytes,
+        ScErrorCode, ScErrorType, ScVal, ScVec, SorobanAuthorizationEntry, SorobanResources,
+        TtlEntry, WriteXdr,
     },
     Error,
 };
 
+#[cfg(any(test, feature = "recording_auth"))]
+use crate::{
+    auth::RecordedAuthPayload,
+    xdr::{SorobanAddressCredentials, SorobanCredentials},
+};
 use crate::{
     budget::{AsBudget, Budget},
     events::Events,
-    fees::LedgerEntryRentChange,
+    fees::{LedgerEntryRentChange, TransactionResources},
     host::{
         crypto::sha256_hash_from_bytes,
         ledger_info_helper::get_key_durability,
         metered_clone::{MeteredAlloc, MeteredClone, MeteredContainer, MeteredIterator},
         metered_xdr::{metered_from_xdr_with_budget, metered_write_xdr},
     },
-    storage::{AccessType, Footprint, FootprintMap, SnapshotSource, Storage, StorageMap},
-    DiagnosticLevel, Host, HostError, LedgerInfo, MeteredOrdMap,
+    storage::{
+        for_each_entry_change, AccessType, EntryWithLiveUntil, Footprint, FootprintMap,
+        FootprintUsageReport, SnapshotSource, Storage, StorageMap,
+    },
+    DiagnosticLevel, Host, HostError, LedgerInfo, MeteredOrdMap, DEFAULT_XDR_RW_LIMITS,
 };
 
 pub type TtlEntryMap = MeteredOrdMap<Rc<LedgerKey>, Rc<TtlEntry>, Budget>;
 
 /// Result of invoking a single host function prepared for embedder consumption.
+///
+/// When serialized, the invocation error is represented by its `ScError`, so
+/// a deserialized result carries the error type and code, but none of the
+/// debug information of the original error.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InvokeHostFunctionResult {
     /// Result value of the function, encoded `ScVal` XDR on success, or error.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            serialize_with = "serialize_encoded_invoke_result",
+            deserialize_with = "deserialize_encoded_invoke_result"
+        )
+    )]
     pub encoded_invoke_result: Result<Vec<u8>, HostError>,
     /// All the ledger changes caused by this invocation, including no-ops.
     /// This contains an entry for *every* item in the input footprint, even if
@@ -47,24 +71,151 @@ pub struct InvokeHostFunctionResult {
     /// `ContractEvent` XDR.
     ///
     /// Empty when invocation fails.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::vec_bytes"))]
     pub encoded_contract_events: Vec<Vec<u8>>,
+    /// Resources consumed by this invocation.
+    pub resources: InvocationResources,
+    /// Keys of the footprint entries that haven't been accessed during the
+    /// invocation, encoded as `LedgerKey` XDR. These can be removed from the
+    /// footprint.
+    ///
+    /// Only populated when diagnostics are enabled. Empty when invocation
+    /// fails.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::vec_bytes"))]
+    pub encoded_unused_keys: Vec<Vec<u8>>,
+    /// Keys of the read-write footprint entries that have only been read
+    /// during the invocation, encoded as `LedgerKey` XDR. These can be moved
+    /// to the read-only footprint.
+    ///
+    /// Only populated when diagnostics are enabled. Empty when invocation
+    /// fails.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::vec_bytes"))]
+    pub encoded_unwritten_read_write_keys: Vec<Vec<u8>>,
+}
+
+/// Result of simulating a single host function invocation in the recording
+/// mode, prepared for embedder consumption.
+///
+/// The recorded values are meant to be used to build a transaction that can
+/// then be applied via `invoke_host_function`.
+#[cfg(any(test, feature = "recording_auth"))]
+pub struct SimulateInvokeHostFunctionResult {
+    /// Result value of the function, encoded `ScVal` XDR on success, or error.
+    pub encoded_invoke_result: Result<Vec<u8>, HostError>,
+    /// Footprint of all the ledger entries accessed during the invocation.
+    pub footprint: LedgerFootprint,
+    /// Authorization entries recorded during the invocation.
+    ///
+    /// The entries with `Address` credentials have a void signature and
+    /// `signature_expiration_ledger` set to `0`; these have to be filled in
+    /// (i.e. signed) by the caller before the entries can be used.
+    pub auth_entries: Vec<SorobanAuthorizationEntry>,
+    /// All the ledger changes caused by this invocation, including no-ops.
+    /// This contains an entry for every item in the recorded footprint.
+    ///
+    /// Empty when invocation fails.
+    pub ledger_changes: Vec<LedgerEntryChange>,
+    /// All the events that contracts emitted during invocation, encoded as
+    /// `ContractEvent` XDR.
+    ///
+    /// Empty when invocation fails.
+    pub encoded_contract_events: Vec<Vec<u8>>,
+    /// Resources consumed by this invocation.
+    pub resources: InvocationResources,
+    /// Keys of the archived persistent entries in the recorded footprint,
+    /// encoded as `LedgerKey` XDR.
+    ///
+    /// The simulation reads the archived entries as if they were live, but
+    /// they have to be restored before the invocation can be applied, e.g.
+    /// via `RestoreFootprintOp` with these keys in the read-write footprint.
+    pub encoded_archived_keys: Vec<Vec<u8>>,
+}
+
+/// Resources consumed by a single host function invocation.
+///
+/// The fields map one-to-one onto the respective fields of
+/// `fees::TransactionResources` (see `to_transaction_resources`). The memory
+/// and the maximum key/entry sizes are not charged for directly, but are
+/// limited by the network configuration.
+///
+/// Only the CPU, memory and ledger entry counts are populated when the
+/// invocation fails, as the remaining values depend on the ledger changes and
+/// events.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InvocationResources {
+    /// Number of CPU instructions consumed.
+    pub instructions: u64,
+    /// Number of memory bytes consumed.
+    pub mem_bytes: u64,
+    /// Number of read-only ledger entries in the footprint.
+    pub read_entries: u32,
+    /// Number of read-write ledger entries in the footprint.
+    pub write_entries: u32,
+    /// Total size of the ledger entries read (including the entries that have
+    /// been modified), in bytes of `LedgerEntry` XDR.
+    pub read_bytes: u32,
+    /// Total size of the ledger entries written, in bytes of `LedgerEntry`
+    /// XDR.
+    pub write_bytes: u32,
+    /// Total size of the contract events, in bytes of `ContractEvent` XDR.
+    pub contract_events_size_bytes: u32,
+    /// Size of the largest ledger key accessed, in bytes of `LedgerKey` XDR.
+    pub max_key_size_bytes: u32,
+    /// Size of the largest ledger entry accessed (either before or after the
+    /// modification), in bytes of `LedgerEntry` XDR.
+    pub max_entry_size_bytes: u32,
+}
+
+impl InvocationResources {
+    /// Converts the consumed resources to `TransactionResources` suitable for
+    /// `fees::compute_transaction_resource_fee`.
+    ///
+    /// The transaction size is not known to the host and thus has to be
+    /// provided by the caller.
+    pub fn to_transaction_resources(&self, transaction_size_bytes: u32) -> TransactionResources {
+        TransactionResources {
+            instructions: u32::try_from(self.instructions).unwrap_or(u32::MAX),
+            read_entries: self.read_entries,
+            write_entries: self.write_entries,
+            read_bytes: self.read_bytes,
+            write_bytes: self.write_bytes,
+            contract_events_size_bytes: self.contract_events_size_bytes,
+            transaction_size_bytes,
+        }
+    }
+}
+
+/// Result of applying a TTL-related operation (`ExtendFootprintTtlOp` or
+/// `RestoreFootprintOp`) prepared for embedder consumption.
+pub struct TtlOperationResult {
+    /// All the ledger changes caused by the operation, including no-ops.
+    /// This contains an entry for *every* item in the input footprint.
+    pub ledger_changes: Vec<LedgerEntryChange>,
+    /// Rent-related changes of the entries that have been extended or
+    /// restored. These can be used to compute the rent fee via
+    /// `fees::compute_rent_fee`.
+    pub rent_changes: Vec<LedgerEntryRentChange>,
 }
 
 /// Represents a change of the ledger entry from 'old' value to the 'new' one.
 /// Only contains the final value of the entry (if any) and some minimal
 /// information about the old entry for convenience.
-#[derive(Default)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LedgerEntryChange {
     /// Whether the ledger entry is read-only, as defined by the footprint.
     pub read_only: bool,
 
     /// Entry key encoded as `LedgerKey` XDR.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::bytes"))]
     pub encoded_key: Vec<u8>,
     /// Size of the old entry in bytes. This is size of `LedgerEntry` encoded
     /// XDR.
     pub old_entry_size_bytes: u32,
     /// New value of the ledger entry encoded as `LedgerEntry` XDR.
     /// Only set for non-removed, non-readonly values, otherwise `None`.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::option_bytes"))]
     pub encoded_new_value: Option<Vec<u8>>,
     /// Change of the live until state of the entry.
     /// Only set for entries that have a TTL, otherwise `None`.
@@ -72,8 +223,11 @@ pub struct LedgerEntryChange {
 }
 
 /// Represents the live until-related state of the entry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LedgerEntryLiveUntilChange {
     /// Hash of the LedgerKey for the entry that this live until ledger change is tied to
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::bytes"))]
     pub key_hash: Vec<u8>,
     /// Durability of the entry.    
     pub durability: ContractDataDurability,
@@ -87,7 +241,7 @@ pub struct LedgerEntryLiveUntilChange {
 /// Returns the difference between the `storage` and its initial snapshot as
 /// `LedgerEntryChanges`.
 /// Returns an entry for every item in `storage` footprint.
-pub fn get_ledger_changes<T: SnapshotSource>(
+pub fn get_ledger_changes<T: SnapshotSource + ?Sized>(
     budget: &Budget,
     storage: &Storage,
     init_storage_snapshot: &T,
@@ -103,64 +257,110 @@ pub fn get_ledger_changes<T: SnapshotSource>(
     // happen in embedder environments, or simply fundamental invariant bugs.
     let internal_error: HostError =
         Error::from_type_and_code(ScErrorType::Storage, ScErrorCode::InternalError).into();
-    for (key, entry_with_live_until_ledger) in storage.map.iter(budget)? {
-        let mut entry_change = LedgerEntryChange::default();
-        metered_write_xdr(budget, key.as_ref(), &mut entry_change.encoded_key)?;
-        let durability = get_key_durability(key);
-
-        if let Some(durability) = durability {
-            let key_hash = match init_ttl_entries.get::<Rc<LedgerKey>>(key, budget)? {
-                Some(ee) => ee.key_hash.0.to_vec(),
-                None => sha256_hash_from_bytes(entry_change.encoded_key.as_slice(), budget)?,
-            };
+    for_each_entry_change(
+        budget,
+        storage,
+        init_storage_snapshot,
+        |key, old_entry, new_entry| {
+            let mut entry_change = LedgerEntryChange::default();
+            metered_write_xdr(budget, key.as_ref(), &mut entry_change.encoded_key)?;
+            let durability = get_key_durability(key);
 
-            entry_change.ttl_change = Some(LedgerEntryLiveUntilChange {
-                key_hash,
-                durability,
-                old_live_until_ledger: 0,
-                new_live_until_ledger: 0,
-            });
-        }
-        if init_storage_snapshot.has(key)? {
-            let (old_entry, old_live_until_ledger) = init_storage_snapshot.get(key)?;
-            let mut buf = vec![];
-            metered_write_xdr(budget, old_entry.as_ref(), &mut buf)?;
-            entry_change.old_entry_size_bytes = buf.len() as u32;
+            if let Some(durability) = durability {
+                let key_hash = match init_ttl_entries.get::<Rc<LedgerKey>>(key, budget)? {
+                    Some(ee) => ee.key_hash.0.to_vec(),
+                    None => sha256_hash_from_bytes(entry_change.encoded_key.as_slice(), budget)?,
+                };
 
-            if let Some(ref mut ttl_change) = &mut entry_change.ttl_change {
-                ttl_change.old_live_until_ledger =
-                    old_live_until_ledger.ok_or_else(|| internal_error.clone())?;
-            }
-        }
-        if let Some((_, new_live_until_ledger)) = entry_with_live_until_ledger {
-            if let Some(ref mut ttl_change) = &mut entry_change.ttl_change {
-                // Never reduce the final live until ledger.
-                ttl_change.new_live_until_ledger = max(
-                    new_live_until_ledger.ok_or_else(|| internal_error.clone())?,
-                    ttl_change.old_live_until_ledger,
-                );
+                entry_change.ttl_change = Some(LedgerEntryLiveUntilChange {
+                    key_hash,
+                    durability,
+                    old_live_until_ledger: 0,
+                    new_live_until_ledger: 0,
+                });
             }
-        }
-        let maybe_access_type: Option<AccessType> =
-            footprint_map.get::<Rc<LedgerKey>>(key, budget)?.copied();
-        match maybe_access_type {
-            Some(AccessType::ReadOnly) => {
-                entry_change.read_only = true;
+            if let Some((old_entry, old_live_until_ledger)) = old_entry {
+                let mut buf = vec![];
+                metered_write_xdr(budget, old_entry.as_ref(), &mut buf)?;
+                entry_change.old_entry_size_bytes = buf.len() as u32;
+
+                if let Some(ref mut ttl_change) = &mut entry_change.ttl_change {
+                    ttl_change.old_live_until_ledger =
+                        old_live_until_ledger.ok_or_else(|| internal_error.clone())?;
+                }
             }
-            Some(AccessType::ReadWrite) => {
-                if let Some((entry, _)) = entry_with_live_until_ledger {
-                    let mut entry_buf = vec![];
-                    metered_write_xdr(budget, entry.as_ref(), &mut entry_buf)?;
-                    entry_change.encoded_new_value = Some(entry_buf);
+            if let Some((_, new_live_until_ledger)) = new_entry {
+                if let Some(ref mut ttl_change) = &mut entry_change.ttl_change {
+                    ttl_change.new_live_until_ledger =
+                        new_live_until_ledger.ok_or_else(|| internal_error.clone())?;
                 }
             }
-            None => {
-                return Err(internal_error);
+            let maybe_access_type: Option<AccessType> =
+                footprint_map.get::<Rc<LedgerKey>>(key, budget)?.copied();
+            match maybe_access_type {
+                Some(AccessType::ReadOnly) => {
+                    entry_change.read_only = true;
+                }
+                Some(AccessType::ReadWrite) => {
+                    if let Some((entry, _)) = new_entry {
+                        let mut entry_buf = vec![];
+                        metered_write_xdr(budget, entry.as_ref(), &mut entry_buf)?;
+                        entry_change.encoded_new_value = Some(entry_buf);
+                    }
+                }
+                None => {
+                    return Err(internal_error.clone());
+                }
             }
+            changes.push(entry_change);
+            Ok(())
+        },
+    )?;
+    Ok(changes)
+}
+
+/// Computes the resources consumed by an invocation given its final storage
+/// footprint, ledger changes and encoded contract events.
+///
+/// This should be called after all the metered work of the invocation has been
+/// done in order to get the precise CPU and memory consumption.
+pub fn compute_invocation_resources(
+    budget: &Budget,
+    footprint: &Footprint,
+    ledger_changes: &[LedgerEntryChange],
+    encoded_contract_events: &[Vec<u8>],
+) -> Result<InvocationResources, HostError> {
+    let mut resources = InvocationResources::default();
+    for (_, access_type) in footprint.0.iter(budget)? {
+        match access_type {
+            AccessType::ReadOnly => resources.read_entries += 1,
+            AccessType::ReadWrite => resources.write_entries += 1,
         }
-        changes.push(entry_change);
     }
-    Ok(changes)
+    for change in ledger_changes {
+        resources.read_bytes = resources
+            .read_bytes
+            .saturating_add(change.old_entry_size_bytes);
+        resources.max_key_size_bytes = resources
+            .max_key_size_bytes
+            .max(change.encoded_key.len() as u32);
+        resources.max_entry_size_bytes = resources
+            .max_entry_size_bytes
+            .max(change.old_entry_size_bytes);
+        if let Some(new_value) = &change.encoded_new_value {
+            let new_size = new_value.len() as u32;
+            resources.write_bytes = resources.write_bytes.saturating_add(new_size);
+            resources.max_entry_size_bytes = resources.max_entry_size_bytes.max(new_size);
+        }
+    }
+    for event in encoded_contract_events {
+        resources.contract_events_size_bytes = resources
+            .contract_events_size_bytes
+            .saturating_add(event.len() as u32);
+    }
+    resources.instructions = budget.get_cpu_insns_consumed()?;
+    resources.mem_bytes = budget.get_mem_bytes_consumed()?;
+    Ok(resources)
 }
 
 /// Extracts the rent-related changes from the provided ledger changes.
@@ -236,19 +436,28 @@ pub fn invoke_host_function<T: AsRef<[u8]>, I: ExactSizeIterator<Item = T>>(
 
     let resources: SorobanResources =
         metered_from_xdr_with_budget(encoded_resources.as_ref(), &budget)?;
-    let footprint = build_storage_footprint_from_xdr(&budget, resources.footprint)?;
-    let storage_and_ttl_maps = build_storage_map_from_xdr_ledger_entries(
+    let footprint = build_storage_footprint_from_xdr(
+        &budget,
+        resources.footprint,
+        ledger_info.protocol_version,
+    )?;
+    let (storage_map, ttl_map) = build_storage_map_from_xdr_ledger_entries(
         &budget,
         &footprint,
         encoded_ledger_entries,
         encoded_ttl_entries,
-        ledger_info.sequence_number,
+        &ledger_info,
+        false,
     )?;
 
-    let storage_map = storage_and_ttl_maps.0;
     let init_storage_map = storage_map.metered_clone(budget)?;
 
-    let storage = Storage::with_enforcing_footprint_and_map(footprint, storage_map);
+    let mut storage = Storage::with_enforcing_footprint_and_map(footprint, storage_map);
+    // The footprint usage report is purely informational and is not metered,
+    // so it's only produced along with the other diagnostics.
+    if enable_diagnostics {
+        storage.enable_footprint_usage_tracking();
+    }
     let host = Host::with_storage_and_budget(storage, budget.clone());
     let auth_entries = host.build_auth_entries_from_xdr(encoded_auth_entries)?;
     let host_function: HostFunction = host.metered_from_xdr(encoded_host_fn.as_ref())?;
@@ -272,6 +481,14 @@ pub fn invoke_host_function<T: AsRef<[u8]>, I: ExactSizeIterator<Item = T>>(
         let _span1 = tracy_span!("Host::invoke_function");
         host.invoke_function(host_function)
     };
+    let footprint_usage = host.try_borrow_storage()?.footprint_usage_report();
+    let footprint_usage = match footprint_usage {
+        Some(report) if result.is_ok() => {
+            host.footprint_usage_diagnostics(&report);
+            report
+        }
+        _ => FootprintUsageReport::default(),
+    };
     let (storage, events) = host.try_finish()?;
     if enable_diagnostics {
         extract_diagnostic_events(&events, diagnostic_events);
@@ -281,30 +498,554 @@ pub fn invoke_host_function<T: AsRef<[u8]>, I: ExactSizeIterator<Item = T>>(
         metered_write_xdr(&budget, &res, &mut encoded_result_sc_val)?;
         Ok(encoded_result_sc_val)
     })?;
-    if encoded_invoke_result.is_ok() {
+    // The footprint usage report is purely informational, so it's encoded
+    // without metering in order to not affect the cost of the invocation.
+    let encode_unmetered = |keys: &[Rc<LedgerKey>]| -> Result<Vec<Vec<u8>>, HostError> {
+        keys.iter()
+            .map(|key| Ok(key.to_xdr(DEFAULT_XDR_RW_LIMITS)?))
+            .collect()
+    };
+    let encoded_unused_keys = encode_unmetered(&footprint_usage.unused_keys)?;
+    let encoded_unwritten_read_write_keys =
+        encode_unmetered(&footprint_usage.unwritten_read_write_keys)?;
+    let (ledger_changes, encoded_contract_events) = if encoded_invoke_result.is_ok() {
         let init_storage_snapshot = StorageMapSnapshotSource {
             budget: &budget,
             map: &init_storage_map,
         };
-        let ledger_changes = get_ledger_changes(
-            &budget,
-            &storage,
-            &init_storage_snapshot,
-            storage_and_ttl_maps.1,
-        )?;
+        let ledger_changes =
+            get_ledger_changes(&budget, &storage, &init_storage_snapshot, ttl_map)?;
         let encoded_contract_events = encode_contract_events(budget, &events)?;
-        Ok(InvokeHostFunctionResult {
-            encoded_invoke_result,
-            ledger_changes,
-            encoded_contract_events,
-        })
+        (ledger_changes, encoded_contract_events)
+    } else {
+        (vec![], vec![])
+    };
+    let resources = compute_invocation_resources(
+        budget,
+        &storage.footprint,
+        &ledger_changes,
+        &encoded_contract_events,
+    )?;
+    Ok(InvokeHostFunctionResult {
+        encoded_invoke_result,
+        ledger_changes,
+        encoded_contract_events,
+        resources,
+        encoded_unused_keys,
+        encoded_unwritten_read_write_keys,
+    })
+}
+
+/// Simulates invocation of a host function within a fresh host instance.
+///
+/// This is the recording mode counterpart of `invoke_host_function`: instead
+/// of enforcing the footprint and the authorization entries provided by the
+/// transaction, the storage reads through to the provided `ledger_snapshot`
+/// and records the footprint, while the authorization manager records the
+/// authorization payloads that the invocation requires.
+///
+/// As in `invoke_host_function`, a clean budget has to be provided in order to
+/// get the precise metering data and the host function invocation errors are
+/// stored within `Ok(SimulateInvokeHostFunctionResult)`.
+///
+/// When diagnostics are enabled, we try to populate `diagnostic_events`
+/// even if the `SimulateInvokeHostFunctionResult` fails for any reason.
+///
+/// When `validate_contract_spec` is set, the arguments of the invoked
+/// contract function are checked against the contract spec (see
+/// [Host::enable_contract_spec_validation]), which doesn't affect the
+/// simulated resource consumption but fails the invocation when the arguments
+/// don't match the spec.
+#[cfg(any(test, feature = "recording_auth"))]
+#[allow(clippy::too_many_arguments)]
+pub fn simulate_invoke_host_function<T: AsRef<[u8]>>(
+    budget: &Budget,
+    enable_diagnostics: bool,
+    validate_contract_spec: bool,
+    encoded_host_fn: T,
+    encoded_source_account: T,
+    ledger_info: LedgerInfo,
+    ledger_snapshot: Rc<dyn SnapshotSource>,
+    base_prng_seed: T,
+    diagnostic_events: &mut Vec<DiagnosticEvent>,
+) -> Result<SimulateInvokeHostFunctionResult, HostError> {
+    let _span0 = tracy_span!("simulate_invoke_host_function");
+
+    let storage = Storage::with_recording_footprint(Rc::clone(&ledger_snapshot));
+    let host = Host::with_storage_and_budget(storage, budget.clone());
+    let host_function: HostFunction = host.metered_from_xdr(encoded_host_fn.as_ref())?;
+    let source_account: AccountId = host.metered_from_xdr(encoded_source_account.as_ref())?;
+    host.set_source_account(source_account)?;
+    host.set_ledger_info(ledger_info)?;
+    host.switch_to_recording_auth(true)?;
+    host.enable_contract_spec_validation(validate_contract_spec)?;
+    let seed32: [u8; 32] = base_prng_seed.as_ref().try_into().map_err(|_| {
+        host.err(
+            ScErrorType::Context,
+            ScErrorCode::InternalError,
+            "base PRNG seed is not 32-bytes long",
+            &[],
+        )
+    })?;
+    host.set_base_prng_seed(seed32)?;
+    if enable_diagnostics {
+        host.set_diagnostic_level(DiagnosticLevel::Debug)?;
+    }
+    let result = {
+        let _span1 = tracy_span!("Host::invoke_function");
+        host.invoke_function(host_function)
+    };
+    let auth_entries = host
+        .get_recorded_auth_payloads()?
+        .into_iter()
+        .map(recorded_auth_payload_to_auth_entry)
+        .metered_collect::<Result<Vec<SorobanAuthorizationEntry>, HostError>>(budget)??;
+    let archived_keys = host.recorded_archived_keys(ledger_snapshot.as_ref())?;
+    if !archived_keys.is_empty() {
+        host.archived_keys_diagnostics(&archived_keys);
+    }
+    let (storage, events) = host.try_finish()?;
+    if enable_diagnostics {
+        extract_diagnostic_events(&events, diagnostic_events);
+    }
+    let footprint = build_xdr_footprint_from_storage_footprint(budget, &storage.footprint)?;
+    let encoded_invoke_result = match result {
+        Ok(res) => {
+            let mut encoded_result_sc_val = vec![];
+            metered_write_xdr(budget, &res, &mut encoded_result_sc_val)?;
+            Ok(encoded_result_sc_val)
+        }
+        Err(e) => Err(e),
+    };
+    let (ledger_changes, encoded_contract_events) = if encoded_invoke_result.is_ok() {
+        (
+            get_ledger_changes(
+                budget,
+                &storage,
+                ledger_snapshot.as_ref(),
+                TtlEntryMap::new(),
+            )?,
+            encode_contract_events(budget, &events)?,
+        )
     } else {
-        Ok(InvokeHostFunctionResult {
-            encoded_invoke_result,
-            ledger_changes: vec![],
-            encoded_contract_events: vec![],
+        (vec![], vec![])
+    };
+    let resources = compute_invocation_resources(
+        budget,
+        &storage.footprint,
+        &ledger_changes,
+        &encoded_contract_events,
+    )?;
+    let mut encoded_archived_keys = Vec::with_capacity(archived_keys.len());
+    for key in archived_keys {
+        let mut encoded_key = vec![];
+        metered_write_xdr(budget, key.as_ref(), &mut encoded_key)?;
+        encoded_archived_keys.push(encoded_key);
+    }
+    Ok(SimulateInvokeHostFunctionResult {
+        encoded_invoke_result,
+        footprint,
+        auth_entries,
+        ledger_changes,
+        encoded_contract_events,
+        resources,
+        encoded_archived_keys,
+    })
+}
+
+/// Applies `ExtendFootprintTtlOp` to the provided ledger entries.
+///
+/// This accepts the inputs in the same format as `invoke_host_function`. The
+/// footprint must only contain read-only contract data and code entries. Every
+/// entry that is live and has live until ledger below
+/// `ledger_info.sequence_number + extend_to` is extended to that ledger.
+/// Entries that are missing or archived are skipped.
+///
+/// This may fail when the operation is malformed (i.e. the footprint is not
+/// valid for the operation or `extend_to` exceeds `max_entry_ttl`), when
+/// budget is exceeded, or if there is an internal error.
+pub fn extend_footprint_ttl<T: AsRef<[u8]>, I: ExactSizeIterator<Item = T>>(
+    budget: &Budget,
+    encoded_op: T,
+    encoded_resources: T,
+    ledger_info: LedgerInfo,
+    encoded_ledger_entries: I,
+    encoded_ttl_entries: I,
+) -> Result<TtlOperationResult, HostError> {
+    let _span0 = tracy_span!("extend_footprint_ttl");
+
+    let op: ExtendFootprintTtlOp = metered_from_xdr_with_budget(encoded_op.as_ref(), budget)?;
+    let resources: SorobanResources =
+        metered_from_xdr_with_budget(encoded_resources.as_ref(), budget)?;
+    if !resources.footprint.read_write.is_empty() {
+        return Err(invalid_ttl_operation_error());
+    }
+    for key in resources.footprint.read_only.iter() {
+        if get_key_durability(key).is_none() {
+            return Err(invalid_ttl_operation_error());
+        }
+    }
+    let new_live_until_ledger = ledger_info
+        .sequence_number
+        .checked_add(op.extend_to)
+        .ok_or_else(invalid_ttl_operation_error)?;
+    if new_live_until_ledger > ledger_info.max_live_until_ledger()? {
+        return Err(invalid_ttl_operation_error());
+    }
+    apply_ttl_operation(
+        budget,
+        resources.footprint,
+        &ledger_info,
+        encoded_ledger_entries,
+        encoded_ttl_entries,
+        TtlOperation::Extend {
+            new_live_until_ledger,
+        },
+    )
+}
+
+/// Applies `RestoreFootprintOp` to the provided ledger entries.
+///
+/// This accepts the inputs in the same format as `invoke_host_function`, but
+/// the ledger entries may be archived. The footprint must only contain
+/// read-write persistent contract data and code entries. Every archived entry
+/// is restored with the minimum live until ledger for persistent entries.
+/// Entries that are missing or still live are skipped.
+///
+/// This may fail when the operation is malformed (i.e. the footprint is not
+/// valid for the operation), when budget is exceeded, or if there is an
+/// internal error.
+pub fn restore_footprint<T: AsRef<[u8]>, I: ExactSizeIterator<Item = T>>(
+    budget: &Budget,
+    encoded_op: T,
+    encoded_resources: T,
+    ledger_info: LedgerInfo,
+    encoded_ledger_entries: I,
+    encoded_ttl_entries: I,
+) -> Result<TtlOperationResult, HostError> {
+    let _span0 = tracy_span!("restore_footprint");
+
+    let _op: RestoreFootprintOp = metered_from_xdr_with_budget(encoded_op.as_ref(), budget)?;
+    let resources: SorobanResources =
+        metered_from_xdr_with_budget(encoded_resources.as_ref(), budget)?;
+    if !resources.footprint.read_only.is_empty() {
+        return Err(invalid_ttl_operation_error());
+    }
+    for key in resources.footprint.read_write.iter() {
+        if !matches!(
+            get_key_durability(key),
+            Some(ContractDataDurability::Persistent)
+        ) {
+            return Err(invalid_ttl_operation_error());
+        }
+    }
+    let restored_live_until_ledger =
+        ledger_info.min_live_until_ledger(ContractDataDurability::Persistent)?;
+    apply_ttl_operation(
+        budget,
+        resources.footprint,
+        &ledger_info,
+        encoded_ledger_entries,
+        encoded_ttl_entries,
+        TtlOperation::Restore {
+            restored_live_until_ledger,
+        },
+    )
+}
+
+/// Mutable in-memory view of the ledger on top of a `SnapshotSource`.
+///
+/// The entries that have been modified via `apply_ledger_changes` (or
+/// directly via `put`, `del` and `set_live_until_ledger`) are served from the
+/// view itself, while all the other entries are read through to the
+/// underlying snapshot, which is never modified.
+pub struct LedgerView {
+    snapshot: Rc<dyn SnapshotSource>,
+    updated_entries: BTreeMap<Rc<LedgerKey>, Option<EntryWithLiveUntil>>,
+}
+
+impl LedgerView {
+    pub fn new(snapshot: Rc<dyn SnapshotSource>) -> Self {
+        Self {
+            snapshot,
+            updated_entries: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the current state of the entry with its live until ledger, or
+    /// `None` if the entry doesn't exist.
+    pub fn get_entry(&self, key: &Rc<LedgerKey>) -> Result<Option<EntryWithLiveUntil>, HostError> {
+        if let Some(entry) = self.updated_entries.get(key) {
+            return Ok(entry.clone());
+        }
+        if self.snapshot.has(key)? {
+            Ok(Some(self.snapshot.get(key)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns all the entries that have been modified relatively to the
+    /// underlying snapshot. Removed entries are represented as `None`.
+    pub fn updated_entries(
+        &self,
+    ) -> impl Iterator<Item = (&Rc<LedgerKey>, &Option<EntryWithLiveUntil>)> {
+        self.updated_entries.iter()
+    }
+
+    /// Drops all the modifications, so that all the entries are read through
+    /// to the underlying snapshot again.
+    pub fn discard_updated_entries(&mut self) {
+        self.updated_entries.clear();
+    }
+
+    /// Creates or overwrites the entry.
+    pub fn put(
+        &mut self,
+        entry: LedgerEntry,
+        live_until_ledger: Option<u32>,
+    ) -> Result<(), HostError> {
+        let key = Rc::new(ledger_entry_to_ledger_key(&entry, &Budget::default())?);
+        self.updated_entries
+            .insert(key, Some((Rc::new(entry), live_until_ledger)));
+        Ok(())
+    }
+
+    /// Removes the entry. Removing an entry that doesn't exist is a no-op.
+    pub fn del(&mut self, key: &Rc<LedgerKey>) {
+        self.updated_entries.insert(Rc::clone(key), None);
+    }
+
+    /// Updates the live until ledger of an existing entry.
+    pub fn set_live_until_ledger(
+        &mut self,
+        key: &Rc<LedgerKey>,
+        live_until_ledger: u32,
+    ) -> Result<(), HostError> {
+        let (entry, _) = self.get(key)?;
+        self.updated_entries
+            .insert(Rc::clone(key), Some((entry, Some(live_until_ledger))));
+        Ok(())
+    }
+
+    /// Folds the ledger changes produced by `invoke_host_function` (or any
+    /// other e2e entry point) into this view.
+    ///
+    /// Read-only entries may only have their live until ledger updated, while
+    /// read-write entries are overwritten or removed.
+    pub fn apply_ledger_changes(
+        &mut self,
+        ledger_changes: &[LedgerEntryChange],
+    ) -> Result<(), HostError> {
+        for change in ledger_changes {
+            let key = Rc::new(LedgerKey::from_xdr(
+                &change.encoded_key,
+                DEFAULT_XDR_RW_LIMITS,
+            )?);
+            let new_live_until_ledger = change
+                .ttl_change
+                .as_ref()
+                .map(|ttl_change| ttl_change.new_live_until_ledger);
+            let new_entry = if change.read_only {
+                match (self.get_entry(&key)?, new_live_until_ledger) {
+                    (Some((entry, old_live_until_ledger)), Some(new_live_until_ledger))
+                        if old_live_until_ledger != Some(new_live_until_ledger) =>
+                    {
+                        Some((entry, Some(new_live_until_ledger)))
+                    }
+                    _ => continue,
+                }
+            } else if let Some(encoded_new_value) = &change.encoded_new_value {
+                let entry = LedgerEntry::from_xdr(encoded_new_value, DEFAULT_XDR_RW_LIMITS)?;
+                Some((Rc::new(entry), new_live_until_ledger))
+            } else {
+                None
+            };
+            self.updated_entries.insert(key, new_entry);
+        }
+        Ok(())
+    }
+}
+
+impl SnapshotSource for LedgerView {
+    fn get(&self, key: &Rc<LedgerKey>) -> Result<EntryWithLiveUntil, HostError> {
+        self.get_entry(key)?.ok_or_else(|| {
+            Error::from_type_and_code(ScErrorType::Storage, ScErrorCode::MissingValue).into()
         })
     }
+
+    fn has(&self, key: &Rc<LedgerKey>) -> Result<bool, HostError> {
+        Ok(self.get_entry(key)?.is_some())
+    }
+}
+
+/// Inputs of a single transaction applied by `TransactionBatchDriver`.
+///
+/// The inputs have the same meaning as the respective arguments of
+/// `invoke_host_function`, except for the ledger entries that are provided by
+/// the driver.
+pub struct BatchTransaction {
+    /// Budget for this transaction. It can be examined after the transaction
+    /// has been applied in order to get its metering data.
+    pub budget: Budget,
+    pub enable_diagnostics: bool,
+    pub encoded_host_fn: Vec<u8>,
+    pub encoded_resources: Vec<u8>,
+    pub encoded_source_account: Vec<u8>,
+    pub encoded_auth_entries: Vec<Vec<u8>>,
+    pub base_prng_seed: Vec<u8>,
+}
+
+/// Result of applying a single transaction via `TransactionBatchDriver`.
+pub struct BatchTransactionResult {
+    /// Result of `invoke_host_function` for this transaction.
+    pub result: Result<InvokeHostFunctionResult, HostError>,
+    /// Diagnostic events emitted by this transaction (if enabled).
+    pub diagnostic_events: Vec<DiagnosticEvent>,
+}
+
+/// Applies sequences of host functions against a `LedgerView`, similarly to
+/// how the transactions are applied during the ledger close.
+///
+/// Every transaction is invoked within a fresh host instance via
+/// `invoke_host_function`, with the footprint entries loaded from the ledger
+/// view. The ledger changes of every successful transaction are folded into
+/// the view before applying the next transaction.
+pub struct TransactionBatchDriver {
+    ledger_info: LedgerInfo,
+    ledger: LedgerView,
+}
+
+impl TransactionBatchDriver {
+    pub fn new(ledger_info: LedgerInfo, snapshot: Rc<dyn SnapshotSource>) -> Self {
+        Self {
+            ledger_info,
+            ledger: LedgerView::new(snapshot),
+        }
+    }
+
+    pub fn ledger(&self) -> &LedgerView {
+        &self.ledger
+    }
+
+    pub fn into_ledger(self) -> LedgerView {
+        self.ledger
+    }
+
+    /// Applies all the transactions in order and returns their results.
+    pub fn apply_transactions(
+        &mut self,
+        transactions: &[BatchTransaction],
+    ) -> Vec<BatchTransactionResult> {
+        transactions
+            .iter()
+            .map(|tx| self.apply_transaction(tx))
+            .collect()
+    }
+
+    /// Applies a single transaction and folds its ledger changes into the
+    /// ledger view in case of success.
+    ///
+    /// Expired entries are passed to `invoke_host_function` as is, so
+    /// transactions that have expired entries in the footprint fail with an
+    /// internal error.
+    pub fn apply_transaction(&mut self, tx: &BatchTransaction) -> BatchTransactionResult {
+        let mut diagnostic_events = vec![];
+        let result = self.apply_transaction_internal(tx, &mut diagnostic_events);
+        BatchTransactionResult {
+            result,
+            diagnostic_events,
+        }
+    }
+
+    fn apply_transaction_internal(
+        &mut self,
+        tx: &BatchTransaction,
+        diagnostic_events: &mut Vec<DiagnosticEvent>,
+    ) -> Result<InvokeHostFunctionResult, HostError> {
+        let _span0 = tracy_span!("TransactionBatchDriver::apply_transaction");
+        // Loading the entries is done by the embedder in real environments,
+        // so it's not metered with the transaction budget.
+        let resources = SorobanResources::from_xdr(&tx.encoded_resources, DEFAULT_XDR_RW_LIMITS)?;
+        let mut encoded_ledger_entries = vec![];
+        let mut encoded_ttl_entries = vec![];
+        for key in resources
+            .footprint
+            .read_only
+            .iter()
+            .chain(resources.footprint.read_write.iter())
+        {
+            let key = Rc::new(key.clone());
+            let Some((entry, live_until_ledger)) = self.ledger.get_entry(&key)? else {
+                continue;
+            };
+            // Entries without TTL are accompanied by empty TTL buffers.
+            let encoded_ttl_entry = match live_until_ledger {
+                Some(live_until_ledger) => TtlEntry {
+                    key_hash: Hash(Sha256::digest(key.to_xdr(DEFAULT_XDR_RW_LIMITS)?).into()),
+                    live_until_ledger_seq: live_until_ledger,
+                }
+                .to_xdr(DEFAULT_XDR_RW_LIMITS)?,
+                None => vec![],
+            };
+            encoded_ledger_entries.push(entry.to_xdr(DEFAULT_XDR_RW_LIMITS)?);
+            encoded_ttl_entries.push(encoded_ttl_entry);
+        }
+        let res = invoke_host_function(
+            &tx.budget,
+            tx.enable_diagnostics,
+            &tx.encoded_host_fn,
+            &tx.encoded_resources,
+            &tx.encoded_source_account,
+            tx.encoded_auth_entries.iter(),
+            self.ledger_info.clone(),
+            encoded_ledger_entries.iter(),
+            encoded_ttl_entries.iter(),
+            &tx.base_prng_seed,
+            diagnostic_events,
+        )?;
+        if res.encoded_invoke_result.is_ok() {
+            self.ledger.apply_ledger_changes(&res.ledger_changes)?;
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum EncodedInvokeResult<'a> {
+    Ok(#[serde(with = "crate::serde_hex::bytes")] std::borrow::Cow<'a, [u8]>),
+    Error(crate::xdr::ScError),
+}
+
+#[cfg(feature = "serde")]
+fn serialize_encoded_invoke_result<S: serde::Serializer>(
+    res: &Result<Vec<u8>, HostError>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use crate::xdr::ScError;
+    use serde::Serialize;
+
+    match res {
+        Ok(v) => EncodedInvokeResult::Ok(v.into()),
+        Err(e) => EncodedInvokeResult::Error(
+            ScError::try_from(e).unwrap_or(ScError::Context(ScErrorCode::InternalError)),
+        ),
+    }
+    .serialize(serializer)
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_encoded_invoke_result<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Result<Vec<u8>, HostError>, D::Error> {
+    use serde::Deserialize;
+
+    Ok(match EncodedInvokeResult::deserialize(deserializer)? {
+        EncodedInvokeResult::Ok(v) => Ok(v.into_owned()),
+        EncodedInvokeResult::Error(e) => Err(HostError::from(e)),
+    })
 }
 
 /// Encodes host events as `ContractEvent` XDR.
@@ -336,7 +1077,10 @@ fn extract_diagnostic_events(events: &Events, diagnostic_events: &mut Vec<Diagno
     }
 }
 
-fn ledger_entry_to_ledger_key(le: &LedgerEntry, budget: &Budget) -> Result<LedgerKey, HostError> {
+pub(crate) fn ledger_entry_to_ledger_key(
+    le: &LedgerEntry,
+    budget: &Budget,
+) -> Result<LedgerKey, HostError> {
     match &le.data {
         LedgerEntryData::Account(a) => Ok(LedgerKey::Account(LedgerKeyAccount {
             account_id: a.account_id.metered_clone(budget)?,
@@ -353,6 +1097,11 @@ fn ledger_entry_to_ledger_key(le: &LedgerEntry, budget: &Budget) -> Result<Ledge
         LedgerEntryData::ContractCode(code) => Ok(LedgerKey::ContractCode(LedgerKeyContractCode {
             hash: code.hash.metered_clone(budget)?,
         })),
+        LedgerEntryData::ConfigSetting(setting) => {
+            Ok(LedgerKey::ConfigSetting(LedgerKeyConfigSetting {
+                config_setting_id: setting.discriminant(),
+            }))
+        }
         _ => {
             return Err(Error::from_type_and_code(
                 ScErrorType::Storage,
@@ -363,11 +1112,103 @@ fn ledger_entry_to_ledger_key(le: &LedgerEntry, budget: &Budget) -> Result<Ledge
     }
 }
 
+enum TtlOperation {
+    Extend { new_live_until_ledger: u32 },
+    Restore { restored_live_until_ledger: u32 },
+}
+
+fn invalid_ttl_operation_error() -> HostError {
+    Error::from_type_and_code(ScErrorType::Storage, ScErrorCode::InvalidInput).into()
+}
+
+fn apply_ttl_operation<T: AsRef<[u8]>, I: ExactSizeIterator<Item = T>>(
+    budget: &Budget,
+    footprint: LedgerFootprint,
+    ledger_info: &LedgerInfo,
+    encoded_ledger_entries: I,
+    encoded_ttl_entries: I,
+    op: TtlOperation,
+) -> Result<TtlOperationResult, HostError> {
+    let footprint =
+        build_storage_footprint_from_xdr(budget, footprint, ledger_info.protocol_version)?;
+    // The expired entries are kept in the map in order to be restored or
+    // skipped by the operation.
+    let (init_storage_map, ttl_map) = build_storage_map_from_xdr_ledger_entries(
+        budget,
+        &footprint,
+        encoded_ledger_entries,
+        encoded_ttl_entries,
+        ledger_info,
+        true,
+    )?;
+    let mut storage_map = init_storage_map.metered_clone(budget)?;
+    let mut rent_changes = vec![];
+    for (key, entry_with_live_until_ledger) in init_storage_map.iter(budget)? {
+        let Some((entry, Some(old_live_until_ledger))) = entry_with_live_until_ledger else {
+            continue;
+        };
+        let is_live = *old_live_until_ledger >= ledger_info.sequence_number;
+        let (new_live_until_ledger, is_restored) = match op {
+            TtlOperation::Extend {
+                new_live_until_ledger,
+            } => {
+                if !is_live || *old_live_until_ledger >= new_live_until_ledger {
+                    continue;
+                }
+                (new_live_until_ledger, false)
+            }
+            TtlOperation::Restore {
+                restored_live_until_ledger,
+            } => {
+                if is_live {
+                    continue;
+                }
+                (restored_live_until_ledger, true)
+            }
+        };
+        let mut entry_buf = vec![];
+        metered_write_xdr(budget, entry.as_ref(), &mut entry_buf)?;
+        let entry_size = entry_buf.len() as u32;
+        // Restored entries are treated as newly created for the sake of the
+        // rent computation.
+        rent_changes.push(LedgerEntryRentChange {
+            is_persistent: matches!(
+                get_key_durability(key),
+                Some(ContractDataDurability::Persistent)
+            ),
+            old_size_bytes: if is_restored { 0 } else { entry_size },
+            new_size_bytes: entry_size,
+            old_live_until_ledger: if is_restored {
+                0
+            } else {
+                *old_live_until_ledger
+            },
+            new_live_until_ledger,
+        });
+        storage_map = storage_map.insert(
+            Rc::clone(key),
+            Some((Rc::clone(entry), Some(new_live_until_ledger))),
+            budget,
+        )?;
+    }
+    let storage = Storage::with_enforcing_footprint_and_map(footprint, storage_map);
+    let init_storage_snapshot = StorageMapSnapshotSource {
+        budget,
+        map: &init_storage_map,
+    };
+    let ledger_changes = get_ledger_changes(budget, &storage, &init_storage_snapshot, ttl_map)?;
+    Ok(TtlOperationResult {
+        ledger_changes,
+        rent_changes,
+    })
+}
+
 fn build_storage_footprint_from_xdr(
     budget: &Budget,
     footprint: LedgerFootprint,
+    protocol_version: u32,
 ) -> Result<Footprint, HostError> {
-    let mut footprint_map = FootprintMap::new();
+    let mut footprint_map = FootprintMap::new_for_protocol(protocol_version);
 
     for key in footprint.read_write.as_vec() {
         Storage::check_supported_ledger_key_type(&key)?;
@@ -389,14 +1230,65 @@ fn build_storage_footprint_from_xdr(
     Ok(Footprint(footprint_map))
 }
 
+#[cfg(any(test, feature = "recording_auth"))]
+fn build_xdr_footprint_from_storage_footprint(
+    budget: &Budget,
+    footprint: &Footprint,
+) -> Result<LedgerFootprint, HostError> {
+    let mut read_only = vec![];
+    let mut read_write = vec![];
+    for (key, access_type) in footprint.0.iter(budget)? {
+        let key = key.as_ref().metered_clone(budget)?;
+        match access_type {
+            AccessType::ReadOnly => read_only.push(key),
+            AccessType::ReadWrite => read_write.push(key),
+        }
+    }
+    Ok(LedgerFootprint {
+        read_only: read_only.try_into()?,
+        read_write: read_write.try_into()?,
+    })
+}
+
+// Converts the recorded authorization payload into an authorization entry
+// that still has to be signed when the address credentials are used.
+#[cfg(any(test, feature = "recording_auth"))]
+fn recorded_auth_payload_to_auth_entry(
+    payload: RecordedAuthPayload,
+) -> Result<SorobanAuthorizationEntry, HostError> {
+    let credentials = match (payload.address, payload.nonce) {
+        (Some(address), Some(nonce)) => SorobanCredentials::Address(SorobanAddressCredentials {
+            address,
+            nonce,
+            signature_expiration_ledger: 0,
+            signature: ScVal::Void,
+        }),
+        (None, None) => SorobanCredentials::SourceAccount,
+        _ => {
+            return Err(
+                Error::from_type_and_code(ScErrorType::Auth, ScErrorCode::InternalError).into(),
+            )
+        }
+    };
+    Ok(SorobanAuthorizationEntry {
+        credentials,
+        root_invocation: payload.invocation,
+    })
+}
+
+// Builds the storage map from the encoded ledger entries. Entries with TTL
+// that has already expired relatively to `ledger_num` are only allowed when
+// `allow_archived_entries` is set (i.e. for the TTL operations).
 fn build_storage_map_from_xdr_ledger_entries<T: AsRef<[u8]>, I: ExactSizeIterator<Item = T>>(
     budget: &Budget,
     footprint: &Footprint,
     encoded_ledger_entries: I,
     encoded_ttl_entries: I,
-    ledger_num: u32,
+    ledger_info: &LedgerInfo,
+    allow_archived_entries: bool,
 ) -> Result<(StorageMap, TtlEntryMap), HostError> {
-    let mut storage_map = StorageMap::new();
+    let ledger_num = ledger_info.sequence_number;
+    let mut storage_map = StorageMap::new_for_protocol(ledger_info.protocol_version);
     let mut ttl_map = TtlEntryMap::new();
 
     if encoded_ledger_entries.len() != encoded_ttl_entries.len() {
@@ -413,6 +1305,13 @@ fn build_storage_map_from_xdr_ledger_entries<T: AsRef<[u8]>, I: ExactSizeIterato
             budget,
         )?;
         let key = Rc::metered_new(ledger_entry_to_ledger_key(&le, budget)?, budget)?;
+        if !footprint.0.contains_key::<LedgerKey>(&key, budget)? {
+            return Err(Error::from_type_and_code(
+                ScErrorType::Storage,
+                ScErrorCode::InternalError,
+            )
+            .into());
+        }
 
         if !ttl_buf.as_ref().is_empty() {
             let ee = Rc::metered_new(
@@ -420,7 +1319,7 @@ fn build_storage_map_from_xdr_ledger_entries<T: AsRef<[u8]>, I: ExactSizeIterato
                 budget,
             )?;
 
-            if ee.live_until_ledger_seq < ledger_num {
+            if ee.live_until_ledger_seq < ledger_num && !allow_archived_entries {
                 return Err(Error::from_type_and_code(
                     ScErrorType::Storage,
                     ScErrorCode::InternalError,
@@ -440,14 +1339,6 @@ fn build_storage_map_from_xdr_ledger_entries<T: AsRef<[u8]>, I: ExactSizeIterato
             )
             .into());
         }
-
-        if !footprint.0.contains_key::<LedgerKey>(&key, budget)? {
-            return Err(Error::from_type_and_code(
-                ScErrorType::Storage,
-                ScErrorCode::InternalError,
-            )
-            .into());
-        }
         storage_map = storage_map.insert(key, Some((le, live_until_ledger)), budget)?;
     }
 
@@ -461,6 +1352,107 @@ fn build_storage_map_from_xdr_ledger_entries<T: AsRef<[u8]>, I: ExactSizeIterato
 }
 
 impl Host {
+    // Converts the ledger key to the value used in diagnostic events: contract
+    // data keys are represented as `[contract address, key]` pairs, contract
+    // code keys as Wasm hashes and account keys as account addresses. Other
+    // keys are not represented.
+    fn ledger_key_diagnostic_val(&self, key: &LedgerKey) -> Result<Option<ScVal>, HostError> {
+        let key_val = match key {
+            LedgerKey::ContractData(LedgerKeyContractData { contract, key, .. }) => {
+                let pair = vec![
+                    ScVal::Address(contract.metered_clone(self)?),
+                    key.metered_clone(self)?,
+                ];
+                ScVal::Vec(Some(ScVec(pair.try_into()?)))
+            }
+            LedgerKey::ContractCode(LedgerKeyContractCode { hash }) => {
+                ScVal::Bytes(ScBytes(hash.0.to_vec().try_into()?))
+            }
+            LedgerKey::Account(LedgerKeyAccount { account_id }) => {
+                ScVal::Address(ScAddress::Account(account_id.metered_clone(self)?))
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(key_val))
+    }
+
+    // Returns the keys of the persistent entries in the recorded footprint
+    // that are archived in the `ledger_snapshot`, i.e. have TTL that has
+    // already expired.
+    #[cfg(any(test, feature = "recording_auth"))]
+    fn recorded_archived_keys(
+        &self,
+        ledger_snapshot: &dyn SnapshotSource,
+    ) -> Result<Vec<Rc<LedgerKey>>, HostError> {
+        let ledger_num = self.with_ledger_info(|li| Ok(li.sequence_number))?;
+        let storage = self.try_borrow_storage()?;
+        let mut archived_keys = vec![];
+        for (key, _) in storage.footprint.0.iter(self.as_budget())? {
+            if !matches!(
+                get_key_durability(key),
+                Some(ContractDataDurability::Persistent)
+            ) || !ledger_snapshot.has(key)?
+            {
+                continue;
+            }
+            if let (_, Some(live_until_ledger)) = ledger_snapshot.get(key)? {
+                if live_until_ledger < ledger_num {
+                    archived_keys.push(Rc::clone(key));
+                }
+            }
+        }
+        Ok(archived_keys)
+    }
+
+    // Emits a diagnostic event with the archived keys found by the
+    // simulation. This only happens in debug mode and is thus not metered.
+    #[cfg(any(test, feature = "recording_auth"))]
+    fn archived_keys_diagnostics(&self, archived_keys: &[Rc<LedgerKey>]) {
+        self.with_debug_mode(|| {
+            let mut args = Vec::with_capacity(archived_keys.len());
+            for key in archived_keys {
+                if let Some(key_val) = self.ledger_key_diagnostic_val(key)? {
+                    args.push(self.to_host_val(&key_val)?);
+                }
+            }
+            self.log_diagnostics(
+                "footprint contains archived persistent entries, they have to be restored first",
+                &args,
+            );
+            Ok(())
+        })
+    }
+
+    // Emits a diagnostic event for every non-empty part of the footprint usage
+    // report. This only happens in debug mode and is thus not metered.
+    fn footprint_usage_diagnostics(&self, report: &FootprintUsageReport) {
+        self.with_debug_mode(|| {
+            let parts = [
+                (
+                    &report.unused_keys,
+                    "footprint contains keys that have not been accessed",
+                ),
+                (
+                    &report.unwritten_read_write_keys,
+                    "footprint contains read-write keys that have only been read",
+                ),
+            ];
+            for (keys, msg) in parts {
+                if keys.is_empty() {
+                    continue;
+                }
+                let mut args = Vec::with_capacity(keys.len());
+                for key in keys {
+                    if let Some(key_val) = self.ledger_key_diagnostic_val(key)? {
+                        args.push(self.to_host_val(&key_val)?);
+                    }
+                }
+                self.log_diagnostics(msg, &args);
+            }
+            Ok(())
+        })
+    }
+
     fn build_auth_entries_from_xdr<T: AsRef<[u8]>, I: ExactSizeIterator<Item = T>>(
         &self,
         encoded_contract_auth_entries: I,
@@ -20,6 +20,7 @@ use crate::{Host, HostError};
 
 /// The external representation of a host event.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HostEvent {
     pub event: crate::xdr::ContractEvent,
     // failed_call keeps track of if the call this event was emitted in failed
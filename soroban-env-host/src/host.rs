@@ -4,12 +4,14 @@ use std::rc::Rc;
 use crate::{
     auth::AuthorizationManager,
     budget::{AsBudget, Budget},
+    contract_spec::ContractSpec,
     events::{diagnostic::DiagnosticLevel, Events, InternalEventsBuffer},
     host_object::{HostMap, HostObject, HostObjectType, HostVec},
     impl_bignum_host_fns, impl_bignum_host_fns_rhs_u32, impl_wrapping_obj_from_num,
     impl_wrapping_obj_to_num,
     num::*,
-    storage::Storage,
+    storage::{LedgerEntrySizeLimits, Storage, StorageAccessLog},
+    vm::{InstantiatedModules, InvocationProfile, ModuleCache, VmProfiler},
     xdr::{
         int128_helpers, AccountId, Asset, ContractCostType, ContractEventType, ContractExecutable,
         ContractIdPreimage, ContractIdPreimageFromAddress, CreateContractArgs, Duration, Hash,
@@ -34,6 +36,8 @@ mod lifecycle;
 mod mem_helper;
 pub(crate) mod metered_clone;
 pub(crate) mod metered_map;
+pub(crate) mod metered_persistent_map;
+pub(crate) mod metered_storage_map;
 pub(crate) mod metered_vector;
 pub(crate) mod metered_xdr;
 mod num;
@@ -65,10 +69,12 @@ pub(crate) struct VmSlice {
 }
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LedgerInfo {
     pub protocol_version: u32,
     pub sequence_number: u32,
     pub timestamp: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::bytes"))]
     pub network_id: [u8; 32],
     pub base_reserve: u32,
     pub min_temp_entry_ttl: u32,
@@ -121,6 +127,22 @@ struct HostImpl {
     // `with_debug_mode` callback that switches to the shadow budget.
     diagnostic_level: RefCell<DiagnosticLevel>,
     base_prng: RefCell<Option<Prng>>,
+    // Embedder-provided cache of parsed Wasm modules, possibly shared with
+    // other hosts.
+    module_cache: RefCell<Option<ModuleCache>>,
+    // The modules of the contracts instantiated by this host, which decide
+    // whether an instantiation is charged as a cached one. Unlike the
+    // `module_cache` this only depends on the executed invocations.
+    instantiated_modules: RefCell<InstantiatedModules>,
+    // Parsed contract specs keyed by the Wasm hash (`None` when the contract
+    // has no valid spec), used for checking the top-level contract call
+    // arguments against the spec. Only present when the check is enabled, which is only
+    // meant for tests and simulation. The specs are cached by the code rather
+    // than by the `Vm`, as a new `Vm` is instantiated for every call.
+    contract_specs: RefCell<Option<std::collections::BTreeMap<Hash, Option<Rc<ContractSpec>>>>>,
+    // Per-invocation budget attribution, only present when the VM profiling
+    // is enabled.
+    vm_profiler: RefCell<Option<VmProfiler>>,
     // Auth-recording mode generates pseudorandom nonces to populate its output.
     // We'd like these to be deterministic from one run to the next, but also
     // completely isolated from any use of the user-accessible PRNGs (either
@@ -147,6 +169,11 @@ struct HostImpl {
     // has happened or has been recorded.
     #[cfg(any(test, feature = "testutils"))]
     previous_authorization_manager: RefCell<Option<AuthorizationManager>>,
+    // Persistent entries archived by the ledger time-travel in tests (see
+    // `Host::advance_ledger`). Not set until the first time-travel, which
+    // also installs the snapshot source that fails reading these entries.
+    #[cfg(any(test, feature = "testutils"))]
+    archived_entries: RefCell<Option<crate::testutils::ArchivedEntries>>,
     // Store a hook that we will call with various lifecycle events during
     // the host's execution. No guarantees are made about the stability of this
     // interface, it exists strictly for internal testing of the host.
@@ -216,6 +243,30 @@ impl_checked_borrow_helpers!(
     try_borrow_objects_mut
 );
 impl_checked_borrow_helpers!(storage, Storage, try_borrow_storage, try_borrow_storage_mut);
+impl_checked_borrow_helpers!(
+    contract_specs,
+    Option<std::collections::BTreeMap<Hash, Option<Rc<ContractSpec>>>>,
+    try_borrow_contract_specs,
+    try_borrow_contract_specs_mut
+);
+impl_checked_borrow_helpers!(
+    vm_profiler,
+    Option<VmProfiler>,
+    try_borrow_vm_profiler,
+    try_borrow_vm_profiler_mut
+);
+impl_checked_borrow_helpers!(
+    module_cache,
+    Option<ModuleCache>,
+    try_borrow_module_cache,
+    try_borrow_module_cache_mut
+);
+impl_checked_borrow_helpers!(
+    instantiated_modules,
+    InstantiatedModules,
+    try_borrow_instantiated_modules,
+    try_borrow_instantiated_modules_mut
+);
 impl_checked_borrow_helpers!(
     context_stack,
     Vec<Context>,
@@ -274,6 +325,14 @@ impl_checked_borrow_helpers!(
     try_borrow_previous_authorization_manager_mut
 );
 
+#[cfg(any(test, feature = "testutils"))]
+impl_checked_borrow_helpers!(
+    archived_entries,
+    Option<crate::testutils::ArchivedEntries>,
+    try_borrow_archived_entries,
+    try_borrow_archived_entries_mut
+);
+
 #[cfg(any(test, feature = "testutils"))]
 impl_checked_borrow_helpers!(
     lifecycle_event_hook,
@@ -322,6 +381,10 @@ impl Host {
             ),
             diagnostic_level: Default::default(),
             base_prng: RefCell::new(None),
+            module_cache: RefCell::new(None),
+            instantiated_modules: Default::default(),
+            contract_specs: RefCell::new(None),
+            vm_profiler: RefCell::new(None),
             #[cfg(any(test, feature = "recording_auth"))]
             recording_auth_nonce_prng: RefCell::new(None),
             #[cfg(any(test, feature = "testutils"))]
@@ -331,6 +394,8 @@ impl Host {
             #[cfg(any(test, feature = "testutils"))]
             previous_authorization_manager: RefCell::new(None),
             #[cfg(any(test, feature = "testutils"))]
+            archived_entries: RefCell::new(None),
+            #[cfg(any(test, feature = "testutils"))]
             lifecycle_event_hook: RefCell::new(None),
             #[cfg(any(test, feature = "testutils"))]
             top_contract_invocation_hook: RefCell::new(None),
@@ -436,6 +501,8 @@ impl Host {
     }
 
     pub fn set_ledger_info(&self, info: LedgerInfo) -> Result<(), HostError> {
+        self.try_borrow_storage_mut()?
+            .use_protocol_version(info.protocol_version, self.budget_ref())?;
         *self.try_borrow_ledger_mut()? = Some(info);
         Ok(())
     }
@@ -459,18 +526,22 @@ impl Host {
     where
         F: FnMut(&mut LedgerInfo),
     {
-        match self.try_borrow_ledger_mut()?.as_mut() {
-            None => Err(self.err(
-                ScErrorType::Context,
-                ScErrorCode::InternalError,
-                "missing ledger info",
-                &[],
-            )),
+        let protocol_version = match self.try_borrow_ledger_mut()?.as_mut() {
+            None => {
+                return Err(self.err(
+                    ScErrorType::Context,
+                    ScErrorCode::InternalError,
+                    "missing ledger info",
+                    &[],
+                ))
+            }
             Some(li) => {
                 f(li);
-                Ok(())
+                li.protocol_version
             }
-        }
+        };
+        self.try_borrow_storage_mut()?
+            .use_protocol_version(protocol_version, self.budget_ref())
     }
 
     pub fn get_ledger_protocol_version(&self) -> Result<u32, HostError> {
@@ -504,6 +575,73 @@ impl Host {
         self.set_diagnostic_level(DiagnosticLevel::Debug)
     }
 
+    /// Enables recording of the per-key storage access statistics, which can
+    /// be retrieved via [`Host::get_storage_access_log`], or from the
+    /// [`Storage`] returned by [`Host::try_finish`].
+    pub fn enable_storage_access_log(&self) -> Result<(), HostError> {
+        self.try_borrow_storage_mut()?.enable_access_log();
+        Ok(())
+    }
+
+    /// Returns the storage access statistics recorded so far, or `None` if
+    /// the recording hasn't been enabled via
+    /// [`Host::enable_storage_access_log`].
+    pub fn get_storage_access_log(&self) -> Result<Option<StorageAccessLog>, HostError> {
+        Ok(self.try_borrow_storage()?.access_log().cloned())
+    }
+
+    /// Sets the [`ModuleCache`] to look the contract Wasm modules up in
+    /// before parsing them, or stops using the cache when `None`.
+    pub fn set_module_cache(&self, cache: Option<ModuleCache>) -> Result<(), HostError> {
+        *self.try_borrow_module_cache_mut()? = cache;
+        Ok(())
+    }
+
+    /// Enables checking the arguments of the top-level Wasm contract call
+    /// (i.e. the [`HostFunction::InvokeContract`](crate::xdr::HostFunction)
+    /// arguments) against the parameter types declared in the contract spec
+    /// (see [`crate::contract_spec`]) before the call is dispatched. The check is not metered and is only
+    /// meant to provide better error reporting in tests and simulation.
+    pub fn enable_contract_spec_validation(&self, enabled: bool) -> Result<(), HostError> {
+        *self.try_borrow_contract_specs_mut()? = enabled.then(Default::default);
+        Ok(())
+    }
+
+    /// Enables recording an [`InvocationProfile`] for every Wasm contract
+    /// function invocation, which attributes the consumed fuel and the host
+    /// function charges to the invoked function as a whole (the profile is
+    /// per export, not per Wasm function). Enabling the profiling discards
+    /// the previously recorded profiles.
+    pub fn enable_vm_profiling(&self, enabled: bool) -> Result<(), HostError> {
+        *self.try_borrow_vm_profiler_mut()? = enabled.then(VmProfiler::default);
+        Ok(())
+    }
+
+    /// Returns the profiles of the Wasm contract function invocations recorded
+    /// since the VM profiling has been enabled, in the order the invocations
+    /// have started.
+    pub fn vm_profile(&self) -> Result<Vec<InvocationProfile>, HostError> {
+        Ok(self
+            .try_borrow_vm_profiler()?
+            .as_ref()
+            .map(|profiler| profiler.invocations().to_vec())
+            .unwrap_or_default())
+    }
+
+    pub(crate) fn module_cache(&self) -> Result<Option<ModuleCache>, HostError> {
+        Ok(self.try_borrow_module_cache()?.clone())
+    }
+
+    /// Sets the [`LedgerEntrySizeLimits`] that the contract data, instance and
+    /// code writes are checked against, or disables the checks when `None`.
+    pub fn set_ledger_entry_size_limits(
+        &self,
+        limits: Option<LedgerEntrySizeLimits>,
+    ) -> Result<(), HostError> {
+        self.try_borrow_storage_mut()?.set_entry_size_limits(limits);
+        Ok(())
+    }
+
     /// Wraps a `budget.with_shadow_mode` call with a check against the
     /// diagnostic level. This wrapper should be used for any work that is part
     /// of the production workflow but in debug mode, i.e. diagnostic related
@@ -2215,6 +2353,39 @@ impl VmCallerEnv for Host {
         }
     }
 
+    // Notes on metering: covered by the components.
+    #[cfg(feature = "next")]
+    fn try_call_with_budget(
+        &self,
+        vmcaller: &mut VmCaller<Host>,
+        contract_address: AddressObject,
+        func: Symbol,
+        args: VecObject,
+        cpu_insns: U64Val,
+        mem_bytes: U64Val,
+    ) -> Result<Val, HostError> {
+        let cpu_insns = u64::try_from_val(self, &cpu_insns)?;
+        let mem_bytes = u64::try_from_val(self, &mem_bytes)?;
+        // Exceeding the limits of the call is recoverable, unlike running out
+        // of the whole budget, which is reported by `with_sub_limits` as the
+        // outer error and still traps. Handing the (Budget, ExceededLimit)
+        // error to the caller as a value doesn't let it forge a budget failure
+        // further up: returning it from the contract is an illegal non-contract
+        // error return, which `try_call` recovers from like any other.
+        let res = self.as_budget().with_sub_limits(cpu_insns, mem_bytes, || {
+            self.try_call(vmcaller, contract_address, func, args)
+        })?;
+        match res {
+            Err(e)
+                if e.error.is_type(ScErrorType::Budget)
+                    && e.error.is_code(ScErrorCode::ExceededLimit) =>
+            {
+                Ok(e.error.to_val())
+            }
+            res => res,
+        }
+    }
+
     // endregion: "call" module functions
     // region: "buf" module functions
 
@@ -9,7 +9,7 @@ use soroban_env_common::xdr::{
 use soroban_env_common::{AddressObject, Env, StorageType, U32Val, Val};
 
 use crate::budget::AsBudget;
-use crate::storage::{InstanceStorageMap, Storage};
+use crate::storage::{xdr_size, InstanceStorageMap, Storage};
 use crate::xdr::{
     AccountEntry, AccountId, ContractDataEntry, Hash, HashIdPreimage, LedgerEntry, LedgerEntryData,
     LedgerEntryExt, LedgerKey, LedgerKeyAccount, LedgerKeyContractCode, LedgerKeyContractData,
@@ -198,6 +198,7 @@ impl Host {
                 ));
             }
 
+            self.check_contract_instance_entry_size(key, &current, &contract_id)?;
             self.try_borrow_storage_mut()?
                 .put(
                     &key,
@@ -224,10 +225,12 @@ impl Host {
                 durability: ContractDataDurability::Persistent,
                 ext: ExtensionPoint::V0,
             };
+            let entry = Host::new_contract_data(self, data)?;
+            self.check_contract_instance_entry_size(key, &entry, &contract_id)?;
             self.try_borrow_storage_mut()?
                 .put(
                     key,
-                    &Host::new_contract_data(self, data)?,
+                    &entry,
                     Some(self.get_min_live_until_ledger(ContractDataDurability::Persistent)?),
                     self.as_budget(),
                 )
@@ -480,6 +483,7 @@ impl Host {
                     ));
                 }
             }
+            self.check_contract_data_entry_size(&key, &current, || Ok(k))?;
             self.try_borrow_storage_mut()?
                 .put(
                     &key,
@@ -496,10 +500,12 @@ impl Host {
                 durability,
                 ext: ExtensionPoint::V0,
             };
+            let entry = Host::new_contract_data(self, data)?;
+            self.check_contract_data_entry_size(&key, &entry, || Ok(k))?;
             self.try_borrow_storage_mut()?
                 .put(
                     &key,
-                    &Host::new_contract_data(self, data)?,
+                    &entry,
                     Some(self.get_min_live_until_ledger(durability)?),
                     self.as_budget(),
                 )
@@ -510,6 +516,87 @@ impl Host {
     }
 }
 
+impl Host {
+    // Checks the contract data entry about to be written against the
+    // `LedgerEntrySizeLimits`, if these are set. `diagnostic_key` builds the
+    // value identifying the entry in the error diagnostics and is only called
+    // when the check fails.
+    // Notes on metering: the XDR sizes are computed without metering, so that
+    // enforcing the limits (e.g. during simulation) doesn't change the budget
+    // usage compared to the execution without them. Only the error path is
+    // metered.
+    fn check_contract_data_entry_size(
+        &self,
+        key: &LedgerKey,
+        entry: &LedgerEntry,
+        diagnostic_key: impl FnOnce() -> Result<Val, HostError>,
+    ) -> Result<(), HostError> {
+        let Some(limits) = self.try_borrow_storage()?.entry_size_limits() else {
+            return Ok(());
+        };
+        let key_size = xdr_size(key)?;
+        if key_size > limits.max_contract_data_key_size_bytes as u64 {
+            return Err(self.err(
+                ScErrorType::Storage,
+                ScErrorCode::ExceededLimit,
+                "contract data key size exceeds the network limit",
+                &[
+                    diagnostic_key()?,
+                    u32::try_from(key_size).unwrap_or(u32::MAX).into(),
+                    limits.max_contract_data_key_size_bytes.into(),
+                ],
+            ));
+        }
+        let entry_size = xdr_size(entry)?;
+        if entry_size > limits.max_contract_data_entry_size_bytes as u64 {
+            return Err(self.err(
+                ScErrorType::Storage,
+                ScErrorCode::ExceededLimit,
+                "contract data entry size exceeds the network limit",
+                &[
+                    diagnostic_key()?,
+                    u32::try_from(entry_size).unwrap_or(u32::MAX).into(),
+                    limits.max_contract_data_entry_size_bytes.into(),
+                ],
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_contract_instance_entry_size(
+        &self,
+        key: &LedgerKey,
+        entry: &LedgerEntry,
+        contract_id: &Hash,
+    ) -> Result<(), HostError> {
+        self.check_contract_data_entry_size(key, entry, || {
+            let address =
+                self.add_host_object(ScAddress::Contract(contract_id.metered_clone(self)?))?;
+            Ok(address.into())
+        })
+    }
+
+    // Checks the size of the Wasm code about to be uploaded against the
+    // `LedgerEntrySizeLimits`, if these are set.
+    pub(crate) fn check_contract_code_size(&self, wasm: &[u8]) -> Result<(), HostError> {
+        let Some(limits) = self.try_borrow_storage()?.entry_size_limits() else {
+            return Ok(());
+        };
+        if wasm.len() > limits.max_contract_size_bytes as usize {
+            return Err(self.err(
+                ScErrorType::Storage,
+                ScErrorCode::ExceededLimit,
+                "contract code size exceeds the network limit",
+                &[
+                    (wasm.len() as u32).into(),
+                    limits.max_contract_size_bytes.into(),
+                ],
+            ));
+        }
+        Ok(())
+    }
+}
+
 #[cfg(any(test, feature = "testutils"))]
 use super::crypto;
 #[cfg(any(test, feature = "testutils"))]
@@ -548,8 +635,8 @@ impl Host {
     // footprint in enforcing mode.
     // "testutils" are not covered by budget metering.
     pub fn setup_storage_footprint(&self, footprint: Footprint) -> Result<(), HostError> {
-        for (key, access_type) in footprint.0.map {
-            self.setup_storage_entry(key, None, access_type)?;
+        for (key, access_type) in footprint.0.unmetered_iter() {
+            self.setup_storage_entry(Rc::clone(key), None, *access_type)?;
         }
         Ok(())
     }
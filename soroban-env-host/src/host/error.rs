@@ -1,6 +1,7 @@
 use crate::{
     budget::AsBudget,
     events::Events,
+    vm::ContractInvocationStack,
     xdr::{self, Hash, LedgerKey, ScAddress, ScError, ScErrorCode, ScErrorType},
     ConversionError, EnvBase, Error, Host, TryFromVal, U32Val, Val,
 };
@@ -19,6 +20,7 @@ use super::metered_clone::MeteredClone;
 #[derive(Clone)]
 pub(crate) struct DebugInfo {
     events: Events,
+    contract_invocation_stack: Option<ContractInvocationStack>,
     #[cfg(any(test, feature = "testutils"))]
     backtrace: Backtrace,
 }
@@ -61,6 +63,20 @@ impl DebugInfo {
         Ok(())
     }
 
+    fn write_contract_invocation_stack(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.contract_invocation_stack {
+            Some(stack) if !stack.is_empty() => {
+                writeln!(f)?;
+                writeln!(
+                    f,
+                    "Contract invocation stack (newest first, invoked functions only):"
+                )?;
+                write!(f, "{}", stack)
+            }
+            _ => Ok(()),
+        }
+    }
+
     #[cfg(not(any(test, feature = "testutils")))]
     fn write_backtrace(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         Ok(())
@@ -117,6 +133,7 @@ impl Debug for HostError {
         writeln!(f, "HostError: {:?}", self.error)?;
         if let Some(info) = &self.info {
             info.write_events(f)?;
+            info.write_contract_invocation_stack(f)?;
             info.write_backtrace(f)
         } else {
             writeln!(f, "DebugInfo not available")
@@ -139,6 +156,19 @@ impl HostError {
         }
     }
 
+    /// Returns the stack of the contract invocations captured when the error
+    /// has been caused by a contract trap (see [ContractInvocationStack]).
+    /// Only available in debug mode with `testutils`.
+    pub fn contract_invocation_stack(&self) -> Option<&ContractInvocationStack> {
+        self.info.as_ref()?.contract_invocation_stack.as_ref()
+    }
+
+    pub(crate) fn set_contract_invocation_stack(&mut self, stack: ContractInvocationStack) {
+        if let Some(info) = &mut self.info {
+            info.contract_invocation_stack = Some(stack);
+        }
+    }
+
     /// Identifies whether the error can be meaningfully recovered from.
     ///
     /// We consider errors that occur due to broken execution preconditions (
@@ -272,7 +302,11 @@ impl Host {
                 if let Ok(events_ref) = self.0.events.try_borrow() {
                     let events = events_ref.externalize(self)?;
                     let backtrace = Backtrace::new_unresolved();
-                    res = Some(Box::new(DebugInfo { backtrace, events }));
+                    res = Some(Box::new(DebugInfo {
+                        backtrace,
+                        events,
+                        contract_invocation_stack: None,
+                    }));
                 }
                 Ok(())
             });
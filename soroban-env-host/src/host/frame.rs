@@ -1,15 +1,17 @@
 use crate::{
     auth::AuthorizationManagerSnapshot,
-    budget::AsBudget,
+    budget::{AsBudget, CostFrame},
+    contract_spec::ContractSpec,
     err,
     host::{
         metered_clone::{MeteredClone, MeteredContainer, MeteredIterator},
         prng::Prng,
     },
     storage::{InstanceStorageMap, StorageMap},
+    vm::{ContractInvocationFrame, ContractInvocationStack, WasmNames},
     xdr::{
-        ContractExecutable, ContractIdPreimage, Hash, HostFunction, HostFunctionType, ScAddress,
-        ScContractInstance, ScErrorCode, ScErrorType, ScVal,
+        ContractExecutable, ContractIdPreimage, Hash, HostFunction, HostFunctionType,
+        LedgerEntryData, ScAddress, ScContractInstance, ScErrorCode, ScErrorType, ScVal,
     },
     AddressObject, Error, Host, HostError, Object, Symbol, SymbolStr, TryFromVal, TryIntoVal, Val,
     Vm, DEFAULT_HOST_DEPTH_LIMIT,
@@ -17,7 +19,10 @@ use crate::{
 
 #[cfg(any(test, feature = "testutils"))]
 use core::cell::RefCell;
-use std::rc::Rc;
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    rc::Rc,
+};
 
 /// Determines the re-entry mode for calling a contract.
 pub(crate) enum ContractReentryMode {
@@ -171,11 +176,41 @@ impl Host {
         };
         // Charge for the push, which might also run out of gas.
         Vec::<Context>::charge_bulk_init_cpy(1, self.as_budget())?;
+        let cost_frame = self.cost_frame(&ctx.frame)?;
         // Finally commit to doing the push.
         self.try_borrow_context_stack_mut()?.push(ctx);
+        if let Some(cost_frame) = cost_frame {
+            self.as_budget().enter_cost_frame(cost_frame)?;
+        }
         Ok(rp)
     }
 
+    // Returns the cost call tree label of `frame` when the tree is recorded.
+    // The label is built in the shadow mode so that recording the tree
+    // doesn't change the charges.
+    fn cost_frame(&self, frame: &Frame) -> Result<Option<CostFrame>, HostError> {
+        if !self.as_budget().is_cost_tree_enabled()? {
+            return Ok(None);
+        }
+        let mut cost_frame = CostFrame::default();
+        self.budget_ref().with_shadow_mode(|| {
+            let (contract_id, function) = match frame {
+                Frame::ContractVM { vm, fn_name, .. } => (Some(&vm.contract_id), *fn_name),
+                Frame::HostFunction(hf) => {
+                    cost_frame.function = hf.name().to_string();
+                    return Ok(());
+                }
+                Frame::StellarAssetContract(id, fn_name, ..) => (Some(id), *fn_name),
+                #[cfg(any(test, feature = "testutils"))]
+                Frame::TestContract(tc) => (Some(&tc.id), tc.func),
+            };
+            cost_frame.contract_id = contract_id.cloned();
+            cost_frame.function = SymbolStr::try_from_val(self, &function)?.to_string();
+            Ok(())
+        });
+        Ok(Some(cost_frame))
+    }
+
     /// Helper function for [`Host::with_frame`] below. Pops a [`Context`] off
     /// the current context stack and optionally rolls back the [`Host`]'s objects
     /// and storage map to the state in the provided [`RollbackPoint`].
@@ -183,6 +218,9 @@ impl Host {
         let _span = tracy_span!("pop context");
 
         let ctx = self.try_borrow_context_stack_mut()?.pop();
+        if ctx.is_some() {
+            self.as_budget().exit_cost_frame()?;
+        }
 
         #[cfg(any(test, feature = "recording_auth"))]
         if self.try_borrow_context_stack()?.is_empty() {
@@ -294,6 +332,68 @@ impl Host {
         }
     }
 
+    /// Returns the stack of the Wasm contract invocations on the context
+    /// stack, newest first. Only meant to be called in debug mode, when a
+    /// contract traps: this parses the function names of the contract code.
+    pub(crate) fn contract_invocation_stack(&self) -> Result<ContractInvocationStack, HostError> {
+        let vm_frames: Vec<(Hash, Option<Hash>, Symbol)> = self
+            .try_borrow_context_stack()?
+            .iter()
+            .rev()
+            .filter_map(|ctx| match &ctx.frame {
+                Frame::ContractVM {
+                    vm,
+                    fn_name,
+                    instance,
+                    ..
+                } => {
+                    let wasm_hash = match &instance.executable {
+                        ContractExecutable::Wasm(wasm_hash) => Some(wasm_hash.clone()),
+                        _ => None,
+                    };
+                    Some((vm.contract_id.clone(), wasm_hash, *fn_name))
+                }
+                _ => None,
+            })
+            .collect();
+        let mut names = BTreeMap::<Hash, Option<WasmNames>>::new();
+        let mut stack = ContractInvocationStack::default();
+        for (contract_id, wasm_hash, fn_name) in vm_frames {
+            let export = SymbolStr::try_from_val(self, &fn_name)?;
+            let names = match wasm_hash {
+                Some(wasm_hash) => match names.entry(wasm_hash) {
+                    Entry::Occupied(e) => e.into_mut().as_ref(),
+                    Entry::Vacant(e) => {
+                        let parsed = self.parse_wasm_names(e.key())?;
+                        e.insert(parsed).as_ref()
+                    }
+                },
+                None => None,
+            };
+            stack.frames.push(ContractInvocationFrame::new(
+                contract_id,
+                export.as_ref(),
+                names,
+            ));
+        }
+        Ok(stack)
+    }
+
+    // Parses the function names of the contract code that is already in the
+    // storage map. The storage map is accessed directly, in order to not
+    // affect the footprint and the storage access tracking.
+    fn parse_wasm_names(&self, wasm_hash: &Hash) -> Result<Option<WasmNames>, HostError> {
+        let key = self.contract_code_ledger_key(wasm_hash)?;
+        let storage = self.try_borrow_storage()?;
+        Ok(match storage.map.get(&key, self.as_budget())? {
+            Some(Some((entry, _))) => match &entry.data {
+                LedgerEntryData::ContractCode(code) => WasmNames::parse(code.code.as_slice()),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
     pub(crate) fn with_current_frame_relative_object_table<F, U>(
         &self,
         f: F,
@@ -614,6 +714,72 @@ impl Host {
         ))
     }
 
+    // Checks the call arguments against the contract spec, if enabled. Only
+    // the arguments of the top-level `InvokeContract` host function are
+    // checked: the contracts may pass arbitrary values to each other, and
+    // failing such calls would make the simulation diverge from the apply.
+    // The check happens in the shadow budget mode, as it must not change the
+    // simulated resource consumption; the spec may also be missing or
+    // malformed, in which case nothing is checked. The spec is only parsed
+    // on the first call of the contract code.
+    fn check_args_against_contract_spec(
+        &self,
+        vm: &Vm,
+        wasm_hash: &Hash,
+        func: &Symbol,
+        args: &[Val],
+    ) -> Result<(), HostError> {
+        if self.try_borrow_contract_specs()?.is_none() {
+            return Ok(());
+        }
+        let is_top_level_invocation = matches!(
+            self.try_borrow_context_stack()?.as_slice(),
+            [Context {
+                frame: Frame::HostFunction(HostFunctionType::InvokeContract),
+                ..
+            }]
+        );
+        if !is_top_level_invocation {
+            return Ok(());
+        }
+        let mut mismatch = None;
+        self.budget_ref().with_shadow_mode(|| {
+            let spec = {
+                let mut specs = self.try_borrow_contract_specs_mut()?;
+                let Some(specs) = specs.as_mut() else {
+                    return Ok(());
+                };
+                match specs.get(wasm_hash) {
+                    Some(spec) => spec.clone(),
+                    None => {
+                        let spec = ContractSpec::from_vm(vm).ok().flatten().map(Rc::new);
+                        specs.insert(wasm_hash.clone(), spec.clone());
+                        spec
+                    }
+                }
+            };
+            let Some(spec) = spec else {
+                return Ok(());
+            };
+            let func_name: SymbolStr = func.try_into_val(self)?;
+            let mut sc_args = Vec::with_capacity(args.len());
+            for arg in args {
+                sc_args.push(self.from_host_val(*arg)?);
+            }
+            mismatch = spec.check_args(func_name.as_ref(), &sc_args).err();
+            Ok(())
+        });
+        match mismatch {
+            Some(mismatch) => Err(self.err(
+                ScErrorType::Value,
+                ScErrorCode::UnexpectedType,
+                &mismatch.to_string(),
+                &[],
+            )),
+            None => Ok(()),
+        }
+    }
+
     // Notes on metering: this is covered by the called components.
     fn call_contract_fn(&self, id: &Hash, func: &Symbol, args: &[Val]) -> Result<Val, HostError> {
         // Create key for storage
@@ -626,7 +792,13 @@ impl Host {
         match &instance.executable {
             ContractExecutable::Wasm(wasm_hash) => {
                 let code_entry = self.retrieve_wasm_from_storage(&wasm_hash)?;
-                let vm = Vm::new(self, id.metered_clone(self)?, code_entry.as_slice())?;
+                let vm = Vm::new_with_module_cache(
+                    self,
+                    id.metered_clone(self)?,
+                    wasm_hash,
+                    code_entry.as_slice(),
+                )?;
+                self.check_args_against_contract_spec(&vm, wasm_hash, func, args)?;
                 let relative_objects = Vec::new();
                 self.with_frame(
                     Frame::ContractVM {
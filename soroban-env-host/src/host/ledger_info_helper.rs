@@ -3,21 +3,18 @@ use crate::{
     Error, Host, HostError, LedgerInfo,
 };
 
-impl Host {
-    pub(crate) fn get_min_live_until_ledger(
+impl LedgerInfo {
+    /// Returns the minimum live until ledger for a newly created entry with
+    /// the given durability (or for a restored persistent entry).
+    pub(crate) fn min_live_until_ledger(
         &self,
         storage_type: ContractDataDurability,
     ) -> Result<u32, HostError> {
-        let ledger_seq = self.with_ledger_info(|li| Ok(li.sequence_number))?;
         let min_live_until = match storage_type {
-            ContractDataDurability::Temporary => {
-                self.with_ledger_info(|li| Ok(li.min_temp_entry_ttl))?
-            }
-            ContractDataDurability::Persistent => {
-                self.with_ledger_info(|li: &LedgerInfo| Ok(li.min_persistent_entry_ttl))?
-            }
+            ContractDataDurability::Temporary => self.min_temp_entry_ttl,
+            ContractDataDurability::Persistent => self.min_persistent_entry_ttl,
         };
-        ledger_seq
+        self.sequence_number
             .checked_add(min_live_until.saturating_sub(1))
             .ok_or_else(|| {
                 // overflowing here means a misconfiguration of the network (the
@@ -31,23 +28,35 @@ impl Host {
             })
     }
 
+    /// Returns the maximum live until ledger that any entry may have.
+    pub(crate) fn max_live_until_ledger(&self) -> Result<u32, HostError> {
+        self.sequence_number
+            // Entry can live for at most max_entry_live_until ledgers from
+            // now, counting the current one.
+            .checked_add(self.max_entry_ttl.saturating_sub(1))
+            .ok_or_else(|| {
+                // overflowing here means a misconfiguration of the network
+                // (the ttl is too large), in which case we immediately flag
+                // it as an unrecoverable `InternalError`, even though the
+                // source is external to the host.
+                HostError::from(Error::from_type_and_code(
+                    ScErrorType::Context,
+                    ScErrorCode::InternalError,
+                ))
+            })
+    }
+}
+
+impl Host {
+    pub(crate) fn get_min_live_until_ledger(
+        &self,
+        storage_type: ContractDataDurability,
+    ) -> Result<u32, HostError> {
+        self.with_ledger_info(|li| li.min_live_until_ledger(storage_type))
+    }
+
     pub(crate) fn max_live_until_ledger(&self) -> Result<u32, HostError> {
-        self.with_ledger_info(|li| {
-            li.sequence_number
-                // Entry can live for at most max_entry_live_until ledgers from
-                // now, counting the current one.
-                .checked_add(li.max_entry_ttl.saturating_sub(1))
-                .ok_or_else(|| {
-                    // overflowing here means a misconfiguration of the network
-                    // (the ttl is too large), in which case we immediately flag
-                    // it as an unrecoverable `InternalError`, even though the
-                    // source is external to the host.
-                    HostError::from(Error::from_type_and_code(
-                        ScErrorType::Context,
-                        ScErrorCode::InternalError,
-                    ))
-                })
-        })
+        self.with_ledger_info(|li| li.max_live_until_ledger())
     }
 }
 
@@ -163,6 +163,7 @@ impl Host {
                 &[],
             )
         })?;
+        self.check_contract_code_size(wasm_bytes_m.as_slice())?;
 
         // Instantiate a temporary / throwaway VM using this wasm. This will do
         // both quick checks like "does this wasm have the right protocol number
@@ -177,7 +178,7 @@ impl Host {
             // native test contracts behave like wasm. They will never be
             // instantiated, this is just to exercise their storage logic.
         } else {
-            let _check_vm = Vm::new(
+            let _check_vm = Vm::new_for_upload(
                 self,
                 Hash(hash_bytes.metered_clone(self)?),
                 wasm_bytes_m.as_slice(),
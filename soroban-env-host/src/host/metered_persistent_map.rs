@@ -0,0 +1,528 @@
+use soroban_env_common::xdr::{ScErrorCode, ScErrorType};
+
+use super::{
+    declared_size::DeclaredSizeForMetering,
+    metered_clone::{charge_heap_alloc, charge_shallow_copy},
+    MeteredClone,
+};
+use crate::{budget::AsBudget, xdr::ContractCostType, Compare, Error, HostError};
+use std::{borrow::Borrow, cmp::Ordering, marker::PhantomData, rc::Rc};
+
+const MAP_OOB: Error = Error::from_type_and_code(ScErrorType::Object, ScErrorCode::IndexBounds);
+
+type Link<K, V> = Option<Rc<Node<K, V>>>;
+
+// Node of a persistent AVL tree. Nodes are immutable once built and are
+// shared between all the versions of the map that contain them.
+struct Node<K, V> {
+    entry: (K, V),
+    height: u32,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+impl<K, V> DeclaredSizeForMetering for Node<K, V>
+where
+    K: DeclaredSizeForMetering,
+    V: DeclaredSizeForMetering,
+{
+    // The entry, two links and the height with 4 bytes of alignment overhead.
+    const DECLARED_SIZE: u64 = <(K, V) as DeclaredSizeForMetering>::DECLARED_SIZE
+        .saturating_add(<Link<K, V> as DeclaredSizeForMetering>::DECLARED_SIZE.saturating_mul(2))
+        .saturating_add(8);
+}
+
+fn height<K, V>(link: &Link<K, V>) -> u32 {
+    link.as_ref().map_or(0, |node| node.height)
+}
+
+fn make_node<K, V>(entry: (K, V), left: Link<K, V>, right: Link<K, V>) -> Rc<Node<K, V>> {
+    let height = 1 + height(&left).max(height(&right));
+    Rc::new(Node {
+        entry,
+        height,
+        left,
+        right,
+    })
+}
+
+/// An ordered map with the same interface as
+/// [`MeteredOrdMap`](super::metered_map::MeteredOrdMap), but backed by a
+/// persistent (immutable and structurally shared) AVL tree instead of a sorted
+/// vector.
+///
+/// Cloning the map is O(1) and modifications only copy the O(log n) nodes on
+/// the path to the modified entry, while the remaining nodes are shared with
+/// the original map. This makes it suitable for the maps that are frequently
+/// snapshotted and modified one entry at a time, such as the storage map that
+/// is saved for rollback on every frame push.
+pub struct MeteredPersistentMap<K, V, Ctx> {
+    root: Link<K, V>,
+    len: usize,
+    ctx: PhantomData<Ctx>,
+}
+
+/// `Clone` only bumps the reference count of the root, so it is just as cheap
+/// as `MeteredClone`.
+impl<K, V, Ctx> Clone for MeteredPersistentMap<K, V, Ctx> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            len: self.len,
+            ctx: Default::default(),
+        }
+    }
+}
+
+impl<K, V, Ctx> Default for MeteredPersistentMap<K, V, Ctx> {
+    fn default() -> Self {
+        Self {
+            root: None,
+            len: 0,
+            ctx: Default::default(),
+        }
+    }
+}
+
+impl<K, V, Ctx> MeteredPersistentMap<K, V, Ctx>
+where
+    K: DeclaredSizeForMetering,
+    V: DeclaredSizeForMetering,
+    Ctx: AsBudget,
+{
+    const ENTRY_SIZE: u64 = <(K, V) as DeclaredSizeForMetering>::DECLARED_SIZE;
+
+    fn charge_access<B: AsBudget>(&self, count: usize, b: &B) -> Result<(), HostError> {
+        b.as_budget().charge(
+            ContractCostType::MemCpy,
+            Some(Self::ENTRY_SIZE.saturating_mul(count as u64)),
+        )
+    }
+
+    fn charge_scan<B: AsBudget>(&self, b: &B) -> Result<(), HostError> {
+        b.as_budget().charge(
+            ContractCostType::MemCpy,
+            Some(Self::ENTRY_SIZE.saturating_mul(self.len as u64)),
+        )
+    }
+
+    // Charge tree search includes accessing every entry on the path from the
+    // root to the leaf. Cost of comparison is charged separately and not
+    // covered here.
+    fn charge_search<B: AsBudget>(&self, b: &B) -> Result<(), HostError> {
+        b.as_budget().charge(
+            ContractCostType::MemCpy,
+            Some(Self::ENTRY_SIZE.saturating_mul(1 + height(&self.root) as u64)),
+        )
+    }
+
+    // Charge for allocating and copying the nodes on the path from the root
+    // to the leaf, plus the nodes that may be created by the rebalancing.
+    // Substructure of the copied entries is charged separately.
+    fn charge_path_copy<B: AsBudget>(&self, b: &B) -> Result<(), HostError> {
+        let count = 2 + height(&self.root) as u64;
+        charge_heap_alloc::<Node<K, V>>(count, b.as_budget())?;
+        charge_shallow_copy::<Node<K, V>>(count, b.as_budget())
+    }
+}
+
+// Same as for `MeteredOrdMap`, we abstract over Ctx:AsBudget here so that the
+// map can be populated and reused with only a Budget available.
+impl<K, V, Ctx> MeteredPersistentMap<K, V, Ctx>
+where
+    K: MeteredClone,
+    V: MeteredClone,
+    Ctx: AsBudget + Compare<K, Error = HostError>,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_map(map: Vec<(K, V)>, ctx: &Ctx) -> Result<Self, HostError> {
+        if u32::try_from(map.len()).is_err() {
+            return Err(MAP_OOB.into());
+        }
+        // Building the tree allocates a node per entry, while the entries
+        // themselves are moved. Checks that input has sorted and unique keys.
+        let mut m = Self::default();
+        m.len = map.len();
+        m.charge_scan(ctx)?;
+        for w in map.as_slice().windows(2) {
+            match <Ctx as Compare<K>>::compare(ctx, &w[0].0, &w[1].0)? {
+                Ordering::Less => (),
+                _ => return Err((ScErrorType::Object, ScErrorCode::InvalidInput).into()),
+            }
+        }
+        charge_heap_alloc::<Node<K, V>>(map.len() as u64, ctx.as_budget())?;
+        let mut iter = map.into_iter();
+        m.root = Self::build_balanced(&mut iter, m.len)?;
+        Ok(m)
+    }
+
+    // This mirrors `MeteredOrdMap::from_exact_iter` and only works with the
+    // iterators that report an exact size_hint.
+    pub fn from_exact_iter<I: Iterator<Item = (K, V)>>(
+        iter: I,
+        ctx: &Ctx,
+    ) -> Result<Self, HostError> {
+        let _span = tracy_span!("new persistent map");
+        if let (_, Some(sz)) = iter.size_hint() {
+            if u32::try_from(sz).is_err() {
+                Err(MAP_OOB.into())
+            } else {
+                let map: Vec<(K, V)> = iter.collect();
+                map.charge_deep_clone(ctx.as_budget())?;
+                // Delegate to from_map here to recheck sort order.
+                Self::from_map(map, ctx)
+            }
+        } else {
+            // This is a logic error, we should never get here.
+            Err((ScErrorType::Object, ScErrorCode::InternalError).into())
+        }
+    }
+
+    // Builds a perfectly balanced tree out of the next `len` entries of the
+    // sorted `iter`.
+    fn build_balanced<I: Iterator<Item = (K, V)>>(
+        iter: &mut I,
+        len: usize,
+    ) -> Result<Link<K, V>, HostError> {
+        if len == 0 {
+            return Ok(None);
+        }
+        let left_len = len / 2;
+        let left = Self::build_balanced(iter, left_len)?;
+        let entry = iter
+            .next()
+            .ok_or_else(|| HostError::from((ScErrorType::Object, ScErrorCode::InternalError)))?;
+        let right = Self::build_balanced(iter, len - left_len - 1)?;
+        Ok(Some(make_node(entry, left, right)))
+    }
+
+    // Clones the entry of a node that is being copied. The shallow copy of
+    // the node has already been charged by `charge_path_copy`.
+    fn clone_entry(entry: &(K, V), ctx: &Ctx) -> Result<(K, V), HostError> {
+        entry.charge_for_substructure(ctx.as_budget())?;
+        Ok(entry.clone())
+    }
+
+    // Builds a node out of `entry` and the subtrees, restoring the AVL
+    // invariant in case if the subtree heights differ by 2.
+    fn balance(
+        entry: (K, V),
+        left: Link<K, V>,
+        right: Link<K, V>,
+        ctx: &Ctx,
+    ) -> Result<Rc<Node<K, V>>, HostError> {
+        let (hl, hr) = (height(&left), height(&right));
+        if hl > hr + 1 {
+            if let Some(l) = &left {
+                if height(&l.left) >= height(&l.right) {
+                    let new_right = make_node(entry, l.right.clone(), right);
+                    return Ok(make_node(
+                        Self::clone_entry(&l.entry, ctx)?,
+                        l.left.clone(),
+                        Some(new_right),
+                    ));
+                }
+                if let Some(lr) = &l.right {
+                    let new_left = make_node(
+                        Self::clone_entry(&l.entry, ctx)?,
+                        l.left.clone(),
+                        lr.left.clone(),
+                    );
+                    let new_right = make_node(entry, lr.right.clone(), right);
+                    return Ok(make_node(
+                        Self::clone_entry(&lr.entry, ctx)?,
+                        Some(new_left),
+                        Some(new_right),
+                    ));
+                }
+            }
+        } else if hr > hl + 1 {
+            if let Some(r) = &right {
+                if height(&r.right) >= height(&r.left) {
+                    let new_left = make_node(entry, left, r.left.clone());
+                    return Ok(make_node(
+                        Self::clone_entry(&r.entry, ctx)?,
+                        Some(new_left),
+                        r.right.clone(),
+                    ));
+                }
+                if let Some(rl) = &r.left {
+                    let new_left = make_node(entry, left, rl.left.clone());
+                    let new_right = make_node(
+                        Self::clone_entry(&r.entry, ctx)?,
+                        rl.right.clone(),
+                        r.right.clone(),
+                    );
+                    return Ok(make_node(
+                        Self::clone_entry(&rl.entry, ctx)?,
+                        Some(new_left),
+                        Some(new_right),
+                    ));
+                }
+            }
+        }
+        Ok(make_node(entry, left, right))
+    }
+
+    fn find_node<Q>(&self, key: &Q, ctx: &Ctx) -> Result<Option<&Node<K, V>>, HostError>
+    where
+        K: Borrow<Q>,
+        Ctx: Compare<Q, Error = HostError>,
+    {
+        let _span = tracy_span!("persistent map lookup");
+        self.charge_search(ctx)?;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            match <Ctx as Compare<Q>>::compare(ctx, key, node.entry.0.borrow())? {
+                Ordering::Less => link = &node.left,
+                Ordering::Greater => link = &node.right,
+                Ordering::Equal => return Ok(Some(node.as_ref())),
+            }
+        }
+        Ok(None)
+    }
+
+    fn insert_rec(
+        link: &Link<K, V>,
+        key: K,
+        value: V,
+        ctx: &Ctx,
+    ) -> Result<(Rc<Node<K, V>>, bool), HostError> {
+        let Some(node) = link else {
+            return Ok((make_node((key, value), None, None), true));
+        };
+        match <Ctx as Compare<K>>::compare(ctx, &key, &node.entry.0)? {
+            Ordering::Less => {
+                let (left, added) = Self::insert_rec(&node.left, key, value, ctx)?;
+                let entry = Self::clone_entry(&node.entry, ctx)?;
+                Ok((
+                    Self::balance(entry, Some(left), node.right.clone(), ctx)?,
+                    added,
+                ))
+            }
+            Ordering::Greater => {
+                let (right, added) = Self::insert_rec(&node.right, key, value, ctx)?;
+                let entry = Self::clone_entry(&node.entry, ctx)?;
+                Ok((
+                    Self::balance(entry, node.left.clone(), Some(right), ctx)?,
+                    added,
+                ))
+            }
+            Ordering::Equal => Ok((
+                make_node((key, value), node.left.clone(), node.right.clone()),
+                false,
+            )),
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V, ctx: &Ctx) -> Result<Self, HostError> {
+        if self.len == u32::MAX as usize {
+            return Err(MAP_OOB.into());
+        }
+        self.charge_search(ctx)?;
+        self.charge_path_copy(ctx)?;
+        let (root, added) = Self::insert_rec(&self.root, key, value, ctx)?;
+        Ok(Self {
+            root: Some(root),
+            len: if added { self.len + 1 } else { self.len },
+            ctx: Default::default(),
+        })
+    }
+
+    pub fn get<Q>(&self, key: &Q, ctx: &Ctx) -> Result<Option<&V>, HostError>
+    where
+        K: Borrow<Q>,
+        Ctx: Compare<Q, Error = HostError>,
+    {
+        match self.find_node(key, ctx)? {
+            Some(node) => {
+                self.charge_access(1, ctx)?;
+                Ok(Some(&node.entry.1))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Removes the minimal entry of the subtree, returning the new subtree and
+    // the removed entry.
+    fn remove_min(node: &Rc<Node<K, V>>, ctx: &Ctx) -> Result<(Link<K, V>, (K, V)), HostError> {
+        match &node.left {
+            None => Ok((node.right.clone(), Self::clone_entry(&node.entry, ctx)?)),
+            Some(left) => {
+                let (new_left, min) = Self::remove_min(left, ctx)?;
+                let entry = Self::clone_entry(&node.entry, ctx)?;
+                let new_node = Self::balance(entry, new_left, node.right.clone(), ctx)?;
+                Ok((Some(new_node), min))
+            }
+        }
+    }
+
+    fn remove_rec<Q>(
+        link: &Link<K, V>,
+        key: &Q,
+        ctx: &Ctx,
+    ) -> Result<Option<(Link<K, V>, V)>, HostError>
+    where
+        K: Borrow<Q>,
+        Ctx: Compare<Q, Error = HostError>,
+    {
+        let Some(node) = link else {
+            return Ok(None);
+        };
+        match <Ctx as Compare<Q>>::compare(ctx, key, node.entry.0.borrow())? {
+            Ordering::Less => {
+                let Some((left, value)) = Self::remove_rec(&node.left, key, ctx)? else {
+                    return Ok(None);
+                };
+                let entry = Self::clone_entry(&node.entry, ctx)?;
+                let new_node = Self::balance(entry, left, node.right.clone(), ctx)?;
+                Ok(Some((Some(new_node), value)))
+            }
+            Ordering::Greater => {
+                let Some((right, value)) = Self::remove_rec(&node.right, key, ctx)? else {
+                    return Ok(None);
+                };
+                let entry = Self::clone_entry(&node.entry, ctx)?;
+                let new_node = Self::balance(entry, node.left.clone(), right, ctx)?;
+                Ok(Some((Some(new_node), value)))
+            }
+            Ordering::Equal => {
+                let value = node.entry.1.metered_clone(ctx.as_budget())?;
+                let new_link = match (&node.left, &node.right) {
+                    (None, right) => right.clone(),
+                    (left, None) => left.clone(),
+                    (left, Some(right)) => {
+                        let (new_right, min) = Self::remove_min(right, ctx)?;
+                        Some(Self::balance(min, left.clone(), new_right, ctx)?)
+                    }
+                };
+                Ok(Some((new_link, value)))
+            }
+        }
+    }
+
+    /// Returns a `Some((new_self, val))` pair where `new_self` no longer
+    /// contains an entry for `key`, if the key existed, otherwise `None` if
+    /// `key` didn't exist.
+    pub fn remove<Q>(&self, key: &Q, ctx: &Ctx) -> Result<Option<(Self, V)>, HostError>
+    where
+        K: Borrow<Q>,
+        Ctx: Compare<Q, Error = HostError>,
+    {
+        self.charge_search(ctx)?;
+        self.charge_path_copy(ctx)?;
+        Ok(
+            Self::remove_rec(&self.root, key, ctx)?.map(|(root, value)| {
+                let new = Self {
+                    root,
+                    len: self.len - 1,
+                    ctx: Default::default(),
+                };
+                (new, value)
+            }),
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q, ctx: &Ctx) -> Result<bool, HostError>
+    where
+        K: Borrow<Q>,
+        Ctx: Compare<Q, Error = HostError>,
+    {
+        Ok(self.find_node(key, ctx)?.is_some())
+    }
+
+    pub fn keys(&self, ctx: &Ctx) -> Result<impl Iterator<Item = &K>, HostError> {
+        self.charge_scan(ctx)?;
+        Ok(self.unmetered_iter().map(|(k, _)| k))
+    }
+
+    pub fn values(&self, ctx: &Ctx) -> Result<impl Iterator<Item = &V>, HostError> {
+        self.charge_scan(ctx)?;
+        Ok(self.unmetered_iter().map(|(_, v)| v))
+    }
+
+    pub fn iter(&self, ctx: &Ctx) -> Result<Iter<'_, K, V>, HostError> {
+        self.charge_scan(ctx)?;
+        Ok(self.unmetered_iter())
+    }
+}
+
+impl<K, V, Ctx> MeteredPersistentMap<K, V, Ctx> {
+    /// Iterates over the entries in key order without charging the budget.
+    /// Only meant for the code paths that are not metered anyway, such as
+    /// diagnostics and test utilities.
+    pub(crate) fn unmetered_iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            remaining: self.len,
+        };
+        iter.push_left_spine(&self.root);
+        iter
+    }
+}
+
+/// In-order iterator over the entries of [`MeteredPersistentMap`].
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left_spine(&mut self, mut link: &'a Link<K, V>) {
+        while let Some(node) = link {
+            self.stack.push(node.as_ref());
+            link = &node.left;
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = &'a (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left_spine(&node.right);
+        self.remaining = self.remaining.saturating_sub(1);
+        Some(&node.entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<K, V, Ctx> DeclaredSizeForMetering for MeteredPersistentMap<K, V, Ctx> {
+    // The root link and the length.
+    const DECLARED_SIZE: u64 = 32;
+}
+
+// Cloning only bumps the reference count of the root node, so the map is
+// shallow for the metering purposes.
+impl<K, V, Ctx> MeteredClone for MeteredPersistentMap<K, V, Ctx> {}
+
+// Hashes the same way as a `Vec` of the entries, so that the hash doesn't
+// depend on the shape of the tree.
+#[cfg(feature = "testutils")]
+impl<K: std::hash::Hash, V: std::hash::Hash, Ctx> std::hash::Hash
+    for MeteredPersistentMap<K, V, Ctx>
+{
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len);
+        for entry in self.unmetered_iter() {
+            entry.hash(state);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use super::{
+    declared_size::DeclaredSizeForMetering,
+    metered_map::MeteredOrdMap,
+    metered_persistent_map::{self, MeteredPersistentMap},
+    MeteredClone,
+};
+use crate::{budget::AsBudget, Compare, HostError};
+use std::borrow::Borrow;
+
+/// The first ledger protocol version that backs the [Storage](crate::storage::Storage)
+/// and [Footprint](crate::storage::Footprint) maps with a [MeteredPersistentMap].
+/// Earlier protocols keep using a [MeteredOrdMap], as the two are metered
+/// differently and the cost of an operation must not change within a
+/// protocol.
+pub const PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION: u32 = 21;
+
+/// Ordered map of the ledger storage, represented as a [MeteredOrdMap] or a
+/// [MeteredPersistentMap] depending on the ledger protocol version (see
+/// [PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION]).
+///
+/// Maps built before the protocol version is known use the [MeteredOrdMap]
+/// representation and get converted with [MeteredStorageMap::for_protocol]
+/// (the [Host](crate::Host) does this for its storage whenever the ledger
+/// info is set). All the operations are metered by the underlying map.
+pub enum MeteredStorageMap<K, V, Ctx> {
+    Ord(MeteredOrdMap<K, V, Ctx>),
+    Persistent(MeteredPersistentMap<K, V, Ctx>),
+}
+
+/// `Clone` should not be used directly, used `MeteredClone` instead if possible. It is defined to
+/// satisfy trait requirements.
+impl<K, V, Ctx> Clone for MeteredStorageMap<K, V, Ctx>
+where
+    K: MeteredClone,
+    V: MeteredClone,
+    Ctx: AsBudget,
+{
+    fn clone(&self) -> Self {
+        match self {
+            Self::Ord(m) => Self::Ord(m.clone()),
+            Self::Persistent(m) => Self::Persistent(m.clone()),
+        }
+    }
+}
+
+impl<K, V, Ctx> Default for MeteredStorageMap<K, V, Ctx>
+where
+    Ctx: Default,
+{
+    fn default() -> Self {
+        Self::Ord(Default::default())
+    }
+}
+
+impl<K, V, Ctx> MeteredStorageMap<K, V, Ctx>
+where
+    K: MeteredClone,
+    V: MeteredClone,
+    Ctx: AsBudget + Compare<K, Error = HostError>,
+{
+    pub fn new() -> Self {
+        Self::Ord(MeteredOrdMap::new())
+    }
+
+    /// Creates an empty map in the representation used by the given ledger
+    /// protocol version.
+    pub fn new_for_protocol(protocol_version: u32) -> Self {
+        if protocol_version >= PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION {
+            Self::Persistent(MeteredPersistentMap::new())
+        } else {
+            Self::new()
+        }
+    }
+
+    pub fn from_map(map: Vec<(K, V)>, ctx: &Ctx) -> Result<Self, HostError> {
+        Ok(Self::Ord(MeteredOrdMap::from_map(map, ctx)?))
+    }
+
+    pub fn from_exact_iter<I: Iterator<Item = (K, V)>>(
+        iter: I,
+        ctx: &Ctx,
+    ) -> Result<Self, HostError> {
+        Ok(Self::Ord(MeteredOrdMap::from_exact_iter(iter, ctx)?))
+    }
+
+    /// Converts the map into the representation used by the given ledger
+    /// protocol version. The conversion is metered as building the new map
+    /// from the entries, and is free when the representation already matches.
+    pub fn for_protocol(self, protocol_version: u32, ctx: &Ctx) -> Result<Self, HostError> {
+        let persistent = protocol_version >= PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION;
+        match self {
+            Self::Ord(m) if persistent => Ok(Self::Persistent(MeteredPersistentMap::from_map(
+                m.map, ctx,
+            )?)),
+            Self::Persistent(m) if !persistent => Ok(Self::Ord(MeteredOrdMap::from_exact_iter(
+                m.unmetered_iter().cloned(),
+                ctx,
+            )?)),
+            m => Ok(m),
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V, ctx: &Ctx) -> Result<Self, HostError> {
+        match self {
+            Self::Ord(m) => Ok(Self::Ord(m.insert(key, value, ctx)?)),
+            Self::Persistent(m) => Ok(Self::Persistent(m.insert(key, value, ctx)?)),
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q, ctx: &Ctx) -> Result<Option<&V>, HostError>
+    where
+        K: Borrow<Q>,
+        Ctx: Compare<Q, Error = HostError>,
+    {
+        match self {
+            Self::Ord(m) => m.get(key, ctx),
+            Self::Persistent(m) => m.get(key, ctx),
+        }
+    }
+
+    pub fn remove<Q>(&self, key: &Q, ctx: &Ctx) -> Result<Option<(Self, V)>, HostError>
+    where
+        K: Borrow<Q>,
+        Ctx: Compare<Q, Error = HostError>,
+    {
+        match self {
+            Self::Ord(m) => Ok(m.remove(key, ctx)?.map(|(m, v)| (Self::Ord(m), v))),
+            Self::Persistent(m) => Ok(m.remove(key, ctx)?.map(|(m, v)| (Self::Persistent(m), v))),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Ord(m) => m.len(),
+            Self::Persistent(m) => m.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q, ctx: &Ctx) -> Result<bool, HostError>
+    where
+        K: Borrow<Q>,
+        Ctx: Compare<Q, Error = HostError>,
+    {
+        match self {
+            Self::Ord(m) => m.contains_key(key, ctx),
+            Self::Persistent(m) => m.contains_key(key, ctx),
+        }
+    }
+
+    pub fn keys(&self, ctx: &Ctx) -> Result<impl Iterator<Item = &K>, HostError> {
+        Ok(self.iter(ctx)?.map(|(k, _)| k))
+    }
+
+    pub fn values(&self, ctx: &Ctx) -> Result<impl Iterator<Item = &V>, HostError> {
+        Ok(self.iter(ctx)?.map(|(_, v)| v))
+    }
+
+    pub fn iter(&self, ctx: &Ctx) -> Result<Iter<'_, K, V>, HostError> {
+        match self {
+            Self::Ord(m) => Ok(Iter::Ord(m.iter(ctx)?)),
+            Self::Persistent(m) => Ok(Iter::Persistent(m.iter(ctx)?)),
+        }
+    }
+}
+
+impl<K, V, Ctx> MeteredStorageMap<K, V, Ctx> {
+    /// Iterates over the entries in key order without charging the budget.
+    /// Only meant for the code paths that are not metered anyway, such as
+    /// diagnostics and test utilities.
+    pub(crate) fn unmetered_iter(&self) -> Iter<'_, K, V> {
+        match self {
+            Self::Ord(m) => Iter::Ord(m.map.iter()),
+            Self::Persistent(m) => Iter::Persistent(m.unmetered_iter()),
+        }
+    }
+}
+
+/// In-order iterator over the entries of [`MeteredStorageMap`].
+pub enum Iter<'a, K, V> {
+    Ord(core::slice::Iter<'a, (K, V)>),
+    Persistent(metered_persistent_map::Iter<'a, K, V>),
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = &'a (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Ord(it) => it.next(),
+            Iter::Persistent(it) => it.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Iter::Ord(it) => it.size_hint(),
+            Iter::Persistent(it) => it.size_hint(),
+        }
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<K, V, Ctx> DeclaredSizeForMetering for MeteredStorageMap<K, V, Ctx>
+where
+    K: DeclaredSizeForMetering,
+    V: DeclaredSizeForMetering,
+{
+    // Matches the declared size of `MeteredOrdMap`, so that copying the map
+    // is charged the same as before the persistent representation existed.
+    // The persistent representation is smaller.
+    const DECLARED_SIZE: u64 = <MeteredOrdMap<K, V, Ctx> as DeclaredSizeForMetering>::DECLARED_SIZE;
+}
+
+impl<K, V, Ctx> MeteredClone for MeteredStorageMap<K, V, Ctx>
+where
+    K: MeteredClone,
+    V: MeteredClone,
+    Ctx: AsBudget,
+{
+    fn charge_for_substructure(&self, budget: impl AsBudget) -> Result<(), HostError> {
+        match self {
+            Self::Ord(m) => m.charge_for_substructure(budget),
+            Self::Persistent(m) => m.charge_for_substructure(budget),
+        }
+    }
+}
+
+// Hashes the same way as a `Vec` of the entries, so that the hash doesn't
+// depend on the representation.
+#[cfg(feature = "testutils")]
+impl<K: std::hash::Hash, V: std::hash::Hash, Ctx> std::hash::Hash for MeteredStorageMap<K, V, Ctx> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        let iter = self.unmetered_iter();
+        state.write_usize(iter.len());
+        for entry in iter {
+            entry.hash(state);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+/// This module contains functionality for capturing all the inputs (and
+/// optionally the outputs) of `e2e_invoke::invoke_host_function` into a single
+/// self-describing bundle and deterministically replaying it.
+///
+/// Bundles are meant to be used for reproducing issues observed in embedder
+/// environments: the embedder captures the invocation, writes the bundle into
+/// a file, and the file can then be replayed against any version of the host
+/// (e.g. with the `soroban-env-host` command-line tool) in order to check
+/// whether the results are byte-identical.
+///
+/// With the `serde` feature enabled, bundles are written as JSON objects with
+/// a `format` field and the fields of `InvocationBundle`. Binary values are
+/// hex-encoded XDR.
+#[cfg(feature = "serde")]
+use std::io;
+
+use crate::{
+    budget::Budget,
+    e2e_invoke::{invoke_host_function, InvokeHostFunctionResult, LedgerEntryChange},
+    xdr::{ContractCostParams, DiagnosticEvent, ScError, ScErrorCode},
+    HostError, LedgerInfo, VERSION,
+};
+
+#[cfg(feature = "serde")]
+const BUNDLE_FORMAT: &str = "soroban-invocation-bundle v2";
+
+/// Budget configuration used for the invocation. This normally comes from the
+/// network configuration settings.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BudgetConfig {
+    pub cpu_limit: u64,
+    pub mem_limit: u64,
+    pub cpu_cost_params: ContractCostParams,
+    pub mem_cost_params: ContractCostParams,
+}
+
+impl BudgetConfig {
+    /// Builds a fresh budget from this configuration.
+    pub fn to_budget(&self) -> Result<Budget, HostError> {
+        Budget::try_from_configs(
+            self.cpu_limit,
+            self.mem_limit,
+            self.cpu_cost_params.clone(),
+            self.mem_cost_params.clone(),
+        )
+    }
+}
+
+/// Observable outcome of `invoke_host_function` that has to be reproduced
+/// byte-for-byte during replay.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InvocationOutcome {
+    /// Result value of the function encoded as `ScVal` XDR, or the error
+    /// that the invocation has failed with.
+    #[cfg_attr(feature = "serde", serde(with = "serde_invoke_result"))]
+    pub encoded_invoke_result: Result<Vec<u8>, ScError>,
+    pub ledger_changes: Vec<LedgerEntryChange>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::vec_bytes"))]
+    pub encoded_contract_events: Vec<Vec<u8>>,
+    /// Diagnostic events emitted by the invocation (when the diagnostics are
+    /// enabled). These carry the details of the errors beyond their
+    /// `ScError`.
+    pub diagnostic_events: Vec<DiagnosticEvent>,
+}
+
+impl InvocationOutcome {
+    pub fn from_invoke_result(
+        res: &Result<InvokeHostFunctionResult, HostError>,
+        diagnostic_events: &[DiagnosticEvent],
+    ) -> Self {
+        match res {
+            Ok(res) => Self {
+                encoded_invoke_result: res
+                    .encoded_invoke_result
+                    .as_ref()
+                    .cloned()
+                    .map_err(host_error_to_sc_error),
+                ledger_changes: res.ledger_changes.clone(),
+                encoded_contract_events: res.encoded_contract_events.clone(),
+                diagnostic_events: diagnostic_events.to_vec(),
+            },
+            Err(e) => Self {
+                encoded_invoke_result: Err(host_error_to_sc_error(e)),
+                ledger_changes: vec![],
+                encoded_contract_events: vec![],
+                diagnostic_events: diagnostic_events.to_vec(),
+            },
+        }
+    }
+}
+
+/// All the inputs of a single `invoke_host_function` call, the version of the
+/// host that has produced the bundle and, optionally, the invocation outcome.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InvocationBundle {
+    /// Version of the host crate that has captured this bundle.
+    pub host_version: String,
+    /// Git revision of the host crate that has captured this bundle.
+    pub host_revision: String,
+    pub enable_diagnostics: bool,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::bytes"))]
+    pub encoded_host_fn: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::bytes"))]
+    pub encoded_resources: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::bytes"))]
+    pub encoded_source_account: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::vec_bytes"))]
+    pub encoded_auth_entries: Vec<Vec<u8>>,
+    pub ledger_info: LedgerInfo,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::vec_bytes"))]
+    pub encoded_ledger_entries: Vec<Vec<u8>>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::vec_bytes"))]
+    pub encoded_ttl_entries: Vec<Vec<u8>>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex::bytes"))]
+    pub base_prng_seed: Vec<u8>,
+    pub budget_config: BudgetConfig,
+    /// Outcome of the original invocation, if it has been recorded.
+    pub outcome: Option<InvocationOutcome>,
+}
+
+/// Result of replaying an `InvocationBundle`.
+pub struct ReplayReport {
+    /// Outcome of the replayed invocation.
+    pub outcome: InvocationOutcome,
+    /// Human-readable descriptions of the differences between the recorded
+    /// and replayed outcomes. Empty when the outcomes are byte-identical.
+    pub mismatches: Vec<String>,
+    /// Whether the bundle has been captured by a different version of the
+    /// host than the one that has replayed it.
+    pub host_version_differs: bool,
+}
+
+impl ReplayReport {
+    pub fn is_identical(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl InvocationBundle {
+    /// Captures the inputs of `invoke_host_function` stamped with the current
+    /// host version. The outcome can then be recorded via `invoke_and_record`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        enable_diagnostics: bool,
+        encoded_host_fn: Vec<u8>,
+        encoded_resources: Vec<u8>,
+        encoded_source_account: Vec<u8>,
+        encoded_auth_entries: Vec<Vec<u8>>,
+        ledger_info: LedgerInfo,
+        encoded_ledger_entries: Vec<Vec<u8>>,
+        encoded_ttl_entries: Vec<Vec<u8>>,
+        base_prng_seed: Vec<u8>,
+        budget_config: BudgetConfig,
+    ) -> Self {
+        Self {
+            host_version: VERSION.pkg.to_string(),
+            host_revision: VERSION.rev.to_string(),
+            enable_diagnostics,
+            encoded_host_fn,
+            encoded_resources,
+            encoded_source_account,
+            encoded_auth_entries,
+            ledger_info,
+            encoded_ledger_entries,
+            encoded_ttl_entries,
+            base_prng_seed,
+            budget_config,
+            outcome: None,
+        }
+    }
+
+    /// Runs `invoke_host_function` with the bundle inputs within a fresh
+    /// budget built from the bundle budget configuration.
+    pub fn invoke(
+        &self,
+        diagnostic_events: &mut Vec<DiagnosticEvent>,
+    ) -> Result<InvokeHostFunctionResult, HostError> {
+        self.invoke_with_budget(&self.budget_config.to_budget()?, diagnostic_events)
+    }
+
+    /// Runs `invoke_host_function` with the bundle inputs within the provided
+    /// budget, e.g. in order to inspect the budget afterwards.
+    pub fn invoke_with_budget(
+        &self,
+        budget: &Budget,
+        diagnostic_events: &mut Vec<DiagnosticEvent>,
+    ) -> Result<InvokeHostFunctionResult, HostError> {
+        invoke_host_function(
+            budget,
+            self.enable_diagnostics,
+            &self.encoded_host_fn,
+            &self.encoded_resources,
+            &self.encoded_source_account,
+            self.encoded_auth_entries.iter(),
+            self.ledger_info.clone(),
+            self.encoded_ledger_entries.iter(),
+            self.encoded_ttl_entries.iter(),
+            &self.base_prng_seed,
+            diagnostic_events,
+        )
+    }
+
+    /// Runs the invocation and records its outcome in the bundle.
+    pub fn invoke_and_record(
+        &mut self,
+        diagnostic_events: &mut Vec<DiagnosticEvent>,
+    ) -> Result<InvokeHostFunctionResult, HostError> {
+        let events_start = diagnostic_events.len();
+        let res = self.invoke(diagnostic_events);
+        self.outcome = Some(InvocationOutcome::from_invoke_result(
+            &res,
+            &diagnostic_events[events_start..],
+        ));
+        res
+    }
+
+    /// Replays the invocation and compares its outcome to the recorded one.
+    ///
+    /// Bundles without a recorded outcome are reported as mismatching.
+    pub fn replay(&self) -> ReplayReport {
+        let mut diagnostic_events = vec![];
+        let res = self.invoke(&mut diagnostic_events);
+        self.compare_outcome(InvocationOutcome::from_invoke_result(
+            &res,
+            &diagnostic_events,
+        ))
+    }
+
+    /// Compares the outcome of a replayed invocation to the recorded one.
+    pub fn compare_outcome(&self, outcome: InvocationOutcome) -> ReplayReport {
+        let mut mismatches = vec![];
+        match &self.outcome {
+            None => mismatches.push("bundle has no recorded outcome".to_string()),
+            Some(expected) => {
+                if expected.encoded_invoke_result != outcome.encoded_invoke_result {
+                    mismatches.push(format!(
+                        "invocation result differs: expected {:?}, got {:?}",
+                        expected.encoded_invoke_result, outcome.encoded_invoke_result
+                    ));
+                }
+                if expected.ledger_changes.len() != outcome.ledger_changes.len() {
+                    mismatches.push(format!(
+                        "number of ledger changes differs: expected {}, got {}",
+                        expected.ledger_changes.len(),
+                        outcome.ledger_changes.len()
+                    ));
+                }
+                for (i, (expected_change, change)) in expected
+                    .ledger_changes
+                    .iter()
+                    .zip(outcome.ledger_changes.iter())
+                    .enumerate()
+                {
+                    if expected_change != change {
+                        mismatches.push(format!(
+                            "ledger change {i} differs: expected {expected_change:?}, got {change:?}"
+                        ));
+                    }
+                }
+                if expected.encoded_contract_events != outcome.encoded_contract_events {
+                    mismatches.push("contract events differ".to_string());
+                }
+                if expected.diagnostic_events != outcome.diagnostic_events {
+                    mismatches.push("diagnostic events differ".to_string());
+                }
+            }
+        }
+        ReplayReport {
+            outcome,
+            mismatches,
+            host_version_differs: self.host_version != VERSION.pkg
+                || self.host_revision != VERSION.rev,
+        }
+    }
+
+    /// Writes the bundle as JSON, see the module docs.
+    #[cfg(feature = "serde")]
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        serde_json::to_writer_pretty(
+            &mut *w,
+            &BundleFileRef {
+                format: BUNDLE_FORMAT,
+                bundle: self,
+            },
+        )?;
+        writeln!(w)
+    }
+
+    /// Reads the bundle written by `write_to`.
+    #[cfg(feature = "serde")]
+    pub fn read_from<R: io::Read>(r: R) -> io::Result<Self> {
+        let file: BundleFile = serde_json::from_reader(r)?;
+        if file.format != BUNDLE_FORMAT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported invocation bundle format '{}'", file.format),
+            ));
+        }
+        Ok(file.bundle)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct BundleFileRef<'a> {
+    format: &'a str,
+    #[serde(flatten)]
+    bundle: &'a InvocationBundle,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct BundleFile {
+    format: String,
+    #[serde(flatten)]
+    bundle: InvocationBundle,
+}
+
+fn host_error_to_sc_error(e: &HostError) -> ScError {
+    ScError::try_from(e).unwrap_or(ScError::Context(ScErrorCode::InternalError))
+}
+
+#[cfg(feature = "serde")]
+mod serde_invoke_result {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::{e2e_invoke::EncodedInvokeResult, xdr::ScError};
+
+    pub(super) fn serialize<S: Serializer>(
+        res: &Result<Vec<u8>, ScError>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        match res {
+            Ok(v) => EncodedInvokeResult::Ok(v.into()),
+            Err(e) => EncodedInvokeResult::Error(e.clone()),
+        }
+        .serialize(s)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Result<Vec<u8>, ScError>, D::Error> {
+        Ok(match EncodedInvokeResult::deserialize(d)? {
+            EncodedInvokeResult::Ok(v) => Ok(v.into_owned()),
+            EncodedInvokeResult::Error(e) => Err(e),
+        })
+    }
+}
@@ -33,17 +33,27 @@ mod builtin_contracts;
 
 pub mod auth;
 pub mod vm;
-pub use vm::Vm;
+pub use vm::{ModuleCache, Vm, VM_CACHED_INSTANTIATION_PROTOCOL_VERSION};
 pub mod storage;
 pub use budget::{DEFAULT_HOST_DEPTH_LIMIT, DEFAULT_XDR_RW_LIMITS};
 pub use host::{
-    metered_map::MeteredOrdMap, metered_vector::MeteredVector, Host, HostError, LedgerInfo, Seed,
-    SEED_BYTES,
+    metered_map::MeteredOrdMap,
+    metered_persistent_map::MeteredPersistentMap,
+    metered_storage_map::{MeteredStorageMap, PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION},
+    metered_vector::MeteredVector,
+    Host, HostError, LedgerInfo, Seed, SEED_BYTES,
 };
 pub use soroban_env_common::*;
 
+#[cfg(feature = "serde")]
+mod serde_hex;
+
+pub mod contract_spec;
 pub mod e2e_invoke;
 pub mod fees;
+pub mod invocation_bundle;
+#[cfg(feature = "file_snapshot")]
+pub mod snapshot_source;
 
 #[cfg(feature = "bench")]
 #[doc(hidden)]
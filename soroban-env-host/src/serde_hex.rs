@@ -0,0 +1,57 @@
+//! Helpers for encoding binary fields of the embedder-facing types as hex
+//! strings, for use with `#[serde(with = "...")]`.
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+fn from_hex<E: Error>(s: &str) -> Result<Vec<u8>, E> {
+    hex::decode(s).map_err(|_| E::custom("malformed hex string"))
+}
+
+/// Encodes a byte buffer (or a fixed-size byte array) as a hex string.
+pub(crate) mod bytes {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer, T: AsRef<[u8]>>(v: &T, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&hex::encode(v))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>, T: TryFrom<Vec<u8>>>(
+        d: D,
+    ) -> Result<T, D::Error> {
+        let v = from_hex(&String::deserialize(d)?)?;
+        T::try_from(v).map_err(|_| D::Error::custom("unexpected number of bytes"))
+    }
+}
+
+/// Encodes an optional byte buffer as a hex string or `null`.
+pub(crate) mod option_bytes {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(v: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        v.as_ref().map(hex::encode).serialize(s)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|s| from_hex(&s))
+            .transpose()
+    }
+}
+
+/// Encodes a sequence of byte buffers as an array of hex strings.
+pub(crate) mod vec_bytes {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(v: &[Vec<u8>], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(v.iter().map(hex::encode))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(d)?
+            .iter()
+            .map(|s| from_hex(s))
+            .collect()
+    }
+}
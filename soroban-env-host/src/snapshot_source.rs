@@ -0,0 +1,219 @@
+/// This module contains a `SnapshotSource` implementation that is suitable
+/// for running the host outside of the core: a snapshot loaded from files
+/// containing captured ledger entries. Pending writes can be overlaid on top
+/// of it (or any other snapshot) with `e2e_invoke::LedgerView`.
+///
+/// The snapshot files contain `LedgerEntry` XDR of the entries themselves and
+/// of their TTL entries (i.e. `LedgerEntry` with `LedgerEntryData::Ttl`). TTL
+/// entries are matched to the entries they belong to by key hash, so they
+/// may appear anywhere, including a different file. Two file formats are
+/// supported:
+///
+/// - Base64 lines: every non-empty line that doesn't start with `#` is a
+///   base64-encoded `LedgerEntry` XDR.
+/// - JSON: a single array, where every element is either a base64-encoded
+///   `LedgerEntry` XDR string, or a `LedgerEntry` in the XDR JSON
+///   representation.
+///
+/// The format is detected by the first non-whitespace character of the file
+/// (`[` for JSON).
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    budget::Budget,
+    e2e_invoke::ledger_entry_to_ledger_key,
+    storage::{EntryWithLiveUntil, SnapshotSource},
+    xdr::{
+        Hash, LedgerEntry, LedgerEntryData, LedgerEntryExt, LedgerKey, ReadXdr, ScErrorCode,
+        ScErrorType, TtlEntry, WriteXdr,
+    },
+    Error, HostError, DEFAULT_XDR_RW_LIMITS,
+};
+
+/// Immutable snapshot of ledger entries loaded from files.
+///
+/// When the same entry is present more than once, the last occurrence wins.
+/// Directories are read in the lexicographic order of the file names, which
+/// makes it possible to store the incremental updates of a captured ledger
+/// next to it.
+#[derive(Clone, Default)]
+pub struct FileSnapshotSource {
+    entries: BTreeMap<Rc<LedgerKey>, EntryWithLiveUntil>,
+}
+
+impl FileSnapshotSource {
+    /// Loads the snapshot from a single file, or from all the files in a
+    /// directory (non-recursively, skipping the hidden files).
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut loader = SnapshotLoader::default();
+        if path.is_dir() {
+            let mut file_paths = vec![];
+            for dir_entry in fs::read_dir(path)? {
+                let dir_entry = dir_entry?;
+                let is_hidden = dir_entry.file_name().to_string_lossy().starts_with('.');
+                if dir_entry.file_type()?.is_file() && !is_hidden {
+                    file_paths.push(dir_entry.path());
+                }
+            }
+            file_paths.sort();
+            for file_path in file_paths {
+                loader.load_file(&file_path)?;
+            }
+        } else {
+            loader.load_file(path)?;
+        }
+        loader.finish()
+    }
+
+    /// Loads the snapshot from the contents of a single snapshot file.
+    pub fn from_str_contents(contents: &str) -> io::Result<Self> {
+        let mut loader = SnapshotLoader::default();
+        loader.load_contents(contents)?;
+        loader.finish()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over all the entries in the snapshot in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Rc<LedgerKey>, &EntryWithLiveUntil)> {
+        self.entries.iter()
+    }
+
+    /// Writes the snapshot in the base64 lines format, such that it can be
+    /// loaded back with `from_path`.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for (key, (entry, live_until_ledger)) in &self.entries {
+            writeln!(
+                w,
+                "{}",
+                entry
+                    .to_xdr_base64(DEFAULT_XDR_RW_LIMITS)
+                    .map_err(invalid_data)?
+            )?;
+            if let Some(live_until_ledger) = live_until_ledger {
+                let ttl_entry = LedgerEntry {
+                    last_modified_ledger_seq: entry.last_modified_ledger_seq,
+                    data: LedgerEntryData::Ttl(TtlEntry {
+                        key_hash: key_hash(key)?,
+                        live_until_ledger_seq: *live_until_ledger,
+                    }),
+                    ext: LedgerEntryExt::V0,
+                };
+                writeln!(
+                    w,
+                    "{}",
+                    ttl_entry
+                        .to_xdr_base64(DEFAULT_XDR_RW_LIMITS)
+                        .map_err(invalid_data)?
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SnapshotSource for FileSnapshotSource {
+    fn get(&self, key: &Rc<LedgerKey>) -> Result<EntryWithLiveUntil, HostError> {
+        match self.entries.get(key) {
+            Some((entry, live_until_ledger)) => Ok((Rc::clone(entry), *live_until_ledger)),
+            None => Err(missing_value_error()),
+        }
+    }
+
+    fn has(&self, key: &Rc<LedgerKey>) -> Result<bool, HostError> {
+        Ok(self.entries.contains_key(key))
+    }
+}
+
+/// Accumulates the entries from one or more snapshot files and matches the
+/// TTL entries to the entries they belong to.
+#[derive(Default)]
+struct SnapshotLoader {
+    entries: Vec<LedgerEntry>,
+    live_until_ledgers: BTreeMap<Hash, u32>,
+}
+
+impl SnapshotLoader {
+    fn load_file(&mut self, path: &Path) -> io::Result<()> {
+        let contents = fs::read_to_string(path)?;
+        self.load_contents(&contents)
+            .map_err(|e| invalid_data(format!("{}: {e}", path.display())))
+    }
+
+    fn load_contents(&mut self, contents: &str) -> io::Result<()> {
+        if contents.trim_start().starts_with('[') {
+            let values: Vec<serde_json::Value> =
+                serde_json::from_str(contents).map_err(invalid_data)?;
+            for (i, value) in values.into_iter().enumerate() {
+                let entry = match value {
+                    serde_json::Value::String(s) => {
+                        LedgerEntry::from_xdr_base64(s, DEFAULT_XDR_RW_LIMITS)
+                            .map_err(|e| invalid_data(format!("element {i}: {e}")))?
+                    }
+                    value => serde_json::from_value(value)
+                        .map_err(|e| invalid_data(format!("element {i}: {e}")))?,
+                };
+                self.add_entry(entry);
+            }
+        } else {
+            for (i, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let entry = LedgerEntry::from_xdr_base64(line, DEFAULT_XDR_RW_LIMITS)
+                    .map_err(|e| invalid_data(format!("line {}: {e}", i + 1)))?;
+                self.add_entry(entry);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_entry(&mut self, entry: LedgerEntry) {
+        if let LedgerEntryData::Ttl(ttl) = &entry.data {
+            self.live_until_ledgers
+                .insert(ttl.key_hash.clone(), ttl.live_until_ledger_seq);
+        } else {
+            self.entries.push(entry);
+        }
+    }
+
+    fn finish(self) -> io::Result<FileSnapshotSource> {
+        let budget = Budget::default();
+        let mut entries = BTreeMap::new();
+        for entry in self.entries {
+            let key = Rc::new(ledger_entry_to_ledger_key(&entry, &budget).map_err(invalid_data)?);
+            let live_until_ledger = self.live_until_ledgers.get(&key_hash(&key)?).copied();
+            entries.insert(key, (Rc::new(entry), live_until_ledger));
+        }
+        Ok(FileSnapshotSource { entries })
+    }
+}
+
+fn key_hash(key: &LedgerKey) -> io::Result<Hash> {
+    let encoded_key = key.to_xdr(DEFAULT_XDR_RW_LIMITS).map_err(invalid_data)?;
+    Ok(Hash(Sha256::digest(encoded_key).into()))
+}
+
+fn missing_value_error() -> HostError {
+    Error::from_type_and_code(ScErrorType::Storage, ScErrorCode::MissingValue).into()
+}
+
+fn invalid_data(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
@@ -7,18 +7,29 @@
 //!   - [Env::put_contract_data](crate::Env::put_contract_data)
 //!   - [Env::del_contract_data](crate::Env::del_contract_data)
 
-use std::rc::Rc;
+use std::{collections::BTreeMap, rc::Rc};
 
 use crate::{
     budget::Budget,
-    host::{ledger_info_helper::get_key_durability, metered_map::MeteredOrdMap},
-    xdr::{ContractDataDurability, LedgerEntry, LedgerKey, ScErrorCode, ScErrorType},
-    Env, Error, Host, HostError, Val,
+    host::{
+        ledger_info_helper::get_key_durability, metered_map::MeteredOrdMap,
+        metered_storage_map::MeteredStorageMap,
+    },
+    xdr::WriteXdr,
+    xdr::{
+        ConfigSettingEntry, ContractDataDurability, LedgerEntry, LedgerKey, ScErrorCode,
+        ScErrorType,
+    },
+    Env, Error, Host, HostError, Val, DEFAULT_XDR_RW_LIMITS,
 };
 
-pub type FootprintMap = MeteredOrdMap<Rc<LedgerKey>, AccessType, Budget>;
+mod diff;
+pub(crate) use diff::for_each_entry_change;
+pub use diff::{diff_storage_maps, diff_storage_with_snapshot, StorageChange, StorageChangeKind};
+
+pub type FootprintMap = MeteredStorageMap<Rc<LedgerKey>, AccessType, Budget>;
 pub type EntryWithLiveUntil = (Rc<LedgerEntry>, Option<u32>);
-pub type StorageMap = MeteredOrdMap<Rc<LedgerKey>, Option<EntryWithLiveUntil>, Budget>;
+pub type StorageMap = MeteredStorageMap<Rc<LedgerKey>, Option<EntryWithLiveUntil>, Budget>;
 
 /// The in-memory instance storage of the current running contract. Initially
 /// contains entries from the `ScMap` of the corresponding `ScContractInstance`
@@ -30,13 +41,6 @@ pub(crate) struct InstanceStorageMap {
     pub(crate) is_modified: bool,
 }
 
-#[cfg(feature = "testutils")]
-impl std::hash::Hash for StorageMap {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.map.hash(state);
-    }
-}
-
 impl InstanceStorageMap {
     pub(crate) fn from_map(map: Vec<(Val, Val)>, host: &Host) -> Result<Self, HostError> {
         Ok(Self {
@@ -76,18 +80,11 @@ pub trait SnapshotSource {
 /// transaction has an unknown [Footprint] it can be calculated by
 /// running a "preflight" execution in [FootprintMode::Recording],
 /// against a suitably fresh [SnapshotSource].
-// Notes on metering: covered by the underneath `MeteredOrdMap`.
+// Notes on metering: covered by the underneath `MeteredStorageMap`.
 #[derive(Clone, Default)]
 #[cfg_attr(feature = "testutils", derive(Hash))]
 pub struct Footprint(pub FootprintMap);
 
-#[cfg(feature = "testutils")]
-impl std::hash::Hash for FootprintMap {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.map.hash(state);
-    }
-}
-
 impl Footprint {
     pub fn record_access(
         &mut self,
@@ -140,6 +137,93 @@ impl Footprint {
     }
 }
 
+/// Describes how the [Footprint] of an execution in [FootprintMode::Enforcing]
+/// compares to the actual accesses, i.e. which declared keys could have been
+/// omitted from the [Footprint] or declared with a lower [AccessType].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FootprintUsageReport {
+    /// Keys declared in the [Footprint] that haven't been accessed at all.
+    pub unused_keys: Vec<Rc<LedgerKey>>,
+    /// Keys declared as [AccessType::ReadWrite] that have only been read.
+    pub unwritten_read_write_keys: Vec<Rc<LedgerKey>>,
+}
+
+impl FootprintUsageReport {
+    pub fn is_empty(&self) -> bool {
+        self.unused_keys.is_empty() && self.unwritten_read_write_keys.is_empty()
+    }
+}
+
+/// Statistics of the accesses to a single [LedgerKey] recorded in the
+/// [StorageAccessLog].
+///
+/// The byte counts are the total sizes of the `LedgerEntry` XDR of the entries
+/// that have been read via `get` or written via `put`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StorageAccessStats {
+    pub get_count: u32,
+    pub has_count: u32,
+    pub put_count: u32,
+    pub del_count: u32,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub ttl_extension_count: u32,
+}
+
+/// Opt-in log of all the successful [Storage] accesses made during an
+/// execution, aggregated per [LedgerKey].
+pub type StorageAccessLog = BTreeMap<Rc<LedgerKey>, StorageAccessStats>;
+
+/// Network limits on the sizes of the ledger entries written by the host.
+///
+/// These mirror the corresponding [ConfigSettingEntry] values and are only
+/// enforced when set on [Storage] via [Storage::set_entry_size_limits], so
+/// that oversized writes fail during the execution (and thus simulation)
+/// instead of only being rejected later by the network.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LedgerEntrySizeLimits {
+    /// Maximum size of the contract data `LedgerKey` XDR.
+    pub max_contract_data_key_size_bytes: u32,
+    /// Maximum size of the contract data `LedgerEntry` XDR.
+    pub max_contract_data_entry_size_bytes: u32,
+    /// Maximum size of the contract Wasm code.
+    pub max_contract_size_bytes: u32,
+}
+
+impl Default for LedgerEntrySizeLimits {
+    fn default() -> Self {
+        Self {
+            max_contract_data_key_size_bytes: u32::MAX,
+            max_contract_data_entry_size_bytes: u32::MAX,
+            max_contract_size_bytes: u32::MAX,
+        }
+    }
+}
+
+impl LedgerEntrySizeLimits {
+    /// Builds the limits from the network [ConfigSettingEntry] values. The
+    /// settings unrelated to the entry sizes are ignored and the limits that
+    /// are not set are unbounded.
+    pub fn from_config_settings<'a>(
+        settings: impl IntoIterator<Item = &'a ConfigSettingEntry>,
+    ) -> Self {
+        let mut limits = Self::default();
+        for setting in settings {
+            match setting {
+                ConfigSettingEntry::ContractMaxSizeBytes(v) => limits.max_contract_size_bytes = *v,
+                ConfigSettingEntry::ContractDataKeySizeBytes(v) => {
+                    limits.max_contract_data_key_size_bytes = *v
+                }
+                ConfigSettingEntry::ContractDataEntrySizeBytes(v) => {
+                    limits.max_contract_data_entry_size_bytes = *v
+                }
+                _ => (),
+            }
+        }
+        limits
+    }
+}
+
 #[derive(Clone, Default)]
 pub enum FootprintMode {
     Recording(Rc<dyn SnapshotSource>),
@@ -165,10 +249,19 @@ pub struct Storage {
     pub footprint: Footprint,
     pub mode: FootprintMode,
     pub map: StorageMap,
+    // Strongest access type actually used for every accessed key in
+    // [FootprintMode::Enforcing]. This is a diagnostic-only record that is
+    // deliberately not metered (it is bounded by the footprint size), so
+    // it is only populated when enabled via `enable_footprint_usage_tracking`.
+    enforced_accesses: Option<BTreeMap<Rc<LedgerKey>, AccessType>>,
+    // Not metered for the same reason as `enforced_accesses`; only populated
+    // when enabled via `enable_access_log`.
+    access_log: Option<StorageAccessLog>,
+    entry_size_limits: Option<LedgerEntrySizeLimits>,
 }
 
 // Notes on metering: all storage operations: `put`, `get`, `del`, `has` are
-// covered by the underlying [MeteredOrdMap] and the [Footprint]'s own map.
+// covered by the underlying [MeteredStorageMap] and the [Footprint]'s own map.
 impl Storage {
     /// Only a subset of Stellar's XDR ledger key or entry types are supported
     /// by Soroban: accounts, trustlines, contract code and data. The rest are
@@ -206,6 +299,9 @@ impl Storage {
             mode: FootprintMode::Enforcing,
             footprint,
             map,
+            enforced_accesses: None,
+            access_log: None,
+            entry_size_limits: None,
         }
     }
 
@@ -216,9 +312,26 @@ impl Storage {
             mode: FootprintMode::Recording(src),
             footprint: Footprint::default(),
             map: Default::default(),
+            enforced_accesses: None,
+            access_log: None,
+            entry_size_limits: None,
         }
     }
 
+    /// Converts the storage and footprint maps into the representation used
+    /// by the given ledger protocol version (see
+    /// [PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION](crate::PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION)).
+    pub(crate) fn use_protocol_version(
+        &mut self,
+        protocol_version: u32,
+        budget: &Budget,
+    ) -> Result<(), HostError> {
+        self.map = std::mem::take(&mut self.map).for_protocol(protocol_version, budget)?;
+        self.footprint.0 =
+            std::mem::take(&mut self.footprint.0).for_protocol(protocol_version, budget)?;
+        Ok(())
+    }
+
     // Helper function the next 3 `get`-variants funnel into.
     fn try_get_full(
         &mut self,
@@ -228,12 +341,20 @@ impl Storage {
         let _span = tracy_span!("storage get");
         Self::check_supported_ledger_key_type(key)?;
         self.prepare_read_only_access(key, budget)?;
-        match self.map.get::<Rc<LedgerKey>>(key, budget)? {
+        let res = match self.map.get::<Rc<LedgerKey>>(key, budget)? {
             // Key has to be in the storage map at this point due to
             // `prepare_read_only_access`.
-            None => Err((ScErrorType::Storage, ScErrorCode::InternalError).into()),
-            Some(pair_option) => Ok(pair_option.clone()),
-        }
+            None => return Err((ScErrorType::Storage, ScErrorCode::InternalError).into()),
+            Some(pair_option) => pair_option.clone(),
+        };
+        self.log_access(key, |stats| {
+            stats.get_count = stats.get_count.saturating_add(1);
+            if let Some((entry, _)) = &res {
+                stats.read_bytes = stats.read_bytes.saturating_add(xdr_size(entry)?);
+            }
+            Ok(())
+        })?;
+        Ok(res)
     }
 
     /// Attempts to retrieve the [LedgerEntry] associated with a given
@@ -308,8 +429,18 @@ impl Storage {
             }
             FootprintMode::Enforcing => {
                 self.footprint.enforce_access(key, ty, budget)?;
+                self.track_enforced_access(key, ty);
             }
         };
+        self.log_access(key, |stats| {
+            if let Some((entry, _)) = &val {
+                stats.put_count = stats.put_count.saturating_add(1);
+                stats.write_bytes = stats.write_bytes.saturating_add(xdr_size(entry)?);
+            } else {
+                stats.del_count = stats.del_count.saturating_add(1);
+            }
+            Ok(())
+        })?;
         self.map = self.map.insert(Rc::clone(key), val, budget)?;
         Ok(())
     }
@@ -361,13 +492,18 @@ impl Storage {
         let _span = tracy_span!("storage has");
         Self::check_supported_ledger_key_type(key)?;
         self.prepare_read_only_access(key, budget)?;
-        Ok(self
+        let res = self
             .map
             .get::<Rc<LedgerKey>>(key, budget)?
             // Key has to be present in storage at this point, so not having it
             // would be an internal error.
             .ok_or_else(|| HostError::from((ScErrorType::Storage, ScErrorCode::InternalError)))?
-            .is_some())
+            .is_some();
+        self.log_access(key, |stats| {
+            stats.has_count = stats.has_count.saturating_add(1);
+            Ok(())
+        })?;
+        Ok(res)
     }
 
     /// Extends `key` to live `extend_to` ledgers from now (not counting the
@@ -457,6 +593,10 @@ impl Storage {
 
         if new_live_until > old_live_until && old_live_until.saturating_sub(ledger_seq) <= threshold
         {
+            self.log_access(&key, |stats| {
+                stats.ttl_extension_count = stats.ttl_extension_count.saturating_add(1);
+                Ok(())
+            })?;
             self.map = self.map.insert(
                 key,
                 Some((entry.clone(), Some(new_live_until))),
@@ -466,6 +606,49 @@ impl Storage {
         Ok(())
     }
 
+    /// Enables recording of the [StorageAccessLog]. This has no effect on the
+    /// metered cost of the storage operations.
+    pub fn enable_access_log(&mut self) {
+        if self.access_log.is_none() {
+            self.access_log = Some(StorageAccessLog::new());
+        }
+    }
+
+    /// Returns the [StorageAccessLog] recorded so far, or `None` if it hasn't
+    /// been enabled.
+    pub fn access_log(&self) -> Option<&StorageAccessLog> {
+        self.access_log.as_ref()
+    }
+
+    /// Takes the recorded [StorageAccessLog] out of the storage, which also
+    /// stops the recording.
+    pub fn take_access_log(&mut self) -> Option<StorageAccessLog> {
+        self.access_log.take()
+    }
+
+    /// Sets the [LedgerEntrySizeLimits] to enforce for the entries written by
+    /// the host, or disables the enforcement when `None`.
+    pub fn set_entry_size_limits(&mut self, limits: Option<LedgerEntrySizeLimits>) {
+        self.entry_size_limits = limits;
+    }
+
+    /// Returns the enforced [LedgerEntrySizeLimits], if any.
+    pub fn entry_size_limits(&self) -> Option<LedgerEntrySizeLimits> {
+        self.entry_size_limits
+    }
+
+    // Updates the access log stats of `key`, if the log is enabled.
+    fn log_access(
+        &mut self,
+        key: &Rc<LedgerKey>,
+        update: impl FnOnce(&mut StorageAccessStats) -> Result<(), HostError>,
+    ) -> Result<(), HostError> {
+        match &mut self.access_log {
+            Some(access_log) => update(access_log.entry(Rc::clone(key)).or_default()),
+            None => Ok(()),
+        }
+    }
+
     fn prepare_read_only_access(
         &mut self,
         key: &Rc<LedgerKey>,
@@ -488,8 +671,60 @@ impl Storage {
             }
             FootprintMode::Enforcing => {
                 self.footprint.enforce_access(key, ty, budget)?;
+                self.track_enforced_access(key, ty);
             }
         };
         Ok(())
     }
+
+    fn track_enforced_access(&mut self, key: &Rc<LedgerKey>, ty: AccessType) {
+        if let Some(enforced_accesses) = &mut self.enforced_accesses {
+            let access = enforced_accesses.entry(Rc::clone(key)).or_insert(ty);
+            if ty == AccessType::ReadWrite {
+                *access = ty;
+            }
+        }
+    }
+
+    /// Enables tracking of the accesses made in [FootprintMode::Enforcing]
+    /// mode, which is necessary for [Storage::footprint_usage_report]. This
+    /// has no effect on the metered cost of the storage operations.
+    pub fn enable_footprint_usage_tracking(&mut self) {
+        if self.enforced_accesses.is_none() {
+            self.enforced_accesses = Some(BTreeMap::new());
+        }
+    }
+
+    /// Returns the [Footprint] keys that have been over-declared during the
+    /// execution in [FootprintMode::Enforcing] mode: the keys that haven't
+    /// been accessed and the [AccessType::ReadWrite] keys that have only been
+    /// read. The keys are reported in the [Footprint] order.
+    ///
+    /// Returns `None` if the tracking hasn't been enabled via
+    /// [Storage::enable_footprint_usage_tracking]. The report is always empty
+    /// in [FootprintMode::Recording] mode, as the recorded [Footprint] matches
+    /// the accesses exactly.
+    pub fn footprint_usage_report(&self) -> Option<FootprintUsageReport> {
+        let enforced_accesses = self.enforced_accesses.as_ref()?;
+        let mut report = FootprintUsageReport::default();
+        if let FootprintMode::Recording(_) = self.mode {
+            return Some(report);
+        }
+        for (key, declared) in self.footprint.0.unmetered_iter() {
+            match (declared, enforced_accesses.get(key)) {
+                (_, None) => report.unused_keys.push(Rc::clone(key)),
+                (AccessType::ReadWrite, Some(AccessType::ReadOnly)) => {
+                    report.unwritten_read_write_keys.push(Rc::clone(key))
+                }
+                _ => (),
+            }
+        }
+        Some(report)
+    }
+}
+
+// Size of the XDR of a ledger key or entry for the access log and the entry
+// size limits; not metered, see `access_log`.
+pub(crate) fn xdr_size(v: &impl WriteXdr) -> Result<u64, HostError> {
+    Ok(v.to_xdr(DEFAULT_XDR_RW_LIMITS)?.len() as u64)
 }
@@ -0,0 +1,208 @@
+use std::{cmp::max, rc::Rc};
+
+use crate::{
+    budget::Budget,
+    xdr::{LedgerEntry, LedgerEntryData, LedgerKey, ScAddress, ScErrorCode, ScErrorType, ScVal},
+    Error, HostError,
+};
+
+use super::{EntryWithLiveUntil, SnapshotSource, Storage, StorageMap};
+
+/// The kind of a [StorageChange].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StorageChangeKind {
+    /// The entry didn't exist in the old state.
+    Created,
+    /// The entry value has changed. Its live until ledger may have been
+    /// extended as well.
+    Updated,
+    /// The entry doesn't exist in the new state.
+    Deleted,
+    /// Only the live until ledger of the entry has been extended.
+    TtlExtended,
+}
+
+/// A single difference between two storage states, see [diff_storage_maps]
+/// and [diff_storage_with_snapshot].
+///
+/// The entries are kept as-is, i.e. the contract data keys and values are
+/// available as decoded [ScVal]s via [StorageChange::contract_data_key],
+/// [StorageChange::old_contract_data_val] and
+/// [StorageChange::new_contract_data_val].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StorageChange {
+    pub key: Rc<LedgerKey>,
+    pub kind: StorageChangeKind,
+    pub old_entry: Option<Rc<LedgerEntry>>,
+    pub new_entry: Option<Rc<LedgerEntry>>,
+    /// Live until ledger of the old entry, if the entry exists and has a TTL.
+    pub old_live_until_ledger: Option<u32>,
+    /// Live until ledger of the new entry, if the entry exists and has a TTL.
+    /// Never less than `old_live_until_ledger`, as the live until ledger
+    /// can't be reduced.
+    pub new_live_until_ledger: Option<u32>,
+}
+
+impl StorageChange {
+    /// Returns the contract address and the key of a contract data entry.
+    pub fn contract_data_key(&self) -> Option<(&ScAddress, &ScVal)> {
+        match self.key.as_ref() {
+            LedgerKey::ContractData(k) => Some((&k.contract, &k.key)),
+            _ => None,
+        }
+    }
+
+    /// Returns the old value of a contract data entry.
+    pub fn old_contract_data_val(&self) -> Option<&ScVal> {
+        contract_data_val(self.old_entry.as_deref())
+    }
+
+    /// Returns the new value of a contract data entry.
+    pub fn new_contract_data_val(&self) -> Option<&ScVal> {
+        contract_data_val(self.new_entry.as_deref())
+    }
+
+    // Builds the change between the `old` and `new` states of `key`, if there
+    // is any. `new` is expected to have the final live until ledger, see
+    // `with_final_live_until_ledger`.
+    fn from_states(
+        key: &Rc<LedgerKey>,
+        old: Option<EntryWithLiveUntil>,
+        new: Option<EntryWithLiveUntil>,
+    ) -> Result<Option<Self>, HostError> {
+        let (old_entry, old_live_until_ledger) = old.unzip();
+        let (new_entry, new_live_until_ledger) = new.unzip();
+        let old_live_until_ledger = old_live_until_ledger.flatten();
+        let new_live_until_ledger = new_live_until_ledger.flatten();
+        let kind = match (&old_entry, &new_entry) {
+            (None, None) => return Ok(None),
+            (None, Some(_)) => StorageChangeKind::Created,
+            (Some(_), None) => StorageChangeKind::Deleted,
+            (Some(old), Some(new)) => {
+                if !Rc::ptr_eq(old, new) && old != new {
+                    StorageChangeKind::Updated
+                } else if new_live_until_ledger != old_live_until_ledger {
+                    // Entries with TTL must have it in both states.
+                    if old_live_until_ledger.is_none() || new_live_until_ledger.is_none() {
+                        return Err(Error::from_type_and_code(
+                            ScErrorType::Storage,
+                            ScErrorCode::InternalError,
+                        )
+                        .into());
+                    }
+                    StorageChangeKind::TtlExtended
+                } else {
+                    return Ok(None);
+                }
+            }
+        };
+        Ok(Some(Self {
+            key: Rc::clone(key),
+            kind,
+            old_entry,
+            new_entry,
+            old_live_until_ledger,
+            new_live_until_ledger,
+        }))
+    }
+}
+
+// Returns the `new` state of an entry with the final live until ledger, which
+// is never less than the `old` one, as the TTL of an entry can't be reduced.
+fn with_final_live_until_ledger(
+    old: Option<&EntryWithLiveUntil>,
+    new: Option<EntryWithLiveUntil>,
+) -> Option<EntryWithLiveUntil> {
+    match (old, new) {
+        (Some((_, Some(old_live_until))), Some((entry, Some(new_live_until)))) => {
+            Some((entry, Some(max(*old_live_until, new_live_until))))
+        }
+        (_, new) => new,
+    }
+}
+
+/// Calls `f` with the initial state from `snapshot` and the final state of
+/// every entry in the `storage` map, in key order. The final live until
+/// ledger is never less than the initial one (see
+/// `with_final_live_until_ledger`). This is the common part of
+/// [get_ledger_changes](crate::e2e_invoke::get_ledger_changes) and
+/// [diff_storage_with_snapshot].
+pub(crate) fn for_each_entry_change<T: SnapshotSource + ?Sized>(
+    budget: &Budget,
+    storage: &Storage,
+    snapshot: &T,
+    mut f: impl FnMut(
+        &Rc<LedgerKey>,
+        Option<EntryWithLiveUntil>,
+        Option<EntryWithLiveUntil>,
+    ) -> Result<(), HostError>,
+) -> Result<(), HostError> {
+    for (key, new_entry) in storage.map.iter(budget)? {
+        let old_entry = if snapshot.has(key)? {
+            Some(snapshot.get(key)?)
+        } else {
+            None
+        };
+        let new_entry = with_final_live_until_ledger(old_entry.as_ref(), new_entry.clone());
+        f(key, old_entry, new_entry)?;
+    }
+    Ok(())
+}
+
+fn contract_data_val(entry: Option<&LedgerEntry>) -> Option<&ScVal> {
+    match entry.map(|e| &e.data) {
+        Some(LedgerEntryData::ContractData(d)) => Some(&d.val),
+        _ => None,
+    }
+}
+
+/// Returns the changes between two full storage states in key order. A key
+/// that is missing from a map is treated the same way as a key that maps to
+/// `None`, i.e. as a non-existent entry.
+///
+/// Notes on metering: the map iteration and lookups are metered, while the
+/// entry comparison is free, like the rest of the diffing that doesn't happen
+/// during the contract execution.
+pub fn diff_storage_maps(
+    budget: &Budget,
+    old: &StorageMap,
+    new: &StorageMap,
+) -> Result<Vec<StorageChange>, HostError> {
+    let mut changes = vec![];
+    for (key, new_entry) in new.iter(budget)? {
+        let old_entry = old.get::<Rc<LedgerKey>>(key, budget)?.cloned().flatten();
+        let new_entry = with_final_live_until_ledger(old_entry.as_ref(), new_entry.clone());
+        if let Some(change) = StorageChange::from_states(key, old_entry, new_entry)? {
+            changes.push(change);
+        }
+    }
+    for (key, old_entry) in old.iter(budget)? {
+        if !new.contains_key::<Rc<LedgerKey>>(key, budget)? {
+            if let Some(change) = StorageChange::from_states(key, old_entry.clone(), None)? {
+                changes.push(change);
+            }
+        }
+    }
+    changes.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(changes)
+}
+
+/// Returns the changes between the initial state provided by `snapshot` and
+/// the current state of `storage` for every key in the `storage`, in key
+/// order. This is the in-process counterpart of
+/// [get_ledger_changes](crate::e2e_invoke::get_ledger_changes) that doesn't
+/// round-trip the entries through XDR.
+pub fn diff_storage_with_snapshot<T: SnapshotSource + ?Sized>(
+    budget: &Budget,
+    storage: &Storage,
+    snapshot: &T,
+) -> Result<Vec<StorageChange>, HostError> {
+    let mut changes = vec![];
+    for_each_entry_change(budget, storage, snapshot, |key, old_entry, new_entry| {
+        if let Some(change) = StorageChange::from_states(key, old_entry, new_entry)? {
+            changes.push(change);
+        }
+        Ok(())
+    })?;
+    Ok(changes)
+}
@@ -433,3 +433,408 @@ fn total_amount_charged_from_random_inputs() -> Result<(), HostError> {
 
     Ok(())
 }
+
+#[test]
+fn module_cache_instantiation_charges() -> Result<(), HostError> {
+    use crate::{xdr::Hash, ModuleCache, VM_CACHED_INSTANTIATION_PROTOCOL_VERSION};
+    use sha2::{Digest, Sha256};
+    use soroban_test_wasms::ADD_I32;
+
+    let wasm_hash = Hash(Sha256::digest(ADD_I32).try_into().unwrap());
+    let iterations = |host: &Host, ty| host.as_budget().get_tracker(ty).unwrap().iterations;
+    // Calls the contract and returns the number of the full and cached
+    // instantiations charged.
+    let call_add = |host: &Host, contract| -> Result<(u64, u64), HostError> {
+        let full = iterations(host, ContractCostType::VmInstantiation);
+        let cached = iterations(host, ContractCostType::VmCachedInstantiation);
+        host.call(
+            contract,
+            Symbol::try_from_small_str("add")?,
+            test_vec![host, 1_i32, 2_i32].into(),
+        )?;
+        Ok((
+            iterations(host, ContractCostType::VmInstantiation) - full,
+            iterations(host, ContractCostType::VmCachedInstantiation) - cached,
+        ))
+    };
+
+    let cache = ModuleCache::new(1_000_000);
+    let host = Host::test_host_with_recording_footprint();
+    host.set_module_cache(Some(cache.clone()))?;
+    let contract = host.register_test_contract_wasm(ADD_I32);
+    // The repeated instantiations within the same host are charged as cached
+    // ones, starting from the protocol that supports it.
+    let repeated =
+        if host.get_ledger_protocol_version()? >= VM_CACHED_INSTANTIATION_PROTOCOL_VERSION {
+            (0, 1)
+        } else {
+            (1, 0)
+        };
+    // Uploads are always fully validated and don't populate the cache.
+    assert!(cache.is_empty());
+    assert_eq!(call_add(&host, contract)?, (1, 0));
+    assert!(cache.contains(&wasm_hash));
+    assert_eq!(cache.wasm_bytes(), ADD_I32.len());
+    assert_eq!(call_add(&host, contract)?, repeated);
+    // The repeated instantiations don't depend on the cache.
+    host.set_module_cache(None)?;
+    assert_eq!(call_add(&host, contract)?, repeated);
+
+    // The cache is shared with another host, but a hit in the shared cache is
+    // charged as a full instantiation, as the cache state may differ between
+    // the replicas of the host.
+    let other_host = Host::test_host_with_recording_footprint();
+    other_host.set_module_cache(Some(cache.clone()))?;
+    let other_contract = other_host.register_test_contract_wasm(ADD_I32);
+    let cpu_before = other_host.as_budget().get_cpu_insns_consumed()?;
+    assert_eq!(call_add(&other_host, other_contract)?, (1, 0));
+    let cpu_with_hit = other_host.as_budget().get_cpu_insns_consumed()? - cpu_before;
+    let uncached_host = Host::test_host_with_recording_footprint();
+    let uncached_contract = uncached_host.register_test_contract_wasm(ADD_I32);
+    let cpu_before = uncached_host.as_budget().get_cpu_insns_consumed()?;
+    assert_eq!(call_add(&uncached_host, uncached_contract)?, (1, 0));
+    assert_eq!(
+        uncached_host.as_budget().get_cpu_insns_consumed()? - cpu_before,
+        cpu_with_hit
+    );
+
+    // The protocol dependent checks are repeated for the cached modules: a
+    // host on a protocol older than the one the contract has been built for
+    // rejects the contract cached by the other hosts.
+    let old_host = Host::test_host_with_recording_footprint();
+    old_host.set_module_cache(Some(cache.clone()))?;
+    let old_contract = old_host.register_test_contract_wasm(ADD_I32);
+    old_host.with_mut_ledger_info(|li| li.protocol_version = 0)?;
+    let err = call_add(&old_host, old_contract).err().unwrap();
+    assert!(err.error.is_type(ScErrorType::WasmVm));
+    assert!(err.error.is_code(ScErrorCode::InvalidInput));
+
+    assert!(cache.invalidate(&wasm_hash));
+    assert!(!cache.invalidate(&wasm_hash));
+    assert_eq!(call_add(&other_host, other_contract)?, repeated);
+    assert!(cache.is_empty());
+
+    // Modules exceeding the size bound are not cached.
+    let small_cache = ModuleCache::new(ADD_I32.len() - 1);
+    let small_cache_host = Host::test_host_with_recording_footprint();
+    small_cache_host.set_module_cache(Some(small_cache.clone()))?;
+    let small_cache_contract = small_cache_host.register_test_contract_wasm(ADD_I32);
+    assert_eq!(call_add(&small_cache_host, small_cache_contract)?, (1, 0));
+    assert!(small_cache.is_empty());
+    Ok(())
+}
+
+#[test]
+fn vm_profiling_attributes_budget_to_invocations() -> Result<(), HostError> {
+    use crate::testutils::wasm::wasm_module_with_4n_insns;
+    let host = Host::test_host_with_recording_footprint();
+    let id_obj = host.register_test_contract_wasm(&wasm_module_with_4n_insns(1000));
+    let vec_id_obj = host.register_test_contract_wasm(VEC);
+    let _ = host
+        .clone()
+        .test_budget(100_000, 1_048_576)
+        .enable_model(ContractCostType::WasmInsnExec, 6, 0, 0, 0)
+        .enable_model(ContractCostType::DispatchHostFunction, 10, 0, 1, 0);
+
+    // Nothing is recorded until the profiling is enabled.
+    let sym = Symbol::try_from_small_str("test").unwrap();
+    host.call(id_obj, sym, host.test_vec_obj::<u32>(&[4375])?)?;
+    assert!(host.vm_profile()?.is_empty());
+
+    host.enable_vm_profiling(true)?;
+    host.call(id_obj, sym, host.test_vec_obj::<u32>(&[4375])?)?;
+    // `vec_err` calls `vec_new` and `vec_put`.
+    host.try_call(
+        vec_id_obj,
+        Symbol::try_from_small_str("vec_err").unwrap(),
+        host.test_vec_obj::<u32>(&[1])?,
+    )?;
+
+    let profile = host.vm_profile()?;
+    assert_eq!(profile.len(), 2);
+    assert_eq!(profile[0].export, "test");
+    assert_eq!(profile[0].depth, 0);
+    assert_eq!(
+        (profile[0].wasm_fuel, profile[0].wasm_cpu_insns),
+        (4005, 24030)
+    );
+    assert!(profile[0].host_functions.is_empty());
+    assert_eq!(profile[0].total_cpu_insns(), 24030);
+
+    assert_eq!(profile[1].export, "vec_err");
+    assert_eq!(
+        profile[1]
+            .host_functions
+            .values()
+            .map(|hf| hf.calls)
+            .sum::<u64>(),
+        2
+    );
+    assert!(profile[1]
+        .host_functions
+        .values()
+        .all(|hf| hf.cpu_insns >= 10 && hf.mem_bytes >= 1));
+    assert!(profile[1].to_string().contains("`vec_err` in contract"));
+
+    host.enable_vm_profiling(false)?;
+    assert!(host.vm_profile()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn cost_tree_attributes_charges_to_frames() -> Result<(), HostError> {
+    use crate::budget::CostFrame;
+    use soroban_test_wasms::{ADD_I32, INVOKE_CONTRACT};
+
+    let host = Host::test_host_with_recording_footprint();
+    let id0_obj = host.register_test_contract_wasm(INVOKE_CONTRACT);
+    let id1_obj = host.register_test_contract_wasm(ADD_I32);
+    let args = host.test_vec_obj::<i32>(&[5, 6])?;
+    let args = host.vec_push_back(args, id1_obj.to_val())?;
+
+    host.as_budget().enable_cost_tree(true)?;
+    let cpu_before = host.as_budget().get_cpu_insns_consumed()?;
+    let mem_before = host.as_budget().get_mem_bytes_consumed()?;
+    host.call(id0_obj, Symbol::try_from_small_str("add_with")?, args)?;
+
+    let tree = host.as_budget().cost_tree()?.unwrap();
+    assert_eq!(tree.frame, None);
+    assert_eq!(tree.children.len(), 1);
+    let outer = &tree.children[0];
+    assert_eq!(
+        outer.frame,
+        Some(CostFrame {
+            contract_id: Some(host.contract_id_from_address(id0_obj)?),
+            function: "add_with".to_string(),
+        })
+    );
+    // The nested call is made via the `call` host function.
+    let call = outer
+        .children
+        .iter()
+        .find(|c| c.frame.as_ref().map_or(false, |f| f.function == "call"))
+        .unwrap();
+    assert_eq!(call.frame.as_ref().unwrap().contract_id, None);
+    assert!(call
+        .exclusive
+        .contains_key(&ContractCostType::DispatchHostFunction));
+    assert_eq!(call.children.len(), 1);
+    let inner = &call.children[0];
+    assert_eq!(
+        inner.frame,
+        Some(CostFrame {
+            contract_id: Some(host.contract_id_from_address(id1_obj)?),
+            function: "add".to_string(),
+        })
+    );
+    assert!(inner
+        .exclusive
+        .contains_key(&ContractCostType::WasmInsnExec));
+
+    // The inclusive view of a node adds up its own and its children's charges.
+    assert_eq!(
+        outer.inclusive_cpu_insns(),
+        outer.exclusive_cpu_insns()
+            + outer
+                .children
+                .iter()
+                .map(|c| c.inclusive_cpu_insns())
+                .sum::<u64>()
+    );
+    assert_eq!(
+        call.inclusive()[&ContractCostType::WasmInsnExec].cpu,
+        inner.inclusive()[&ContractCostType::WasmInsnExec].cpu
+    );
+
+    // All the charges are accounted for in the tree.
+    assert_eq!(
+        tree.inclusive_cpu_insns(),
+        host.as_budget().get_cpu_insns_consumed()? - cpu_before
+    );
+    assert_eq!(
+        tree.inclusive_mem_bytes(),
+        host.as_budget().get_mem_bytes_consumed()? - mem_before
+    );
+
+    host.as_budget().enable_cost_tree(false)?;
+    assert_eq!(host.as_budget().cost_tree()?, None);
+    Ok(())
+}
+
+#[test]
+fn cost_tree_records_charge_exceeding_budget() -> Result<(), HostError> {
+    let host = Host::test_host();
+    host.as_budget().reset_limits(1_000, 1_000_000)?;
+    host.as_budget().enable_cost_tree(true)?;
+    let res = host
+        .as_budget()
+        .charge(ContractCostType::ComputeSha256Hash, Some(10_000));
+    assert!(HostError::result_matches_err(
+        res,
+        (ScErrorType::Budget, ScErrorCode::ExceededLimit)
+    ));
+    let tree = host.as_budget().cost_tree()?.unwrap();
+    let charge = &tree.exclusive[&ContractCostType::ComputeSha256Hash];
+    assert_eq!(charge.iterations, 1);
+    assert_eq!(charge.cpu, host.as_budget().get_cpu_insns_consumed()?);
+    assert!(charge.cpu > 1_000);
+    Ok(())
+}
+
+#[test]
+fn budget_from_config_settings() -> Result<(), HostError> {
+    use crate::{
+        testutils::MockSnapshotSource,
+        xdr::{
+            ConfigSettingContractComputeV0, ConfigSettingEntry, LedgerEntry, LedgerEntryData,
+            LedgerEntryExt,
+        },
+    };
+
+    // Export the default cost parameters and build a budget from them.
+    let default_budget = Budget::default();
+    let cpu_cost_params = default_budget.get_cpu_cost_params()?;
+    let mem_cost_params = default_budget.get_mem_cost_params()?;
+    assert_eq!(cpu_cost_params.0.len(), ContractCostType::variants().len());
+    let settings = vec![
+        ConfigSettingEntry::ContractComputeV0(ConfigSettingContractComputeV0 {
+            ledger_max_instructions: 500_000_000,
+            tx_max_instructions: 100_000_000,
+            fee_rate_per_instructions_increment: 100,
+            tx_memory_limit: 40 * 1024 * 1024,
+        }),
+        ConfigSettingEntry::ContractCostParamsCpuInstructions(cpu_cost_params.clone()),
+        ConfigSettingEntry::ContractCostParamsMemoryBytes(mem_cost_params.clone()),
+        ConfigSettingEntry::ContractMaxSizeBytes(65536),
+    ];
+    let budget = Budget::try_from_config_settings(settings.iter())?;
+    assert_eq!(budget.get_cpu_insns_remaining()?, 100_000_000);
+    assert_eq!(budget.get_mem_bytes_remaining()?, 40 * 1024 * 1024);
+    assert_eq!(budget.get_cpu_cost_params()?, cpu_cost_params);
+    assert_eq!(budget.get_mem_cost_params()?, mem_cost_params);
+
+    // The same settings loaded from a snapshot.
+    let snapshot = MockSnapshotSource::from_entries(
+        settings
+            .iter()
+            .map(|setting| {
+                let entry = LedgerEntry {
+                    last_modified_ledger_seq: 0,
+                    data: LedgerEntryData::ConfigSetting(setting.clone()),
+                    ext: LedgerEntryExt::V0,
+                };
+                (entry, None)
+            })
+            .collect(),
+    )?;
+    let budget = Budget::try_from_snapshot_source(&snapshot)?;
+    assert_eq!(budget.get_cpu_insns_remaining()?, 100_000_000);
+    assert_eq!(budget.get_cpu_cost_params()?, cpu_cost_params);
+
+    // All the budget settings are required.
+    let res = Budget::try_from_config_settings(settings[1..].iter());
+    assert!(HostError::result_matches_err(
+        res,
+        (ScErrorType::Budget, ScErrorCode::MissingValue)
+    ));
+    Ok(())
+}
+
+#[test]
+fn cost_tree_folded_stacks() -> Result<(), HostError> {
+    use crate::budget::FoldedStackWeight;
+    use soroban_test_wasms::{ADD_I32, INVOKE_CONTRACT};
+
+    let host = Host::test_host_with_recording_footprint();
+    let id0_obj = host.register_test_contract_wasm(INVOKE_CONTRACT);
+    let id1_obj = host.register_test_contract_wasm(ADD_I32);
+    let args = host.test_vec_obj::<i32>(&[5, 6])?;
+    let args = host.vec_push_back(args, id1_obj.to_val())?;
+    host.as_budget().enable_cost_tree(true)?;
+    host.call(id0_obj, Symbol::try_from_small_str("add_with")?, args)?;
+    let tree = host.as_budget().cost_tree()?.unwrap();
+
+    let strkey = |id_obj| -> Result<String, HostError> {
+        Ok(stellar_strkey::Contract(host.contract_id_from_address(id_obj)?.0).to_string())
+    };
+    let cpu_stacks = tree.folded_stacks(FoldedStackWeight::CpuInsns).to_string();
+    let inner_wasm_stack = format!(
+        "{};add_with;call;{};add;WasmInsnExec ",
+        strkey(id0_obj)?,
+        strkey(id1_obj)?
+    );
+    assert!(cpu_stacks
+        .lines()
+        .any(|line| line.starts_with(&inner_wasm_stack)));
+
+    // Every stream adds up to the total charges.
+    let total = |stacks: String| -> u64 {
+        stacks
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap())
+            .sum()
+    };
+    assert_eq!(total(cpu_stacks), tree.inclusive_cpu_insns());
+    assert_eq!(
+        total(tree.folded_stacks(FoldedStackWeight::MemBytes).to_string()),
+        tree.inclusive_mem_bytes()
+    );
+
+    let budget_dump = format!("{:?}", host.as_budget());
+    assert!(budget_dump.contains("Folded stacks (cpu_insns):"));
+    assert!(budget_dump.contains(&inner_wasm_stack));
+    Ok(())
+}
+
+#[test]
+fn charge_histogram_reprices_recorded_charges() -> Result<(), HostError> {
+    use crate::xdr::ContractCostParams;
+    use soroban_test_wasms::ADD_I32;
+
+    let host = Host::test_host_with_recording_footprint();
+    let id_obj = host.register_test_contract_wasm(ADD_I32);
+    let args = host.test_vec_obj::<i32>(&[5, 6])?;
+
+    host.as_budget().enable_charge_histogram(true)?;
+    let cpu_before = host.as_budget().get_cpu_insns_consumed()?;
+    let mem_before = host.as_budget().get_mem_bytes_consumed()?;
+    host.call(id_obj, Symbol::try_from_small_str("add")?, args)?;
+    let histogram = host.as_budget().charge_histogram()?.unwrap();
+    assert!(histogram
+        .0
+        .keys()
+        .any(|(ty, input)| *ty == ContractCostType::MemAlloc && input.is_some()));
+
+    // Under the current parameters the charges are priced as they have been
+    // charged.
+    let cpu_cost_params = host.as_budget().get_cpu_cost_params()?;
+    let mem_cost_params = host.as_budget().get_mem_cost_params()?;
+    let usage = histogram.reprice(cpu_cost_params.clone(), mem_cost_params.clone())?;
+    assert_eq!(
+        usage.cpu_insns,
+        host.as_budget().get_cpu_insns_consumed()? - cpu_before
+    );
+    assert_eq!(
+        usage.mem_bytes,
+        host.as_budget().get_mem_bytes_consumed()? - mem_before
+    );
+
+    // Doubling the cost of a Wasm instruction doubles only the Wasm execution
+    // cost.
+    let mut entries = cpu_cost_params.0.to_vec();
+    entries[ContractCostType::WasmInsnExec as usize].const_term *= 2;
+    let repriced_cpu_cost_params = ContractCostParams(entries.try_into().unwrap());
+    let repriced = histogram.reprice(repriced_cpu_cost_params, mem_cost_params.clone())?;
+    let wasm_cpu_insns = usage.by_cost_type[&ContractCostType::WasmInsnExec].0;
+    assert!(wasm_cpu_insns > 0);
+    assert_eq!(repriced.cpu_insns, usage.cpu_insns + wasm_cpu_insns);
+    assert_eq!(repriced.mem_bytes, usage.mem_bytes);
+
+    // Merged histograms are priced as a whole.
+    let mut merged = histogram.clone();
+    merged.merge(&histogram);
+    let merged_usage = merged.reprice(cpu_cost_params, mem_cost_params)?;
+    assert_eq!(merged_usage.cpu_insns, 2 * usage.cpu_insns);
+    assert_eq!(merged_usage.mem_bytes, 2 * usage.mem_bytes);
+    Ok(())
+}
@@ -1,9 +1,9 @@
 use crate::{
     budget::Budget,
     host_object::HostVec,
-    storage::{Footprint, Storage},
+    storage::{Footprint, Storage, StorageMap},
     testutils::{generate_account_id, generate_bytes_array},
-    Host, HostError, MeteredOrdMap,
+    Host, HostError,
 };
 use soroban_env_common::{Env, Symbol};
 use soroban_test_wasms::COMPLEX;
@@ -51,10 +51,8 @@ fn run_complex() -> Result<(), HostError> {
 
     // Run 2: enforce preflight footprint, with empty map -- contract should only write.
     {
-        let store = Storage::with_enforcing_footprint_and_map(
-            Footprint::default(),
-            MeteredOrdMap::default(),
-        );
+        let store =
+            Storage::with_enforcing_footprint_and_map(Footprint::default(), StorageMap::default());
         let host = ObservedHost::new(
             "soroban_env_host::test::complex::run_complex_2",
             Host::with_storage_and_budget(store, Budget::default()),
@@ -0,0 +1,932 @@
+use std::rc::Rc;
+
+use sha2::{Digest, Sha256};
+use soroban_test_wasms::ADD_I32;
+
+use crate::{
+    budget::Budget,
+    e2e_invoke::{
+        extend_footprint_ttl, invoke_host_function, restore_footprint,
+        simulate_invoke_host_function, BatchTransaction, InvocationResources,
+        TransactionBatchDriver,
+    },
+    fees::LedgerEntryRentChange,
+    invocation_bundle::{BudgetConfig, InvocationBundle},
+    testutils::MockSnapshotSource,
+    xdr::{
+        AccountId, ContractCodeEntry, ContractCostParamEntry, ContractCostParams, ContractCostType,
+        ContractDataDurability, ContractEvent, ContractEventBody, ContractEventType,
+        ContractEventV0, ContractExecutable, ContractIdPreimage, ContractIdPreimageFromAddress,
+        CreateContractArgs, DiagnosticEvent, ExtendFootprintTtlOp, ExtensionPoint, Hash,
+        HostFunction, InvokeContractArgs, LedgerEntry, LedgerEntryData, LedgerEntryExt,
+        LedgerFootprint, LedgerKey, LedgerKeyContractCode, LedgerKeyContractData, PublicKey,
+        ReadXdr, RestoreFootprintOp, ScAddress, ScErrorCode, ScErrorType, ScSymbol, ScVal,
+        SorobanAuthorizationEntry, SorobanCredentials, SorobanResources, TtlEntry, Uint256,
+        WriteXdr,
+    },
+    LedgerInfo, DEFAULT_XDR_RW_LIMITS,
+};
+
+const LEDGER_SEQ: u32 = 100;
+
+fn default_ledger_info() -> LedgerInfo {
+    LedgerInfo {
+        protocol_version: crate::meta::get_ledger_protocol_version(crate::meta::INTERFACE_VERSION),
+        sequence_number: LEDGER_SEQ,
+        timestamp: 12345,
+        network_id: [5; 32],
+        base_reserve: 5_000_000,
+        min_temp_entry_ttl: 16,
+        min_persistent_entry_ttl: 4096,
+        max_entry_ttl: 6_312_000,
+    }
+}
+
+fn source_account() -> AccountId {
+    AccountId(PublicKey::PublicKeyTypeEd25519(Uint256([1; 32])))
+}
+
+fn wasm_hash(wasm: &[u8]) -> Hash {
+    Hash(Sha256::digest(wasm).into())
+}
+
+fn wasm_code_key(wasm: &[u8]) -> LedgerKey {
+    LedgerKey::ContractCode(LedgerKeyContractCode {
+        hash: wasm_hash(wasm),
+    })
+}
+
+fn wasm_code_entry(wasm: &[u8]) -> LedgerEntry {
+    LedgerEntry {
+        last_modified_ledger_seq: 0,
+        data: LedgerEntryData::ContractCode(ContractCodeEntry {
+            ext: ExtensionPoint::V0,
+            hash: wasm_hash(wasm),
+            code: wasm.try_into().unwrap(),
+        }),
+        ext: LedgerEntryExt::V0,
+    }
+}
+
+fn encode<T: WriteXdr>(value: &T) -> Vec<u8> {
+    value.to_xdr(DEFAULT_XDR_RW_LIMITS).unwrap()
+}
+
+fn encoded_ttl_entry(key: &LedgerKey, live_until_ledger_seq: u32) -> Vec<u8> {
+    encode(&TtlEntry {
+        key_hash: Hash(Sha256::digest(encode(key)).into()),
+        live_until_ledger_seq,
+    })
+}
+
+fn rent_change_fields(change: &LedgerEntryRentChange) -> (bool, u32, u32, u32, u32) {
+    (
+        change.is_persistent,
+        change.old_size_bytes,
+        change.new_size_bytes,
+        change.old_live_until_ledger,
+        change.new_live_until_ledger,
+    )
+}
+
+fn resources_with_footprint(footprint: LedgerFootprint) -> SorobanResources {
+    SorobanResources {
+        footprint,
+        instructions: 0,
+        read_bytes: 0,
+        write_bytes: 0,
+    }
+}
+
+fn ttl_op_resources(read_only: Vec<LedgerKey>, read_write: Vec<LedgerKey>) -> SorobanResources {
+    SorobanResources {
+        footprint: LedgerFootprint {
+            read_only: read_only.try_into().unwrap(),
+            read_write: read_write.try_into().unwrap(),
+        },
+        instructions: 0,
+        read_bytes: 0,
+        write_bytes: 0,
+    }
+}
+
+#[test]
+fn test_simulate_upload_wasm() {
+    let snapshot = Rc::new(MockSnapshotSource::new());
+    let host_fn = HostFunction::UploadContractWasm(ADD_I32.try_into().unwrap());
+    let budget = Budget::default();
+    let mut diagnostic_events = vec![];
+    let res = simulate_invoke_host_function(
+        &budget,
+        true,
+        false,
+        encode(&host_fn),
+        encode(&source_account()),
+        default_ledger_info(),
+        snapshot,
+        [0; 32].to_vec(),
+        &mut diagnostic_events,
+    )
+    .unwrap();
+    assert!(res.encoded_invoke_result.is_ok());
+    assert!(res.footprint.read_only.is_empty());
+    assert_eq!(
+        res.footprint.read_write.to_vec(),
+        vec![wasm_code_key(ADD_I32)]
+    );
+    assert!(res.auth_entries.is_empty());
+    let entry_size = encode(&wasm_code_entry(ADD_I32)).len() as u32;
+    assert_eq!(
+        res.resources,
+        InvocationResources {
+            instructions: budget.get_cpu_insns_consumed().unwrap(),
+            mem_bytes: budget.get_mem_bytes_consumed().unwrap(),
+            read_entries: 0,
+            write_entries: 1,
+            read_bytes: 0,
+            write_bytes: entry_size,
+            contract_events_size_bytes: 0,
+            max_key_size_bytes: encode(&wasm_code_key(ADD_I32)).len() as u32,
+            max_entry_size_bytes: entry_size,
+        }
+    );
+    assert_eq!(res.ledger_changes.len(), 1);
+    assert!(res.encoded_contract_events.is_empty());
+
+    // The recorded footprint must be sufficient for running the same function
+    // in the enforcing mode and produce the same changes.
+    let resources = SorobanResources {
+        footprint: res.footprint.clone(),
+        instructions: res.resources.instructions as u32,
+        read_bytes: res.resources.read_bytes,
+        write_bytes: res.resources.write_bytes,
+    };
+    let enforcing_budget = Budget::default();
+    let enforcing_res = invoke_host_function(
+        &enforcing_budget,
+        true,
+        encode(&host_fn),
+        encode(&resources),
+        encode(&source_account()),
+        Vec::<Vec<u8>>::new().into_iter(),
+        default_ledger_info(),
+        Vec::<Vec<u8>>::new().into_iter(),
+        Vec::<Vec<u8>>::new().into_iter(),
+        [0; 32].to_vec(),
+        &mut diagnostic_events,
+    )
+    .unwrap();
+    assert!(enforcing_res.encoded_invoke_result.is_ok());
+    assert_eq!(enforcing_res.ledger_changes.len(), 1);
+    assert_eq!(
+        enforcing_res.ledger_changes[0].encoded_new_value,
+        res.ledger_changes[0].encoded_new_value
+    );
+    assert_eq!(
+        enforcing_res.resources,
+        InvocationResources {
+            instructions: enforcing_budget.get_cpu_insns_consumed().unwrap(),
+            mem_bytes: enforcing_budget.get_mem_bytes_consumed().unwrap(),
+            ..res.resources.clone()
+        }
+    );
+    let tx_resources = enforcing_res.resources.to_transaction_resources(1000);
+    assert_eq!(tx_resources.write_entries, 1);
+    assert_eq!(tx_resources.write_bytes, entry_size);
+    assert_eq!(tx_resources.transaction_size_bytes, 1000);
+}
+
+#[test]
+fn test_simulate_create_contract_records_auth() {
+    let snapshot = Rc::new(
+        MockSnapshotSource::from_entries(vec![(wasm_code_entry(ADD_I32), Some(LEDGER_SEQ + 1000))])
+            .unwrap(),
+    );
+    let host_fn = HostFunction::CreateContract(CreateContractArgs {
+        contract_id_preimage: ContractIdPreimage::Address(ContractIdPreimageFromAddress {
+            address: ScAddress::Account(source_account()),
+            salt: Uint256([2; 32]),
+        }),
+        executable: ContractExecutable::Wasm(wasm_hash(ADD_I32)),
+    });
+    let mut diagnostic_events = vec![];
+    let res = simulate_invoke_host_function(
+        &Budget::default(),
+        false,
+        false,
+        encode(&host_fn),
+        encode(&source_account()),
+        default_ledger_info(),
+        snapshot,
+        [0; 32].to_vec(),
+        &mut diagnostic_events,
+    )
+    .unwrap();
+    assert!(res.encoded_invoke_result.is_ok());
+    assert_eq!(
+        res.footprint.read_only.to_vec(),
+        vec![wasm_code_key(ADD_I32)]
+    );
+    assert_eq!(res.footprint.read_write.len(), 1);
+    assert_eq!(res.auth_entries.len(), 1);
+    assert_eq!(
+        res.auth_entries[0].credentials,
+        SorobanCredentials::SourceAccount
+    );
+    assert_eq!(res.resources.read_entries, 1);
+    assert_eq!(res.resources.write_entries, 1);
+    assert_eq!(
+        res.resources.read_bytes as usize,
+        encode(&wasm_code_entry(ADD_I32)).len()
+    );
+    assert!(res.resources.write_bytes > 0);
+    assert!(diagnostic_events.is_empty());
+}
+
+#[test]
+fn test_simulate_failed_invocation() {
+    let snapshot = Rc::new(MockSnapshotSource::new());
+    let contract = ScAddress::Contract(Hash([3; 32]));
+    let host_fn = HostFunction::InvokeContract(InvokeContractArgs {
+        contract_address: contract.clone(),
+        function_name: ScSymbol("add".try_into().unwrap()),
+        args: vec![ScVal::I32(1), ScVal::I32(2)].try_into().unwrap(),
+    });
+    let budget = Budget::default();
+    let mut diagnostic_events = vec![];
+    let res = simulate_invoke_host_function(
+        &budget,
+        true,
+        false,
+        encode(&host_fn),
+        encode(&source_account()),
+        default_ledger_info(),
+        snapshot,
+        [0; 32].to_vec(),
+        &mut diagnostic_events,
+    )
+    .unwrap();
+    // The failure is reported in the result along with the footprint and the
+    // resources recorded until the failure.
+    assert!(res.encoded_invoke_result.is_err());
+    assert_eq!(
+        res.footprint.read_only.to_vec(),
+        vec![LedgerKey::ContractData(LedgerKeyContractData {
+            contract,
+            key: ScVal::LedgerKeyContractInstance,
+            durability: ContractDataDurability::Persistent,
+        })]
+    );
+    assert!(res.footprint.read_write.is_empty());
+    assert!(res.auth_entries.is_empty());
+    assert!(res.ledger_changes.is_empty());
+    assert!(res.encoded_contract_events.is_empty());
+    assert_eq!(
+        res.resources.instructions,
+        budget.get_cpu_insns_consumed().unwrap()
+    );
+    assert_eq!(res.resources.read_entries, 1);
+    assert!(!diagnostic_events.is_empty());
+}
+
+#[test]
+fn test_extend_footprint_ttl() {
+    let key = wasm_code_key(ADD_I32);
+    let entry = encode(&wasm_code_entry(ADD_I32));
+    let op = ExtendFootprintTtlOp {
+        ext: ExtensionPoint::V0,
+        extend_to: 10_000,
+    };
+    let res = extend_footprint_ttl(
+        &Budget::default(),
+        encode(&op),
+        encode(&ttl_op_resources(vec![key.clone()], vec![])),
+        default_ledger_info(),
+        vec![entry.clone()].into_iter(),
+        vec![encoded_ttl_entry(&key, LEDGER_SEQ + 100)].into_iter(),
+    )
+    .unwrap();
+    assert_eq!(res.rent_changes.len(), 1);
+    assert_eq!(
+        rent_change_fields(&res.rent_changes[0]),
+        (
+            true,
+            entry.len() as u32,
+            entry.len() as u32,
+            LEDGER_SEQ + 100,
+            LEDGER_SEQ + 10_000
+        )
+    );
+    assert_eq!(res.ledger_changes.len(), 1);
+    let change = &res.ledger_changes[0];
+    assert!(change.read_only);
+    assert!(change.encoded_new_value.is_none());
+    let ttl_change = change.ttl_change.as_ref().unwrap();
+    assert_eq!(ttl_change.old_live_until_ledger, LEDGER_SEQ + 100);
+    assert_eq!(ttl_change.new_live_until_ledger, LEDGER_SEQ + 10_000);
+
+    // Entries that already live long enough are not extended.
+    let res = extend_footprint_ttl(
+        &Budget::default(),
+        encode(&op),
+        encode(&ttl_op_resources(vec![key.clone()], vec![])),
+        default_ledger_info(),
+        vec![entry.clone()].into_iter(),
+        vec![encoded_ttl_entry(&key, LEDGER_SEQ + 20_000)].into_iter(),
+    )
+    .unwrap();
+    assert!(res.rent_changes.is_empty());
+
+    // Extension beyond the max TTL is not allowed.
+    let op = ExtendFootprintTtlOp {
+        ext: ExtensionPoint::V0,
+        extend_to: default_ledger_info().max_entry_ttl,
+    };
+    assert!(extend_footprint_ttl(
+        &Budget::default(),
+        encode(&op),
+        encode(&ttl_op_resources(vec![key.clone()], vec![])),
+        default_ledger_info(),
+        vec![entry.clone()].into_iter(),
+        vec![encoded_ttl_entry(&key, LEDGER_SEQ + 100)].into_iter(),
+    )
+    .is_err());
+
+    // Read-write footprint is not allowed.
+    let op = ExtendFootprintTtlOp {
+        ext: ExtensionPoint::V0,
+        extend_to: 10_000,
+    };
+    assert!(extend_footprint_ttl(
+        &Budget::default(),
+        encode(&op),
+        encode(&ttl_op_resources(vec![], vec![key.clone()])),
+        default_ledger_info(),
+        vec![entry].into_iter(),
+        vec![encoded_ttl_entry(&key, LEDGER_SEQ + 100)].into_iter(),
+    )
+    .is_err());
+}
+
+#[test]
+fn test_restore_footprint() {
+    let key = wasm_code_key(ADD_I32);
+    let entry = encode(&wasm_code_entry(ADD_I32));
+    let op = RestoreFootprintOp {
+        ext: ExtensionPoint::V0,
+    };
+    let ledger_info = default_ledger_info();
+    let res = restore_footprint(
+        &Budget::default(),
+        encode(&op),
+        encode(&ttl_op_resources(vec![], vec![key.clone()])),
+        ledger_info.clone(),
+        vec![entry.clone()].into_iter(),
+        vec![encoded_ttl_entry(&key, LEDGER_SEQ - 1)].into_iter(),
+    )
+    .unwrap();
+    let restored_live_until_ledger = LEDGER_SEQ + ledger_info.min_persistent_entry_ttl - 1;
+    assert_eq!(res.rent_changes.len(), 1);
+    assert_eq!(
+        rent_change_fields(&res.rent_changes[0]),
+        (true, 0, entry.len() as u32, 0, restored_live_until_ledger)
+    );
+    assert_eq!(res.ledger_changes.len(), 1);
+    let ttl_change = res.ledger_changes[0].ttl_change.as_ref().unwrap();
+    assert_eq!(ttl_change.old_live_until_ledger, LEDGER_SEQ - 1);
+    assert_eq!(ttl_change.new_live_until_ledger, restored_live_until_ledger);
+
+    // Live entries don't need to be restored.
+    let res = restore_footprint(
+        &Budget::default(),
+        encode(&op),
+        encode(&ttl_op_resources(vec![], vec![key.clone()])),
+        ledger_info.clone(),
+        vec![entry.clone()].into_iter(),
+        vec![encoded_ttl_entry(&key, LEDGER_SEQ)].into_iter(),
+    )
+    .unwrap();
+    assert!(res.rent_changes.is_empty());
+
+    // Read-only footprint is not allowed.
+    assert!(restore_footprint(
+        &Budget::default(),
+        encode(&op),
+        encode(&ttl_op_resources(vec![key.clone()], vec![])),
+        ledger_info,
+        vec![entry].into_iter(),
+        vec![encoded_ttl_entry(&key, LEDGER_SEQ - 1)].into_iter(),
+    )
+    .is_err());
+}
+
+fn batch_transaction(
+    host_fn: &HostFunction,
+    footprint: LedgerFootprint,
+    auth_entries: &[SorobanAuthorizationEntry],
+) -> BatchTransaction {
+    BatchTransaction {
+        budget: Budget::default(),
+        enable_diagnostics: false,
+        encoded_host_fn: encode(host_fn),
+        encoded_resources: encode(&resources_with_footprint(footprint)),
+        encoded_source_account: encode(&source_account()),
+        encoded_auth_entries: auth_entries.iter().map(encode).collect(),
+        base_prng_seed: [0; 32].to_vec(),
+    }
+}
+
+#[test]
+fn test_batch_driver_applies_dependent_transactions() {
+    let upload_fn = HostFunction::UploadContractWasm(ADD_I32.try_into().unwrap());
+    let create_fn = HostFunction::CreateContract(CreateContractArgs {
+        contract_id_preimage: ContractIdPreimage::Address(ContractIdPreimageFromAddress {
+            address: ScAddress::Account(source_account()),
+            salt: Uint256([2; 32]),
+        }),
+        executable: ContractExecutable::Wasm(wasm_hash(ADD_I32)),
+    });
+    // Simulate contract creation on a snapshot with the Wasm already uploaded
+    // in order to get its footprint and auth.
+    let create_sim = simulate_invoke_host_function(
+        &Budget::default(),
+        false,
+        false,
+        encode(&create_fn),
+        encode(&source_account()),
+        default_ledger_info(),
+        Rc::new(
+            MockSnapshotSource::from_entries(vec![(
+                wasm_code_entry(ADD_I32),
+                Some(LEDGER_SEQ + 1000),
+            )])
+            .unwrap(),
+        ),
+        [0; 32].to_vec(),
+        &mut vec![],
+    )
+    .unwrap();
+    let contract_address = match ScVal::from_xdr(
+        create_sim.encoded_invoke_result.unwrap(),
+        DEFAULT_XDR_RW_LIMITS,
+    )
+    .unwrap()
+    {
+        ScVal::Address(address) => address,
+        _ => panic!("unexpected create contract result"),
+    };
+
+    let mut driver =
+        TransactionBatchDriver::new(default_ledger_info(), Rc::new(MockSnapshotSource::new()));
+    let results = driver.apply_transactions(&[
+        batch_transaction(
+            &upload_fn,
+            LedgerFootprint {
+                read_only: Default::default(),
+                read_write: vec![wasm_code_key(ADD_I32)].try_into().unwrap(),
+            },
+            &[],
+        ),
+        batch_transaction(
+            &create_fn,
+            create_sim.footprint.clone(),
+            &create_sim.auth_entries,
+        ),
+        // Contract creation is not idempotent, so the same transaction fails
+        // when applied for the second time.
+        batch_transaction(&create_fn, create_sim.footprint, &create_sim.auth_entries),
+    ]);
+    assert_eq!(results.len(), 3);
+    assert!(results[0]
+        .result
+        .as_ref()
+        .unwrap()
+        .encoded_invoke_result
+        .is_ok());
+    assert!(results[1]
+        .result
+        .as_ref()
+        .unwrap()
+        .encoded_invoke_result
+        .is_ok());
+    assert!(results[2].result.is_err());
+    // The uploaded code and the contract instance are now in the ledger view.
+    let updated_entries: Vec<_> = driver
+        .ledger()
+        .updated_entries()
+        .map(|(_, entry)| entry.clone().unwrap())
+        .collect();
+    assert_eq!(updated_entries.len(), 2);
+    for (_, live_until_ledger) in &updated_entries {
+        assert_eq!(
+            *live_until_ledger,
+            Some(LEDGER_SEQ + default_ledger_info().min_persistent_entry_ttl - 1)
+        );
+    }
+
+    // Invoke the created contract, using the footprint simulated against the
+    // current state of the ledger view.
+    let invoke_fn = HostFunction::InvokeContract(InvokeContractArgs {
+        contract_address,
+        function_name: ScSymbol("add".try_into().unwrap()),
+        args: vec![ScVal::I32(1), ScVal::I32(2)].try_into().unwrap(),
+    });
+    let invoke_sim = simulate_invoke_host_function(
+        &Budget::default(),
+        false,
+        false,
+        encode(&invoke_fn),
+        encode(&source_account()),
+        default_ledger_info(),
+        Rc::new(
+            MockSnapshotSource::from_entries(
+                updated_entries
+                    .into_iter()
+                    .map(|(entry, live_until_ledger)| (entry.as_ref().clone(), live_until_ledger))
+                    .collect(),
+            )
+            .unwrap(),
+        ),
+        [0; 32].to_vec(),
+        &mut vec![],
+    )
+    .unwrap();
+    let res = driver.apply_transaction(&batch_transaction(
+        &invoke_fn,
+        invoke_sim.footprint,
+        &invoke_sim.auth_entries,
+    ));
+    assert_eq!(
+        res.result.unwrap().encoded_invoke_result.unwrap(),
+        encode(&ScVal::I32(3))
+    );
+}
+
+fn test_budget_config() -> BudgetConfig {
+    let cost_params = ContractCostParams(
+        ContractCostType::variants()
+            .iter()
+            .map(|_| ContractCostParamEntry {
+                ext: ExtensionPoint::V0,
+                const_term: 100,
+                linear_term: 1,
+            })
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap(),
+    );
+    BudgetConfig {
+        cpu_limit: 100_000_000,
+        mem_limit: 40 * 1024 * 1024,
+        cpu_cost_params: cost_params.clone(),
+        mem_cost_params: cost_params,
+    }
+}
+
+fn upload_wasm_bundle() -> InvocationBundle {
+    let host_fn = HostFunction::UploadContractWasm(ADD_I32.try_into().unwrap());
+    InvocationBundle::new(
+        true,
+        encode(&host_fn),
+        encode(&resources_with_footprint(LedgerFootprint {
+            read_only: Default::default(),
+            read_write: vec![wasm_code_key(ADD_I32)].try_into().unwrap(),
+        })),
+        encode(&source_account()),
+        vec![],
+        default_ledger_info(),
+        vec![],
+        vec![],
+        [7; 32].to_vec(),
+        test_budget_config(),
+    )
+}
+
+#[test]
+fn test_invocation_bundle_capture_and_replay() {
+    let mut bundle = upload_wasm_bundle();
+    let mut diagnostic_events = vec![];
+    let res = bundle.invoke_and_record(&mut diagnostic_events).unwrap();
+    assert!(res.encoded_invoke_result.is_ok());
+    let outcome = bundle.outcome.clone().unwrap();
+    assert_eq!(outcome.ledger_changes, res.ledger_changes);
+    assert_eq!(outcome.diagnostic_events, diagnostic_events);
+
+    let report = bundle.replay();
+    assert!(report.is_identical(), "{:?}", report.mismatches);
+    assert!(!report.host_version_differs);
+
+    // Tampering with the recorded outcome is detected by the replay.
+    let mut tampered = bundle.clone();
+    tampered.outcome.as_mut().unwrap().ledger_changes[0].old_entry_size_bytes = 1;
+    let report = tampered.replay();
+    assert_eq!(report.mismatches.len(), 1);
+
+    // Errors are compared beyond their `ScError` via the diagnostic events.
+    let mut tampered = bundle.clone();
+    tampered
+        .outcome
+        .as_mut()
+        .unwrap()
+        .diagnostic_events
+        .push(DiagnosticEvent {
+            in_successful_contract_call: false,
+            event: ContractEvent {
+                ext: ExtensionPoint::V0,
+                contract_id: None,
+                type_: ContractEventType::Diagnostic,
+                body: ContractEventBody::V0(ContractEventV0 {
+                    topics: Default::default(),
+                    data: ScVal::Void,
+                }),
+            },
+        });
+    let report = tampered.replay();
+    assert_eq!(report.mismatches, vec!["diagnostic events differ"]);
+
+    // Bundles without the outcome can't be verified.
+    let mut no_outcome = bundle;
+    no_outcome.outcome = None;
+    assert!(!no_outcome.replay().is_identical());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_invocation_bundle_file_round_trip() {
+    let mut bundle = upload_wasm_bundle();
+    bundle.invoke_and_record(&mut vec![]).unwrap();
+
+    let mut buf = vec![];
+    bundle.write_to(&mut buf).unwrap();
+    let restored = InvocationBundle::read_from(buf.as_slice()).unwrap();
+    assert_eq!(restored.outcome, bundle.outcome);
+    assert_eq!(restored.encoded_host_fn, bundle.encoded_host_fn);
+    assert_eq!(restored.budget_config, bundle.budget_config);
+    assert_eq!(
+        restored.ledger_info.network_id,
+        bundle.ledger_info.network_id
+    );
+    // Writing the restored bundle again must produce the same bytes.
+    let mut buf2 = vec![];
+    restored.write_to(&mut buf2).unwrap();
+    assert_eq!(buf, buf2);
+    let report = restored.replay();
+    assert!(report.is_identical(), "{:?}", report.mismatches);
+
+    assert!(InvocationBundle::read_from(&b"not a bundle"[..]).is_err());
+    let mut json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+    json["format"] = "soroban-invocation-bundle v0".into();
+    assert!(InvocationBundle::read_from(json.to_string().as_bytes()).is_err());
+}
+
+#[test]
+fn test_invoke_fails_on_expired_entries() {
+    let code_key = wasm_code_key(ADD_I32);
+    let resources = resources_with_footprint(LedgerFootprint {
+        read_only: Default::default(),
+        read_write: vec![code_key.clone()].try_into().unwrap(),
+    });
+    let err = invoke_host_function(
+        &Budget::default(),
+        true,
+        encode(&HostFunction::UploadContractWasm(
+            ADD_I32.try_into().unwrap(),
+        )),
+        encode(&resources),
+        encode(&source_account()),
+        Vec::<Vec<u8>>::new().into_iter(),
+        default_ledger_info(),
+        vec![encode(&wasm_code_entry(ADD_I32))].into_iter(),
+        vec![encoded_ttl_entry(&code_key, LEDGER_SEQ - 1)].into_iter(),
+        [0; 32].to_vec(),
+        &mut vec![],
+    )
+    .err()
+    .unwrap();
+    assert!(err.error.is_type(ScErrorType::Storage));
+    assert!(err.error.is_code(ScErrorCode::InternalError));
+}
+
+#[test]
+fn test_simulate_reports_archived_entries() {
+    let code_key = wasm_code_key(ADD_I32);
+    let snapshot = Rc::new(
+        MockSnapshotSource::from_entries(vec![(wasm_code_entry(ADD_I32), Some(LEDGER_SEQ - 1))])
+            .unwrap(),
+    );
+    let mut diagnostic_events = vec![];
+    let res = simulate_invoke_host_function(
+        &Budget::default(),
+        true,
+        false,
+        encode(&HostFunction::UploadContractWasm(
+            ADD_I32.try_into().unwrap(),
+        )),
+        encode(&source_account()),
+        default_ledger_info(),
+        snapshot,
+        [0; 32].to_vec(),
+        &mut diagnostic_events,
+    )
+    .unwrap();
+    assert!(res.encoded_invoke_result.is_ok());
+    assert_eq!(res.encoded_archived_keys, vec![encode(&code_key)]);
+    assert!(format!("{:?}", diagnostic_events).contains("archived persistent entries"));
+
+    // The archived keys are sufficient for building the restoration footprint.
+    let archived_keys: Vec<LedgerKey> = res
+        .encoded_archived_keys
+        .iter()
+        .map(|k| LedgerKey::from_xdr(k, DEFAULT_XDR_RW_LIMITS).unwrap())
+        .collect();
+    let restore_res = restore_footprint(
+        &Budget::default(),
+        encode(&RestoreFootprintOp {
+            ext: ExtensionPoint::V0,
+        }),
+        encode(&ttl_op_resources(vec![], archived_keys)),
+        default_ledger_info(),
+        vec![encode(&wasm_code_entry(ADD_I32))].into_iter(),
+        vec![encoded_ttl_entry(&code_key, LEDGER_SEQ - 1)].into_iter(),
+    )
+    .unwrap();
+    assert_eq!(restore_res.rent_changes.len(), 1);
+}
+
+#[test]
+fn test_invoke_reports_unused_footprint_keys_with_diagnostics() {
+    let code_key = wasm_code_key(ADD_I32);
+    let unused_key = LedgerKey::ContractData(LedgerKeyContractData {
+        contract: ScAddress::Contract(Hash([3; 32])),
+        key: ScVal::U32(1),
+        durability: ContractDataDurability::Persistent,
+    });
+    let resources = resources_with_footprint(LedgerFootprint {
+        read_only: vec![unused_key.clone()].try_into().unwrap(),
+        read_write: vec![code_key].try_into().unwrap(),
+    });
+    let invoke = |enable_diagnostics: bool| {
+        invoke_host_function(
+            &Budget::default(),
+            enable_diagnostics,
+            encode(&HostFunction::UploadContractWasm(
+                ADD_I32.try_into().unwrap(),
+            )),
+            encode(&resources),
+            encode(&source_account()),
+            Vec::<Vec<u8>>::new().into_iter(),
+            default_ledger_info(),
+            Vec::<Vec<u8>>::new().into_iter(),
+            Vec::<Vec<u8>>::new().into_iter(),
+            [0; 32].to_vec(),
+            &mut vec![],
+        )
+        .unwrap()
+    };
+
+    let res = invoke(true);
+    assert!(res.encoded_invoke_result.is_ok());
+    assert_eq!(res.encoded_unused_keys, vec![encode(&unused_key)]);
+    assert!(res.encoded_unwritten_read_write_keys.is_empty());
+
+    // The accesses are not tracked without diagnostics.
+    let res = invoke(false);
+    assert!(res.encoded_invoke_result.is_ok());
+    assert!(res.encoded_unused_keys.is_empty());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_json_encoding() {
+    use crate::e2e_invoke::{InvokeHostFunctionResult, LedgerEntryChange};
+
+    let host_fn = HostFunction::UploadContractWasm(ADD_I32.try_into().unwrap());
+    let res = invoke_host_function(
+        &Budget::default(),
+        false,
+        encode(&host_fn),
+        encode(&resources_with_footprint(LedgerFootprint {
+            read_only: Default::default(),
+            read_write: vec![wasm_code_key(ADD_I32)].try_into().unwrap(),
+        })),
+        encode(&source_account()),
+        Vec::<Vec<u8>>::new().into_iter(),
+        default_ledger_info(),
+        Vec::<Vec<u8>>::new().into_iter(),
+        Vec::<Vec<u8>>::new().into_iter(),
+        [0; 32].to_vec(),
+        &mut vec![],
+    )
+    .unwrap();
+    let json = serde_json::to_value(&res).unwrap();
+    assert!(json["encoded_invoke_result"]["ok"].is_string());
+    let key_hex = hex::encode(encode(&wasm_code_key(ADD_I32)));
+    assert_eq!(json["ledger_changes"][0]["encoded_key"], key_hex.as_str());
+    assert_eq!(
+        json["ledger_changes"][0]["ttl_change"]["durability"],
+        "persistent"
+    );
+
+    // Plain data types round-trip through JSON.
+    let change: LedgerEntryChange =
+        serde_json::from_value(json["ledger_changes"][0].clone()).unwrap();
+    assert_eq!(change, res.ledger_changes[0]);
+    let ledger_info: LedgerInfo =
+        serde_json::from_str(&serde_json::to_string(&default_ledger_info()).unwrap()).unwrap();
+    assert_eq!(ledger_info.network_id, default_ledger_info().network_id);
+
+    // The results round-trip as well, with the errors reduced to `ScError`.
+    let restored: InvokeHostFunctionResult = serde_json::from_value(json).unwrap();
+    assert_eq!(
+        restored.encoded_invoke_result.ok(),
+        res.encoded_invoke_result.ok()
+    );
+    assert_eq!(restored.ledger_changes, res.ledger_changes);
+    assert_eq!(restored.resources, res.resources);
+    let failed = InvokeHostFunctionResult {
+        encoded_invoke_result: Err((ScErrorType::Budget, ScErrorCode::ExceededLimit).into()),
+        ledger_changes: vec![],
+        encoded_contract_events: vec![],
+        resources: Default::default(),
+        encoded_unused_keys: vec![],
+        encoded_unwritten_read_write_keys: vec![],
+    };
+    let restored: InvokeHostFunctionResult =
+        serde_json::from_str(&serde_json::to_string(&failed).unwrap()).unwrap();
+    assert_eq!(
+        restored.encoded_invoke_result.err().unwrap().error,
+        failed.encoded_invoke_result.err().unwrap().error
+    );
+}
+
+#[cfg(feature = "file_snapshot")]
+#[test]
+fn test_file_and_layered_snapshot_sources() {
+    use crate::{
+        e2e_invoke::LedgerView, snapshot_source::FileSnapshotSource, storage::SnapshotSource,
+    };
+
+    let code_key = Rc::new(wasm_code_key(ADD_I32));
+    let ttl_entry = LedgerEntry {
+        last_modified_ledger_seq: 0,
+        data: LedgerEntryData::Ttl(TtlEntry {
+            key_hash: Hash(Sha256::digest(encode(code_key.as_ref())).into()),
+            live_until_ledger_seq: LEDGER_SEQ + 1000,
+        }),
+        ext: LedgerEntryExt::V0,
+    };
+    let code_base64 = wasm_code_entry(ADD_I32)
+        .to_xdr_base64(DEFAULT_XDR_RW_LIMITS)
+        .unwrap();
+    let ttl_base64 = ttl_entry.to_xdr_base64(DEFAULT_XDR_RW_LIMITS).unwrap();
+
+    // TTL entries are matched to their entries regardless of the order.
+    let lines = format!("# captured entries\n{ttl_base64}\n\n{code_base64}\n");
+    let from_lines = FileSnapshotSource::from_str_contents(&lines).unwrap();
+    assert_eq!(from_lines.len(), 1);
+    let (entry, live_until_ledger) = from_lines.get(&code_key).unwrap();
+    assert_eq!(entry.as_ref(), &wasm_code_entry(ADD_I32));
+    assert_eq!(live_until_ledger, Some(LEDGER_SEQ + 1000));
+
+    // JSON arrays may mix base64 strings and the XDR JSON representation.
+    let json = serde_json::to_string(&vec![
+        serde_json::Value::String(ttl_base64.clone()),
+        serde_json::to_value(wasm_code_entry(ADD_I32)).unwrap(),
+    ])
+    .unwrap();
+    let from_json = FileSnapshotSource::from_str_contents(&json).unwrap();
+    assert_eq!(from_json.get(&code_key).unwrap().1, Some(LEDGER_SEQ + 1000));
+
+    // Written snapshots can be loaded back.
+    let mut written = vec![];
+    from_lines.write_to(&mut written).unwrap();
+    let reloaded =
+        FileSnapshotSource::from_str_contents(std::str::from_utf8(&written).unwrap()).unwrap();
+    assert_eq!(
+        reloaded.iter().collect::<Vec<_>>(),
+        from_lines.iter().collect::<Vec<_>>()
+    );
+
+    assert!(FileSnapshotSource::from_str_contents("not base64!").is_err());
+
+    // Ledger view updates shadow the base snapshot without modifying it.
+    let base = Rc::new(from_lines);
+    let mut layered = LedgerView::new(base.clone());
+    layered
+        .set_live_until_ledger(&code_key, LEDGER_SEQ + 2000)
+        .unwrap();
+    assert_eq!(layered.get(&code_key).unwrap().1, Some(LEDGER_SEQ + 2000));
+    assert_eq!(base.get(&code_key).unwrap().1, Some(LEDGER_SEQ + 1000));
+
+    layered.del(&code_key);
+    assert!(!layered.has(&code_key).unwrap());
+    assert!(layered.get(&code_key).is_err());
+    assert!(base.has(&code_key).unwrap());
+    assert_eq!(layered.updated_entries().count(), 1);
+
+    layered.put(wasm_code_entry(ADD_I32), None).unwrap();
+    assert_eq!(layered.get(&code_key).unwrap().1, None);
+
+    layered.discard_updated_entries();
+    assert_eq!(layered.get(&code_key).unwrap().1, Some(LEDGER_SEQ + 1000));
+}
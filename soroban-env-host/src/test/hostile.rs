@@ -657,6 +657,43 @@ fn test_unreachable_contract_should_fail() -> Result<(), HostError> {
     Ok(())
 }
 
+#[test]
+fn test_unreachable_contract_has_invocation_stack() -> Result<(), HostError> {
+    let mut wasm = wasm_util::wasm_module_with_unreachable();
+    // Append a `name` section with a function names subsection (id 1) that
+    // has a single entry naming the function 0.
+    let func_names = [&[1u8, 0, 9][..], b"test_impl"].concat();
+    let mut name_section = [&[4u8][..], b"name"].concat();
+    name_section.extend_from_slice(&[1, func_names.len() as u8]);
+    name_section.extend_from_slice(&func_names);
+    wasm.extend_from_slice(&[0, name_section.len() as u8]);
+    wasm.extend_from_slice(&name_section);
+
+    let host = Host::test_host_with_recording_footprint();
+    host.enable_debug()?;
+    let contract_id_obj = host.register_test_contract_wasm(wasm.as_slice());
+    let contract_id = host.contract_id_from_address(contract_id_obj)?;
+
+    let res = host.call(
+        contract_id_obj,
+        Symbol::try_from_small_str("test")?,
+        host.test_vec_obj::<u32>(&[])?,
+    );
+    let err = res.unwrap_err();
+    assert!(err.error.is_type(ScErrorType::WasmVm));
+    let stack = err.contract_invocation_stack().unwrap();
+    assert_eq!(stack.frames.len(), 1);
+    let frame = &stack.frames[0];
+    assert_eq!(frame.contract_id, contract_id);
+    assert_eq!(frame.export, "test");
+    assert_eq!(frame.invoked_func_index, Some(0));
+    assert_eq!(frame.invoked_func_name.as_deref(), Some("test_impl"));
+    assert!(format!("{:?}", err)
+        .contains("Contract invocation stack (newest first, invoked functions only):"));
+    assert!(format!("{:?}", host.get_events()?).contains("test (invoked func[0] <test_impl>)"));
+    Ok(())
+}
+
 #[test]
 fn test_indirect_call_via_table_access() -> Result<(), HostError> {
     // this module contains a table with 128 FuncRef elements, 3 of which are
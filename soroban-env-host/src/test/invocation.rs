@@ -468,3 +468,116 @@ fn guest_error() -> Result<(), HostError> {
     );
     Ok(())
 }
+
+#[test]
+fn contract_spec_validation() -> Result<(), HostError> {
+    use crate::contract_spec::{ContractSpec, ContractSpecMismatch};
+
+    let host = Host::test_host_with_recording_footprint();
+    host.set_diagnostic_level(crate::DiagnosticLevel::Debug)?;
+    let contract_id_obj = host.register_test_contract_wasm(ADD_I32);
+    let add = Symbol::try_from_small_str("add")?;
+
+    let vm = crate::Vm::new(&host, xdr::Hash([0; 32]), ADD_I32)?;
+    let spec = ContractSpec::from_vm(&vm)?.unwrap();
+    assert_eq!(spec.function("add").unwrap().inputs.len(), 2);
+    assert_eq!(
+        spec.check_args("add", &[xdr::ScVal::I32(1)]),
+        Err(ContractSpecMismatch::ArgCount {
+            function: "add".to_string(),
+            expected: 2,
+            got: 1
+        })
+    );
+
+    let invoke_add = |args: Vec<xdr::ScVal>| {
+        host.invoke_function(xdr::HostFunction::InvokeContract(xdr::InvokeContractArgs {
+            contract_address: host.scaddress_from_address(contract_id_obj)?,
+            function_name: "add".try_into()?,
+            args: args.try_into()?,
+        }))
+    };
+
+    host.enable_contract_spec_validation(true)?;
+    let res = invoke_add(vec![xdr::ScVal::I32(1), xdr::ScVal::I32(2)])?;
+    assert_eq!(res, xdr::ScVal::I32(3));
+
+    let res = invoke_add(vec![xdr::ScVal::I32(1), xdr::ScVal::U64(2)]);
+    assert!(HostError::result_matches_err(
+        res,
+        (ScErrorType::Value, ScErrorCode::UnexpectedType)
+    ));
+    let events = format!("{:?}", host.get_events()?);
+    assert!(events.contains("arg 1 of `add` expected i32, got u64"));
+    // The spec is parsed once per contract code.
+    assert_eq!(host.try_borrow_contract_specs()?.as_ref().unwrap().len(), 1);
+
+    // Only the top-level invocation is checked, so the guest fails to convert
+    // the argument of a call that doesn't come from the host function.
+    let res = host.call(contract_id_obj, add, test_vec![&host, 1_i32, 2_u64].into());
+    assert!(!HostError::result_matches_err(
+        res,
+        (ScErrorType::Value, ScErrorCode::UnexpectedType)
+    ));
+
+    // Without the validation the guest fails to convert the argument as well.
+    host.enable_contract_spec_validation(false)?;
+    let res = invoke_add(vec![xdr::ScVal::I32(1), xdr::ScVal::U64(2)]);
+    assert!(!HostError::result_matches_err(
+        res,
+        (ScErrorType::Value, ScErrorCode::UnexpectedType)
+    ));
+    Ok(())
+}
+
+#[cfg(feature = "next")]
+#[test]
+fn try_call_with_budget_recovers_from_exceeded_limits() -> Result<(), HostError> {
+    use soroban_env_common::U64Val;
+    use soroban_test_wasms::HOSTILE;
+
+    let host = Host::test_host_with_recording_footprint();
+    let hostile_id_obj = host.register_test_contract_wasm(HOSTILE);
+    let add_id_obj = host.register_test_contract_wasm(ADD_I32);
+    let limit = |n: u64| U64Val::try_from_val(&host, &n);
+
+    // The infinite loop only exhausts the limits of the call.
+    let cpu_limit = 1_000_000;
+    let cpu_before = host.as_budget().get_cpu_insns_consumed()?;
+    let res = host.try_call_with_budget(
+        hostile_id_obj,
+        Symbol::try_from_small_str("iloop")?,
+        host.test_vec_obj::<u32>(&[])?,
+        limit(cpu_limit)?,
+        limit(10_000_000)?,
+    )?;
+    let err = Error::try_from(res)?;
+    assert!(err.is_type(ScErrorType::Budget));
+    assert!(err.is_code(ScErrorCode::ExceededLimit));
+    assert!(host.as_budget().get_cpu_insns_consumed()? <= cpu_before + cpu_limit);
+
+    // The rest of the budget is still available.
+    let res = host.try_call_with_budget(
+        add_id_obj,
+        Symbol::try_from_small_str("add")?,
+        host.test_vec_obj(&[1_i32, 2_i32])?,
+        limit(cpu_limit)?,
+        limit(10_000_000)?,
+    )?;
+    assert_eq!(i32::try_from_val(&host, &res)?, 3);
+
+    // Running out of the whole budget is still unrecoverable.
+    host.as_budget().reset_limits(100_000, 10_000_000)?;
+    let res = host.try_call_with_budget(
+        hostile_id_obj,
+        Symbol::try_from_small_str("iloop")?,
+        host.test_vec_obj::<u32>(&[])?,
+        limit(cpu_limit)?,
+        limit(10_000_000)?,
+    );
+    assert!(HostError::result_matches_err(
+        res,
+        (ScErrorType::Budget, ScErrorCode::ExceededLimit)
+    ));
+    Ok(())
+}
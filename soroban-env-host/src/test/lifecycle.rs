@@ -553,6 +553,85 @@ fn test_invalid_contract() {
     assert!(err.error.is_code(ScErrorCode::InvalidAction));
 }
 
+#[test]
+fn test_contract_import_validation() {
+    use soroban_synth_wasm::{Arity, ModEmitter};
+
+    let wasm_with_import = |module: &str, fname: &str, arity: u32| {
+        let mut me = ModEmitter::default();
+        me.import_func(module, fname, Arity(arity));
+        me.func(Arity(0), 0).finish_and_export("test").finish()
+    };
+    let upload = |wasm: Vec<u8>| {
+        let host = Host::test_host_with_recording_footprint();
+        host.set_diagnostic_level(crate::DiagnosticLevel::Debug)
+            .unwrap();
+        let res = host.invoke_function(HostFunction::UploadContractWasm(wasm.try_into().unwrap()));
+        (res, format!("{:?}", host.get_events().unwrap()))
+    };
+
+    // `i._` is `obj_from_u64`, which takes a single argument.
+    let (res, _) = upload(wasm_with_import("i", "_", 1));
+    assert!(res.is_ok());
+
+    let (res, events) = upload(wasm_with_import("i", "zz", 1));
+    let err = res.err().unwrap();
+    assert!(err.error.is_type(ScErrorType::WasmVm));
+    assert!(err.error.is_code(ScErrorCode::InvalidInput));
+    assert!(events.contains("contract imports unknown host function"));
+    assert!(events.contains("zz"));
+
+    let (res, events) = upload(wasm_with_import("i", "_", 2));
+    let err = res.err().unwrap();
+    assert!(err.error.is_type(ScErrorType::WasmVm));
+    assert!(err.error.is_code(ScErrorCode::InvalidInput));
+    assert!(events.contains("contract import does not match host function signature"));
+}
+
+#[cfg(feature = "next")]
+#[test]
+fn test_contract_import_protocol_validation() {
+    use crate::meta::{self, get_ledger_protocol_version, INTERFACE_VERSION};
+    use soroban_env_common::xdr::{ScEnvMetaEntry, WriteXdr};
+    use soroban_synth_wasm::{Arity, ModEmitter};
+
+    // `d.1` is `try_call_with_budget`, which is only supported starting from
+    // the current protocol.
+    let mut me = ModEmitter::default();
+    me.import_func("d", "1", Arity(5));
+    let wasm = me.func(Arity(0), 0).finish_and_export("test").finish();
+    let proto = get_ledger_protocol_version(INTERFACE_VERSION);
+    let upload = |ledger_proto: u32, wasm: Vec<u8>| {
+        let host = Host::test_host_with_recording_footprint();
+        host.set_diagnostic_level(crate::DiagnosticLevel::Debug)
+            .unwrap();
+        host.with_mut_ledger_info(|li| li.protocol_version = ledger_proto)
+            .unwrap();
+        let res = host.invoke_function(HostFunction::UploadContractWasm(wasm.try_into().unwrap()));
+        (res, format!("{:?}", host.get_events().unwrap()))
+    };
+
+    let (res, _) = upload(proto, wasm.clone());
+    assert!(res.is_ok());
+
+    // Declaring the previous protocol in the contract metadata doesn't allow
+    // importing the function on the previous protocol.
+    let old_meta = ScEnvMetaEntry::ScEnvMetaKindInterfaceVersion(((proto - 1) as u64) << 32)
+        .to_xdr(DEFAULT_XDR_RW_LIMITS)
+        .unwrap();
+    let meta_pos = wasm
+        .windows(meta::XDR.len())
+        .position(|w| w == meta::XDR)
+        .unwrap();
+    let mut old_wasm = wasm;
+    old_wasm[meta_pos..meta_pos + old_meta.len()].copy_from_slice(&old_meta);
+    let (res, events) = upload(proto - 1, old_wasm);
+    let err = res.err().unwrap();
+    assert!(err.error.is_type(ScErrorType::WasmVm));
+    assert!(err.error.is_code(ScErrorCode::InvalidInput));
+    assert!(events.contains("contract imports host function not supported by the ledger protocol"));
+}
+
 #[test]
 fn test_large_contract() {
     let host = observe_host!(Host::test_host_with_recording_footprint());
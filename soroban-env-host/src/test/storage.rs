@@ -1,13 +1,22 @@
 use std::rc::Rc;
 
 use crate::budget::{AsBudget, Budget};
-use crate::storage::{AccessType, Footprint, Storage};
+use crate::host::metered_clone::MeteredClone;
+use crate::storage::{
+    diff_storage_maps, diff_storage_with_snapshot, AccessType, EntryWithLiveUntil, Footprint,
+    FootprintMap, FootprintUsageReport, LedgerEntrySizeLimits, Storage, StorageChangeKind,
+    StorageMap,
+};
+use crate::testutils::MockSnapshotSource;
 use crate::xdr::{
-    ContractDataDurability, LedgerKey, LedgerKeyContractData, ScAddress, ScErrorCode, ScErrorType,
-    ScVal,
+    ConfigSettingEntry, ContractDataDurability, LedgerKey, LedgerKeyContractData, ScAddress,
+    ScErrorCode, ScErrorType, ScVal,
+};
+use crate::{
+    Host, HostError, LedgerInfo, MeteredPersistentMap, MeteredStorageMap,
+    PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION,
 };
-use crate::{Host, HostError, MeteredOrdMap};
-use soroban_env_common::{AddressObject, Env, Symbol, TryFromVal, TryIntoVal};
+use soroban_env_common::{AddressObject, Env, Symbol, TryFromVal, TryIntoVal, VecObject};
 use soroban_test_wasms::{CONTRACT_STORAGE, INVOKE_CONTRACT};
 
 #[test]
@@ -58,7 +67,7 @@ fn footprint_enforce_access() -> Result<(), HostError> {
     }));
 
     let om = [(Rc::clone(&key), AccessType::ReadOnly)].into();
-    let mom = MeteredOrdMap::from_map(om, &budget)?;
+    let mom = FootprintMap::from_map(om, &budget)?;
     let mut fp = Footprint(mom);
     assert!(fp
         .enforce_access(&key2, AccessType::ReadOnly, &budget)
@@ -97,7 +106,7 @@ fn footprint_attempt_to_write_readonly_entry() -> Result<(), HostError> {
         durability: ContractDataDurability::Persistent,
     }));
     let om = [(Rc::clone(&key), AccessType::ReadOnly)].into();
-    let mom = MeteredOrdMap::from_map(om, &budget)?;
+    let mom = FootprintMap::from_map(om, &budget)?;
     let mut fp = Footprint(mom);
     let res = fp.enforce_access(&key, AccessType::ReadWrite, &budget);
     assert!(HostError::result_matches_err(
@@ -107,6 +116,148 @@ fn footprint_attempt_to_write_readonly_entry() -> Result<(), HostError> {
     Ok(())
 }
 
+#[test]
+fn footprint_usage_report() -> Result<(), HostError> {
+    let budget = Budget::default();
+    let keys: Vec<_> = (0..4)
+        .map(|i| {
+            Rc::new(LedgerKey::ContractData(LedgerKeyContractData {
+                contract: ScAddress::Contract([0; 32].into()),
+                key: ScVal::I32(i),
+                durability: ContractDataDurability::Persistent,
+            }))
+        })
+        .collect();
+    let access_types = [
+        AccessType::ReadOnly,
+        AccessType::ReadWrite,
+        AccessType::ReadWrite,
+        AccessType::ReadOnly,
+    ];
+    let fp = Footprint(FootprintMap::from_map(
+        keys.iter().cloned().zip(access_types).collect(),
+        &budget,
+    )?);
+    let map = StorageMap::from_map(keys.iter().map(|k| (Rc::clone(k), None)).collect(), &budget)?;
+    let mut storage = Storage::with_enforcing_footprint_and_map(fp, map);
+    assert_eq!(storage.footprint_usage_report(), None);
+    storage.enable_footprint_usage_tracking();
+
+    assert!(!storage.has(&keys[0], &budget)?);
+    assert!(!storage.has(&keys[1], &budget)?);
+    storage.del(&keys[2], &budget)?;
+    assert!(!storage.has(&keys[2], &budget)?);
+
+    assert_eq!(
+        storage.footprint_usage_report(),
+        Some(FootprintUsageReport {
+            unused_keys: vec![Rc::clone(&keys[3])],
+            unwritten_read_write_keys: vec![Rc::clone(&keys[1])],
+        })
+    );
+    Ok(())
+}
+
+#[test]
+fn storage_map_structural_sharing() -> Result<(), HostError> {
+    let budget = Budget::default();
+    let key = |i: i32| {
+        Rc::new(LedgerKey::ContractData(LedgerKeyContractData {
+            contract: ScAddress::Contract([0; 32].into()),
+            key: ScVal::I32(i),
+            durability: ContractDataDurability::Persistent,
+        }))
+    };
+    let mut map = StorageMap::new_for_protocol(PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION);
+    assert!(matches!(map, MeteredStorageMap::Persistent(_)));
+    // Insert in non-sorted order to exercise rebalancing.
+    for i in (0..100).rev().step_by(2).chain((0..100).step_by(2)) {
+        map = map.insert(key(i), None, &budget)?;
+    }
+    assert_eq!(map.len(), 100);
+
+    // Modifying the map doesn't affect its snapshot.
+    let snapshot = map.metered_clone(&budget)?;
+    map = map.insert(key(1000), None, &budget)?;
+    let (map, _) = map.remove::<LedgerKey>(&key(0), &budget)?.unwrap();
+    assert!(map.remove::<LedgerKey>(&key(0), &budget)?.is_none());
+    assert_eq!(map.len(), 100);
+    assert_eq!(snapshot.len(), 100);
+    assert!(snapshot.contains_key::<LedgerKey>(&key(0), &budget)?);
+    assert!(!snapshot.contains_key::<LedgerKey>(&key(1000), &budget)?);
+
+    // Iteration is in key order.
+    let keys: Vec<_> = map.keys(&budget)?.cloned().collect();
+    let mut expected: Vec<_> = (1..100).chain([1000]).map(key).collect();
+    expected.sort();
+    assert_eq!(keys, expected);
+
+    // Unsorted input is rejected.
+    let res = MeteredPersistentMap::<_, Option<EntryWithLiveUntil>, Budget>::from_map(
+        vec![(key(1), None), (key(0), None)],
+        &budget,
+    );
+    assert!(HostError::result_matches_err(
+        res,
+        (ScErrorType::Object, ScErrorCode::InvalidInput)
+    ));
+    Ok(())
+}
+
+#[test]
+fn storage_map_representation_follows_protocol_version() -> Result<(), HostError> {
+    let budget = Budget::default();
+    let keys: Vec<_> = (0..3)
+        .map(|i| {
+            Rc::new(LedgerKey::ContractData(LedgerKeyContractData {
+                contract: ScAddress::Contract([0; 32].into()),
+                key: ScVal::I32(i),
+                durability: ContractDataDurability::Persistent,
+            }))
+        })
+        .collect();
+    let fp = Footprint(FootprintMap::from_map(
+        keys.iter()
+            .map(|k| (Rc::clone(k), AccessType::ReadWrite))
+            .collect(),
+        &budget,
+    )?);
+    let map = StorageMap::from_map(keys.iter().map(|k| (Rc::clone(k), None)).collect(), &budget)?;
+    let host =
+        Host::with_storage_and_budget(Storage::with_enforcing_footprint_and_map(fp, map), budget);
+
+    let check_representation = |persistent: bool| -> Result<(), HostError> {
+        host.with_mut_storage(|storage| {
+            assert_eq!(
+                matches!(storage.map, MeteredStorageMap::Persistent(_)),
+                persistent
+            );
+            assert_eq!(
+                matches!(storage.footprint.0, MeteredStorageMap::Persistent(_)),
+                persistent
+            );
+            let stored: Vec<_> = storage.map.keys(host.budget_ref())?.cloned().collect();
+            assert_eq!(stored, keys);
+            assert_eq!(storage.footprint.0.len(), keys.len());
+            Ok(())
+        })
+    };
+    check_representation(false)?;
+    host.set_ledger_info(LedgerInfo {
+        protocol_version: PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION - 1,
+        ..Default::default()
+    })?;
+    check_representation(false)?;
+    host.with_mut_ledger_info(|li| li.protocol_version = PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION)?;
+    check_representation(true)?;
+    host.set_ledger_info(LedgerInfo {
+        protocol_version: PERSISTENT_STORAGE_MAP_PROTOCOL_VERSION - 1,
+        ..Default::default()
+    })?;
+    check_representation(false)?;
+    Ok(())
+}
+
 fn storage_fn_name(host: &Host, fn_name: &str, storage: &str) -> Symbol {
     Symbol::try_from_val(host, &format!("{}_{}", fn_name, storage).as_str()).unwrap()
 }
@@ -341,6 +492,307 @@ fn test_instance_storage() {
     test_storage(&host, contract_id, "instance");
 }
 
+#[test]
+fn test_storage_access_log() {
+    let host = observe_host!(Host::test_host_with_recording_footprint());
+    let contract_id = host.register_test_contract_wasm(CONTRACT_STORAGE);
+    let contract_id_hash = host.contract_id_from_address(contract_id).unwrap();
+    let key_1 = Symbol::try_from_small_str("key_1").unwrap();
+    let storage_key = Rc::new(LedgerKey::ContractData(LedgerKeyContractData {
+        contract: ScAddress::Contract(contract_id_hash),
+        key: ScVal::Symbol("key_1".try_into().unwrap()),
+        durability: ContractDataDurability::Persistent,
+    }));
+
+    host.enable_storage_access_log().unwrap();
+    let call = |fn_name: &str, args: VecObject| {
+        host.call(
+            contract_id,
+            storage_fn_name(&host, fn_name, "persistent"),
+            args,
+        )
+        .unwrap();
+    };
+    call("put", test_vec![&*host, key_1, 1234_u64].into());
+    call("get", test_vec![&*host, key_1].into());
+    call(
+        "extend",
+        test_vec![&*host, key_1, 5000_u32, 5000_u32].into(),
+    );
+    call("del", test_vec![&*host, key_1].into());
+    call("has", test_vec![&*host, key_1].into());
+
+    let access_log = host.get_storage_access_log().unwrap().unwrap();
+    let realhost: Host = (*host).clone();
+    drop(host);
+    let (storage, _) = realhost.try_finish().unwrap();
+    // The log is kept in the finished storage.
+    assert_eq!(storage.access_log(), Some(&access_log));
+    let stats = access_log.get(&storage_key).cloned().unwrap();
+    assert!(stats.put_count >= 1);
+    assert_eq!(stats.del_count, 1);
+    assert!(stats.get_count >= 1);
+    assert!(stats.has_count >= 1);
+    assert_eq!(stats.ttl_extension_count, 1);
+    assert!(stats.read_bytes > 0);
+    assert!(stats.write_bytes > 0);
+}
+
+#[test]
+fn test_storage_access_log_is_opt_in() {
+    let host = observe_host!(Host::test_host_with_recording_footprint());
+    let contract_id = host.register_test_contract_wasm(CONTRACT_STORAGE);
+    test_storage(&host, contract_id, "persistent");
+    assert!(host.get_storage_access_log().unwrap().is_none());
+    let realhost: Host = (*host).clone();
+    drop(host);
+    let (storage, _) = realhost.try_finish().unwrap();
+    assert!(storage.access_log().is_none());
+}
+
+#[test]
+fn test_advance_ledger_expires_entries() {
+    let host = Host::test_host_with_recording_footprint();
+    let contract_id = host.register_test_contract_wasm(CONTRACT_STORAGE);
+    let contract_id_hash = host.contract_id_from_address(contract_id).unwrap();
+    let key_1 = Symbol::try_from_small_str("key_1").unwrap();
+    let storage_key = Rc::new(LedgerKey::ContractData(LedgerKeyContractData {
+        contract: ScAddress::Contract(contract_id_hash),
+        key: ScVal::Symbol("key_1".try_into().unwrap()),
+        durability: ContractDataDurability::Persistent,
+    }));
+    let call = |fn_name: &str, args: VecObject| {
+        host.call(
+            contract_id,
+            Symbol::try_from_val(&host, &fn_name).unwrap(),
+            args,
+        )
+    };
+    let has = |storage: &str| {
+        bool::try_from_val(
+            &host,
+            &call(&format!("has_{storage}"), test_vec![&host, key_1].into()).unwrap(),
+        )
+        .unwrap()
+    };
+
+    call("put_persistent", test_vec![&host, key_1, 1234_u64].into()).unwrap();
+    call("put_temporary", test_vec![&host, key_1, 5678_u64].into()).unwrap();
+    call(
+        "extend_persistent",
+        test_vec![&host, key_1, 10_000_u32, 10_000_u32].into(),
+    )
+    .unwrap();
+    call(
+        "extend_instance",
+        test_vec![&host, 10_000_u32, 10_000_u32].into(),
+    )
+    .unwrap();
+
+    // The temporary entry lives for `min_temp_entry_ttl` ledgers and is
+    // deleted after that.
+    host.advance_ledger(16, 5).unwrap();
+    host.with_ledger_info(|li| {
+        assert_eq!(li.sequence_number, 16);
+        assert_eq!(li.timestamp, 16 * 5);
+        Ok(())
+    })
+    .unwrap();
+    assert!(!has("temporary"));
+    assert!(has("persistent"));
+    assert!(host.archived_keys().unwrap().is_empty());
+
+    // All the persistent entries are archived once they are no longer live.
+    host.advance_ledger(10_000 - 16 + 1, 5).unwrap();
+    let archived_keys = host.archived_keys().unwrap();
+    assert!(archived_keys.contains(&storage_key));
+    assert!(HostError::result_matches_err(
+        call("has_persistent", test_vec![&host, key_1].into()),
+        (ScErrorType::Storage, ScErrorCode::MissingValue)
+    ));
+
+    // Restoring the archived entries makes them accessible again.
+    for key in &archived_keys {
+        host.restore_archived_entry(key).unwrap();
+    }
+    assert!(host.archived_keys().unwrap().is_empty());
+    assert_eq!(
+        u64::try_from_val(
+            &host,
+            &call("get_persistent", test_vec![&host, key_1].into()).unwrap()
+        )
+        .unwrap(),
+        1234
+    );
+    let (_, live_until) = host
+        .with_mut_storage(|s| s.get_with_live_until_ledger(&storage_key, host.as_budget()))
+        .unwrap();
+    assert_eq!(live_until, Some(10_001 + 4096 - 1));
+}
+
+#[test]
+fn test_ledger_entry_size_limits() {
+    let host = Host::test_host_with_recording_footprint();
+    let contract_id = host.register_test_contract_wasm(CONTRACT_STORAGE);
+    let key_1 = Symbol::try_from_small_str("key_1").unwrap();
+    let put = |storage: &str| {
+        host.call(
+            contract_id,
+            storage_fn_name(&host, "put", storage),
+            test_vec![&host, key_1, 1234_u64].into(),
+        )
+    };
+    let size_err = (ScErrorType::Storage, ScErrorCode::ExceededLimit);
+
+    let limits = LedgerEntrySizeLimits::from_config_settings(&[
+        ConfigSettingEntry::ContractDataKeySizeBytes(200),
+        ConfigSettingEntry::ContractDataEntrySizeBytes(1000),
+        ConfigSettingEntry::ContractMaxSizeBytes(100_000),
+        ConfigSettingEntry::BucketlistSizeWindow(Default::default()),
+    ]);
+    assert_eq!(
+        limits,
+        LedgerEntrySizeLimits {
+            max_contract_data_key_size_bytes: 200,
+            max_contract_data_entry_size_bytes: 1000,
+            max_contract_size_bytes: 100_000,
+        }
+    );
+    host.set_ledger_entry_size_limits(Some(limits)).unwrap();
+    put("persistent").unwrap();
+    put("instance").unwrap();
+
+    host.set_ledger_entry_size_limits(Some(LedgerEntrySizeLimits {
+        max_contract_data_key_size_bytes: 10,
+        ..limits
+    }))
+    .unwrap();
+    assert!(HostError::result_matches_err(put("persistent"), size_err));
+    assert!(HostError::result_matches_err(put("temporary"), size_err));
+
+    host.set_ledger_entry_size_limits(Some(LedgerEntrySizeLimits {
+        max_contract_data_entry_size_bytes: 50,
+        ..limits
+    }))
+    .unwrap();
+    assert!(HostError::result_matches_err(put("persistent"), size_err));
+    assert!(HostError::result_matches_err(put("instance"), size_err));
+
+    host.set_ledger_entry_size_limits(Some(LedgerEntrySizeLimits {
+        max_contract_size_bytes: 10,
+        ..limits
+    }))
+    .unwrap();
+    let wasm = host.bytes_new_from_slice(INVOKE_CONTRACT).unwrap();
+    assert!(HostError::result_matches_err(
+        host.upload_wasm(wasm),
+        size_err
+    ));
+
+    // Enforcing the limits doesn't change the budget usage.
+    let cpu_insns_for_put = |limits| {
+        host.set_ledger_entry_size_limits(limits).unwrap();
+        let before = host.as_budget().get_cpu_insns_consumed().unwrap();
+        put("persistent").unwrap();
+        host.as_budget().get_cpu_insns_consumed().unwrap() - before
+    };
+    assert_eq!(cpu_insns_for_put(Some(limits)), cpu_insns_for_put(None));
+
+    // Without the limits the oversized writes succeed.
+    host.set_ledger_entry_size_limits(None).unwrap();
+    put("persistent").unwrap();
+    put("instance").unwrap();
+    let wasm = host.bytes_new_from_slice(INVOKE_CONTRACT).unwrap();
+    host.upload_wasm(wasm).unwrap();
+}
+
+#[test]
+fn test_storage_diff() {
+    let host = Host::test_host_with_recording_footprint();
+    let contract_id = host.register_test_contract_wasm(CONTRACT_STORAGE);
+    let contract_id_hash = host.contract_id_from_address(contract_id).unwrap();
+    let key_1 = Symbol::try_from_small_str("key_1").unwrap();
+    let key_2 = Symbol::try_from_small_str("key_2").unwrap();
+    let call = |fn_name: &str, args: VecObject| {
+        host.call(
+            contract_id,
+            Symbol::try_from_val(&host, &fn_name).unwrap(),
+            args,
+        )
+        .unwrap();
+    };
+    let storage_key = |key: &str, durability| {
+        Rc::new(LedgerKey::ContractData(LedgerKeyContractData {
+            contract: ScAddress::Contract(contract_id_hash.clone()),
+            key: ScVal::Symbol(key.try_into().unwrap()),
+            durability,
+        }))
+    };
+
+    call("put_persistent", test_vec![&host, key_1, 1_u64].into());
+    call("put_persistent", test_vec![&host, key_2, 1_u64].into());
+    let old_map = host.with_mut_storage(|s| Ok(s.map.clone())).unwrap();
+
+    call("put_persistent", test_vec![&host, key_1, 2_u64].into());
+    call("del_persistent", test_vec![&host, key_2].into());
+    call("put_temporary", test_vec![&host, key_1, 3_u64].into());
+    call(
+        "extend_instance",
+        test_vec![&host, 5000_u32, 5000_u32].into(),
+    );
+
+    let changes = host
+        .with_mut_storage(|s| diff_storage_maps(host.as_budget(), &old_map, &s.map))
+        .unwrap();
+    let change = |key: &Rc<LedgerKey>| changes.iter().find(|c| &c.key == key).unwrap();
+    assert_eq!(changes.len(), 5);
+
+    let updated = change(&storage_key("key_1", ContractDataDurability::Persistent));
+    assert_eq!(updated.kind, StorageChangeKind::Updated);
+    assert_eq!(
+        updated.contract_data_key(),
+        Some((
+            &ScAddress::Contract(contract_id_hash.clone()),
+            &ScVal::Symbol("key_1".try_into().unwrap())
+        ))
+    );
+    assert_eq!(updated.old_contract_data_val(), Some(&ScVal::U64(1)));
+    assert_eq!(updated.new_contract_data_val(), Some(&ScVal::U64(2)));
+
+    let deleted = change(&storage_key("key_2", ContractDataDurability::Persistent));
+    assert_eq!(deleted.kind, StorageChangeKind::Deleted);
+    assert_eq!(deleted.old_contract_data_val(), Some(&ScVal::U64(1)));
+    assert_eq!(deleted.new_entry, None);
+
+    let created = change(&storage_key("key_1", ContractDataDurability::Temporary));
+    assert_eq!(created.kind, StorageChangeKind::Created);
+    assert_eq!(created.new_contract_data_val(), Some(&ScVal::U64(3)));
+    assert_eq!(created.old_live_until_ledger, None);
+    assert_eq!(created.new_live_until_ledger, Some(15));
+
+    let instance = change(
+        &host
+            .contract_instance_ledger_key(&contract_id_hash)
+            .unwrap(),
+    );
+    assert_eq!(instance.kind, StorageChangeKind::TtlExtended);
+    assert_eq!(instance.old_live_until_ledger, Some(4095));
+    assert_eq!(instance.new_live_until_ledger, Some(5000));
+    assert!(changes
+        .iter()
+        .any(|c| matches!(c.key.as_ref(), LedgerKey::ContractCode(_))
+            && c.kind == StorageChangeKind::TtlExtended));
+
+    // Against an empty snapshot all the existing entries are created.
+    let changes = host
+        .with_mut_storage(|s| {
+            diff_storage_with_snapshot(host.as_budget(), s, &MockSnapshotSource::new())
+        })
+        .unwrap();
+    assert_eq!(changes.len(), 4);
+    assert!(changes.iter().all(|c| c.kind == StorageChangeKind::Created));
+}
+
 #[test]
 fn test_nested_bump() {
     let host = observe_host!(Host::test_host_with_recording_footprint());
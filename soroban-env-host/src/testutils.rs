@@ -1,4 +1,4 @@
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::panic::{catch_unwind, set_hook, take_hook, UnwindSafe};
 use std::sync::Once;
 
@@ -9,10 +9,12 @@ use rand::RngCore;
 
 use crate::{
     budget::Budget,
-    storage::{SnapshotSource, Storage},
+    e2e_invoke::ledger_entry_to_ledger_key,
+    host::{error::TryBorrowOrErr, ledger_info_helper::get_key_durability},
+    storage::{AccessType, EntryWithLiveUntil, FootprintMode, SnapshotSource, Storage},
     xdr::{
-        AccountId, ContractCostType, LedgerEntry, LedgerKey, PublicKey, ScAddress, ScErrorCode,
-        ScErrorType, ScVal, ScVec, Uint256,
+        AccountId, ContractCostType, ContractDataDurability, LedgerEntry, LedgerKey, PublicKey,
+        ScAddress, ScErrorCode, ScErrorType, ScVal, ScVec, Uint256,
     },
     AddressObject, BytesObject, Env, EnvBase, Error, Host, HostError, LedgerInfo, Val, VecObject,
 };
@@ -123,6 +125,16 @@ impl MockSnapshotSource {
     pub fn new() -> Self {
         Self(BTreeMap::<Rc<LedgerKey>, (Rc<LedgerEntry>, Option<u32>)>::new())
     }
+
+    pub fn from_entries(entries: Vec<(LedgerEntry, Option<u32>)>) -> Result<Self, HostError> {
+        let budget = Budget::default();
+        let mut map = BTreeMap::<Rc<LedgerKey>, (Rc<LedgerEntry>, Option<u32>)>::new();
+        for (entry, live_until_ledger) in entries {
+            let key = Rc::new(ledger_entry_to_ledger_key(&entry, &budget)?);
+            map.insert(key, (Rc::new(entry), live_until_ledger));
+        }
+        Ok(Self(map))
+    }
 }
 impl SnapshotSource for MockSnapshotSource {
     fn get(&self, key: &Rc<LedgerKey>) -> Result<(Rc<LedgerEntry>, Option<u32>), HostError> {
@@ -138,6 +150,39 @@ impl SnapshotSource for MockSnapshotSource {
     }
 }
 
+/// Persistent entries archived by [Host::advance_ledger], along with their
+/// last live until ledger.
+pub(crate) type ArchivedEntries = Rc<RefCell<BTreeMap<Rc<LedgerKey>, EntryWithLiveUntil>>>;
+
+// Snapshot source used in the recording mode once the ledger time-travel is
+// used: reading an archived entry fails in the same way as on-chain, while the
+// rest of the entries are read from the wrapped source.
+struct ArchivingSnapshotSource {
+    src: Rc<dyn SnapshotSource>,
+    archived_entries: ArchivedEntries,
+}
+
+impl ArchivingSnapshotSource {
+    fn check_not_archived(&self, key: &Rc<LedgerKey>) -> Result<(), HostError> {
+        if self.archived_entries.try_borrow_or_err()?.contains_key(key) {
+            return Err((ScErrorType::Storage, ScErrorCode::MissingValue).into());
+        }
+        Ok(())
+    }
+}
+
+impl SnapshotSource for ArchivingSnapshotSource {
+    fn get(&self, key: &Rc<LedgerKey>) -> Result<EntryWithLiveUntil, HostError> {
+        self.check_not_archived(key)?;
+        self.src.get(key)
+    }
+
+    fn has(&self, key: &Rc<LedgerKey>) -> Result<bool, HostError> {
+        self.check_not_archived(key)?;
+        self.src.has(key)
+    }
+}
+
 impl Host {
     pub const TEST_PRNG_SEED: &'static [u8; 32] = b"12345678901234567890123456789012";
 
@@ -168,6 +213,131 @@ impl Host {
         host
     }
 
+    /// Moves the ledger `ledgers` ledgers forward, advancing the timestamp by
+    /// `close_time_seconds` per ledger, and expires the entries in storage
+    /// that are no longer live in the new ledger: the temporary entries are
+    /// deleted, while the persistent entries are archived. Reading an archived
+    /// entry fails in the same way as on-chain until it is restored via
+    /// [Host::restore_archived_entry]: with [ScErrorCode::MissingValue] in the
+    /// recording mode, and as an access outside of the footprint in the
+    /// enforcing mode.
+    pub fn advance_ledger(&self, ledgers: u32, close_time_seconds: u64) -> Result<(), HostError> {
+        let mut li = self.with_ledger_info(|li| Ok(li.clone()))?;
+        li.sequence_number = li.sequence_number.checked_add(ledgers).ok_or_else(|| {
+            self.err(
+                ScErrorType::Context,
+                ScErrorCode::InvalidInput,
+                "ledger sequence number overflow",
+                &[ledgers.into()],
+            )
+        })?;
+        li.timestamp = li
+            .timestamp
+            .saturating_add((ledgers as u64).saturating_mul(close_time_seconds));
+        let ledger_seq = li.sequence_number;
+        self.set_ledger_info(li)?;
+        self.expire_entries(ledger_seq)
+    }
+
+    /// Returns the keys of the persistent entries that have been archived by
+    /// [Host::advance_ledger] and haven't been restored yet.
+    pub fn archived_keys(&self) -> Result<Vec<Rc<LedgerKey>>, HostError> {
+        let archived_entries = self.archived_entries()?;
+        let keys = archived_entries
+            .try_borrow_or_err()?
+            .keys()
+            .cloned()
+            .collect();
+        Ok(keys)
+    }
+
+    /// Restores an archived persistent entry with the same TTL as the
+    /// `RestoreFootprint` operation would give it.
+    pub fn restore_archived_entry(&self, key: &Rc<LedgerKey>) -> Result<(), HostError> {
+        let live_until = self.get_min_live_until_ledger(ContractDataDurability::Persistent)?;
+        let archived_entries = self.archived_entries()?;
+        let (entry, _) = archived_entries
+            .try_borrow_mut_or_err()?
+            .remove(key)
+            .ok_or_else(|| HostError::from((ScErrorType::Storage, ScErrorCode::MissingValue)))?;
+        self.with_mut_storage(|storage| {
+            let budget = self.budget_ref();
+            if let FootprintMode::Enforcing = storage.mode {
+                storage.footprint.0 =
+                    storage
+                        .footprint
+                        .0
+                        .insert(Rc::clone(key), AccessType::ReadWrite, budget)?;
+            }
+            storage.map =
+                storage
+                    .map
+                    .insert(Rc::clone(key), Some((entry, Some(live_until))), budget)?;
+            Ok(())
+        })
+    }
+
+    // Returns the archive of the ledger time-travel, setting it up on the
+    // first use.
+    fn archived_entries(&self) -> Result<ArchivedEntries, HostError> {
+        let mut archived_entries = self.try_borrow_archived_entries_mut()?;
+        if let Some(entries) = archived_entries.as_ref() {
+            return Ok(Rc::clone(entries));
+        }
+        let entries = ArchivedEntries::default();
+        self.with_mut_storage(|storage| {
+            if let FootprintMode::Recording(src) = &storage.mode {
+                storage.mode = FootprintMode::Recording(Rc::new(ArchivingSnapshotSource {
+                    src: Rc::clone(src),
+                    archived_entries: Rc::clone(&entries),
+                }));
+            }
+            Ok(())
+        })?;
+        *archived_entries = Some(Rc::clone(&entries));
+        Ok(entries)
+    }
+
+    // Deletes the temporary entries and archives the persistent entries that
+    // are not live in `ledger_seq` anymore. The archived entries are removed
+    // from the storage map, so that in the recording mode they are read
+    // through the archiving snapshot source, and from the footprint in the
+    // enforcing mode, as the archived entries can't be declared on-chain.
+    fn expire_entries(&self, ledger_seq: u32) -> Result<(), HostError> {
+        let archived_entries = self.archived_entries()?;
+        self.with_mut_storage(|storage| {
+            let budget = self.budget_ref();
+            let expired: Vec<(Rc<LedgerKey>, EntryWithLiveUntil)> = storage
+                .map
+                .unmetered_iter()
+                .filter_map(|(key, entry)| match entry {
+                    Some((le, Some(live_until))) if *live_until < ledger_seq => {
+                        Some((Rc::clone(key), (Rc::clone(le), Some(*live_until))))
+                    }
+                    _ => None,
+                })
+                .collect();
+            for (key, entry) in expired {
+                if get_key_durability(&key) != Some(ContractDataDurability::Persistent) {
+                    storage.map = storage.map.insert(key, None, budget)?;
+                    continue;
+                }
+                if let Some((map, _)) = storage.map.remove::<Rc<LedgerKey>>(&key, budget)? {
+                    storage.map = map;
+                }
+                if let FootprintMode::Enforcing = storage.mode {
+                    if let Some((footprint, _)) =
+                        storage.footprint.0.remove::<Rc<LedgerKey>>(&key, budget)?
+                    {
+                        storage.footprint.0 = footprint;
+                    }
+                }
+                archived_entries.try_borrow_mut_or_err()?.insert(key, entry);
+            }
+            Ok(())
+        })
+    }
+
     pub fn test_budget(self, cpu: u64, mem: u64) -> Self {
         self.with_budget(|budget| {
             budget.reset_limits(cpu, mem)?; // something big but finite that we may exceed
@@ -309,7 +479,6 @@ impl Host {
     ) -> Result<Val, HostError> {
         use crate::{budget::AsBudget, host::HostLifecycleEvent};
         use soroban_bench_utils::HostTracker;
-        use std::cell::RefCell;
 
         let _span = tracy_span!("measured_call");
         let budget = self.as_budget();
@@ -11,6 +11,9 @@
 mod dispatch;
 mod fuel_refillable;
 mod func_info;
+mod invocation_stack;
+mod module_cache;
+mod profile;
 
 #[cfg(feature = "bench")]
 pub(crate) use dispatch::dummy0;
@@ -28,8 +31,16 @@ use std::{cell::RefCell, io::Cursor, rc::Rc};
 
 use fuel_refillable::FuelRefillable;
 use func_info::HOST_FUNCTIONS;
-
-use wasmi::{Engine, FuelConsumptionMode, Instance, Linker, Memory, Module, Store, Value};
+pub(crate) use invocation_stack::WasmNames;
+pub use invocation_stack::{ContractInvocationFrame, ContractInvocationStack};
+pub(crate) use module_cache::InstantiatedModules;
+pub use module_cache::ModuleCache;
+pub(crate) use profile::VmProfiler;
+pub use profile::{HostFunctionProfile, InvocationProfile};
+
+use wasmi::{
+    core::ValueType, Engine, FuelConsumptionMode, Instance, Linker, Memory, Module, Store, Value,
+};
 
 #[cfg(any(test, feature = "testutils"))]
 use crate::VmCaller;
@@ -39,6 +50,13 @@ impl wasmi::core::HostError for HostError {}
 
 const MAX_VM_ARGS: usize = 32;
 
+/// The first ledger protocol version that charges the repeated instantiations
+/// of a contract within the same [Host] as
+/// [ContractCostType::VmCachedInstantiation] (see
+/// [Vm::new_with_module_cache]). Earlier protocols charge every
+/// instantiation as [ContractCostType::VmInstantiation].
+pub const VM_CACHED_INSTANTIATION_PROTOCOL_VERSION: u32 = 21;
+
 /// A [Vm] is a thin wrapper around an instance of [wasmi::Module]. Multiple
 /// [Vm]s may be held in a single [Host], and each contains a single WASM module
 /// instantiation.
@@ -53,9 +71,9 @@ const MAX_VM_ARGS: usize = 32;
 pub struct Vm {
     #[allow(dead_code)]
     pub(crate) contract_id: Hash,
-    // TODO: consider moving store and possibly module to Host so they can be
-    // recycled across calls. Or possibly beyond, to be recycled across txs.
-    module: Module,
+    // The module may be shared with other `Vm`s via the `ModuleCache`.
+    // TODO: consider moving store to Host so it can be recycled across calls.
+    module: Rc<Module>,
     store: RefCell<Store<Host>>,
     instance: Instance,
     pub(crate) memory: Option<Memory>,
@@ -193,11 +211,14 @@ impl Vm {
             match e.ty() {
                 wasmi::ExternType::Func(f) => {
                     if f.params().len() > MAX_VM_ARGS || f.results().len() > MAX_VM_ARGS {
-                        return Err(host.err(
-                            ScErrorType::WasmVm,
-                            ScErrorCode::InvalidInput,
+                        return Err(err!(
+                            host,
+                            (ScErrorType::WasmVm, ScErrorCode::InvalidInput),
                             "Too many arguments or results in wasm export",
-                            &[],
+                            *e.name(),
+                            f.params().len(),
+                            f.results().len(),
+                            MAX_VM_ARGS
                         ));
                     }
                 }
@@ -207,6 +228,61 @@ impl Vm {
         Ok(())
     }
 
+    // Checks that every import of the module is a function from
+    // `HOST_FUNCTIONS` with the matching signature, that is supported by the
+    // ledger protocol. These would mostly also fail to link, but checking them
+    // upfront allows reporting the bad import. This is only done on upload:
+    // the uploaded code has passed the check already when instantiated later.
+    fn check_imports(host: &Host, m: &Module) -> Result<(), HostError> {
+        let ledger_proto = host.get_ledger_protocol_version()?;
+        for import in m.imports() {
+            let (mod_str, fn_str) = (import.module(), import.name());
+            let Some(hf) = HOST_FUNCTIONS
+                .iter()
+                .find(|hf| hf.mod_str == mod_str && hf.fn_str == fn_str)
+            else {
+                return Err(err!(
+                    host,
+                    (ScErrorType::WasmVm, ScErrorCode::InvalidInput),
+                    "contract imports unknown host function",
+                    *mod_str,
+                    *fn_str
+                ));
+            };
+            let signature_matches = match import.ty() {
+                wasmi::ExternType::Func(f) => {
+                    f.params().len() == hf.arity
+                        && f.params().iter().all(|p| *p == ValueType::I64)
+                        && f.results() == [ValueType::I64]
+                }
+                _ => false,
+            };
+            if !signature_matches {
+                return Err(err!(
+                    host,
+                    (ScErrorType::WasmVm, ScErrorCode::InvalidInput),
+                    "contract import does not match host function signature, expected arity",
+                    *mod_str,
+                    *fn_str,
+                    hf.arity
+                ));
+            }
+            if let Some(min_proto) = hf.min_proto {
+                if ledger_proto < min_proto {
+                    return Err(err!(
+                        host,
+                        (ScErrorType::WasmVm, ScErrorCode::InvalidInput),
+                        "contract imports host function not supported by the ledger protocol",
+                        *mod_str,
+                        *fn_str,
+                        min_proto
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Constructs a new instance of a [Vm] within the provided [Host],
     /// establishing a new execution context for a contract identified by
     /// `contract_id` with WASM bytecode provided in `module_wasm_code`.
@@ -237,6 +313,109 @@ impl Vm {
             Some(module_wasm_code.len() as u64),
         )?;
 
+        let engine = Self::new_engine(host)?;
+        let module = Rc::new(Self::parse_module(host, &engine, module_wasm_code)?);
+        Self::instantiate(host, contract_id, &engine, module)
+    }
+
+    /// Like [Vm::new], but also checks that the imports of the module are
+    /// host functions supported by the ledger protocol. This is used for
+    /// validating the wasm on upload, and is charged the same as [Vm::new].
+    pub(crate) fn new_for_upload(
+        host: &Host,
+        contract_id: Hash,
+        module_wasm_code: &[u8],
+    ) -> Result<Rc<Self>, HostError> {
+        let _span = tracy_span!("Vm::new_for_upload");
+
+        host.charge_budget(
+            ContractCostType::VmInstantiation,
+            Some(module_wasm_code.len() as u64),
+        )?;
+
+        let engine = Self::new_engine(host)?;
+        let module = Self::parse_module(host, &engine, module_wasm_code)?;
+        Self::check_imports(host, &module)?;
+        Self::instantiate(host, contract_id, &engine, Rc::new(module))
+    }
+
+    /// Like [Vm::new], but reuses the module of the contract if it has
+    /// already been instantiated by the [Host], or else looks it up by
+    /// `wasm_hash` in the [ModuleCache] set on the host (if any) instead of
+    /// parsing and validating it again. Newly parsed modules are added to the
+    /// cache.
+    ///
+    /// Starting from [VM_CACHED_INSTANTIATION_PROTOCOL_VERSION], the repeated
+    /// instantiations of a contract within the same host are charged as
+    /// [ContractCostType::VmCachedInstantiation]. All the other
+    /// instantiations are charged as [ContractCostType::VmInstantiation]: the
+    /// [ModuleCache] is shared with other hosts, so its state may differ
+    /// between the replicas and it is only a CPU optimization of the embedder.
+    /// For the same reason the module checks that depend on the ledger
+    /// protocol are repeated for the cached modules.
+    pub(crate) fn new_with_module_cache(
+        host: &Host,
+        contract_id: Hash,
+        wasm_hash: &Hash,
+        module_wasm_code: &[u8],
+    ) -> Result<Rc<Self>, HostError> {
+        let _span = tracy_span!("Vm::new_with_module_cache");
+
+        let fuel_config = host.as_budget().fuel_config()?;
+        let instantiated = host
+            .try_borrow_instantiated_modules()?
+            .get(wasm_hash, &fuel_config);
+        if let Some((engine, module)) = instantiated {
+            let cost_type = if host.get_ledger_protocol_version()?
+                >= VM_CACHED_INSTANTIATION_PROTOCOL_VERSION
+            {
+                ContractCostType::VmCachedInstantiation
+            } else {
+                ContractCostType::VmInstantiation
+            };
+            host.charge_budget(cost_type, Some(module_wasm_code.len() as u64))?;
+            Self::check_module(host, &module)?;
+            return Self::instantiate(host, contract_id, &engine, module);
+        }
+
+        host.charge_budget(
+            ContractCostType::VmInstantiation,
+            Some(module_wasm_code.len() as u64),
+        )?;
+
+        let (engine, module) = match host.module_cache()? {
+            Some(cache) => {
+                let engine = cache.engine_for(&fuel_config, || Self::new_engine(host))?;
+                let module = match cache.get(wasm_hash) {
+                    Some(module) => {
+                        Self::check_module(host, &module)?;
+                        module
+                    }
+                    None => {
+                        let module = Rc::new(Self::parse_module(host, &engine, module_wasm_code)?);
+                        cache.insert(wasm_hash, Rc::clone(&module), module_wasm_code.len());
+                        module
+                    }
+                };
+                (engine, module)
+            }
+            None => {
+                let engine = Self::new_engine(host)?;
+                let module = Rc::new(Self::parse_module(host, &engine, module_wasm_code)?);
+                (engine, module)
+            }
+        };
+        host.try_borrow_instantiated_modules_mut()?.insert(
+            wasm_hash,
+            fuel_config,
+            engine.clone(),
+            Rc::clone(&module),
+        );
+        Self::instantiate(host, contract_id, &engine, module)
+    }
+
+    // Creates a new engine configured with the host's fuel costs.
+    fn new_engine(host: &Host) -> Result<Engine, HostError> {
         let mut config = wasmi::Config::default();
         let fuel_costs = host.as_budget().wasmi_fuel_costs()?;
 
@@ -251,19 +430,44 @@ impl Vm {
             .fuel_consumption_mode(FuelConsumptionMode::Eager)
             .set_fuel_costs(fuel_costs);
 
-        let engine = Engine::new(&config);
+        Ok(Engine::new(&config))
+    }
+
+    // Parses and validates the module. This is the part of the instantiation
+    // that can be reused via the `ModuleCache`, except for the checks in
+    // `check_module`.
+    fn parse_module(
+        host: &Host,
+        engine: &Engine,
+        module_wasm_code: &[u8],
+    ) -> Result<Module, HostError> {
         let module = {
             let _span0 = tracy_span!("parse module");
-            host.map_err(Module::new(&engine, module_wasm_code))?
+            host.map_err(Module::new(engine, module_wasm_code))?
         };
 
-        Self::check_max_args(host, &module)?;
-        Self::check_meta_section(host, &module)?;
+        Self::check_module(host, &module)?;
+        Ok(module)
+    }
 
-        let mut store = Store::new(&engine, host.clone());
+    // Performs the checks of the parsed module. These also need to be
+    // performed for the modules reused from a cache, as the result of the
+    // meta section check depends on the ledger protocol.
+    fn check_module(host: &Host, module: &Module) -> Result<(), HostError> {
+        Self::check_max_args(host, module)?;
+        Self::check_meta_section(host, module)
+    }
+
+    fn instantiate(
+        host: &Host,
+        contract_id: Hash,
+        engine: &Engine,
+        module: Rc<Module>,
+    ) -> Result<Rc<Self>, HostError> {
+        let mut store = Store::new(engine, host.clone());
         store.limiter(|host| host);
 
-        let mut linker = <Linker<Host>>::new(&engine);
+        let mut linker = <Linker<Host>>::new(engine);
 
         {
             let _span0 = tracy_span!("define host functions");
@@ -370,6 +574,7 @@ impl Vm {
         // call the function
         let mut wasm_ret: [Value; 1] = [Value::I64(0)];
         self.store.try_borrow_mut_or_err()?.add_fuel_to_vm(host)?;
+        host.profile_invocation_start(&self.contract_id, func_ss.as_ref())?;
         // Metering: the `func.call` will trigger `wasmi::Call` (or `CallIndirect`) instruction,
         // which is technically covered by wasmi fuel metering. So we are double charging a bit
         // here (by a few 100s cpu insns). It is better to be safe.
@@ -383,9 +588,13 @@ impl Vm {
         // wasmi instruction) remaining when the `OutOfFuel` trap occurs. This is only observable
         // if the contract traps with `OutOfFuel`, which may appear confusing if they look closely
         // at the budget amount consumed. So it should be fine.
-        self.store
-            .try_borrow_mut_or_err()?
-            .return_fuel_to_host(host)?;
+        let returned = self
+            .store
+            .try_borrow_mut_or_err()
+            .map_err(HostError::from)
+            .and_then(|mut store| store.return_fuel_to_host(host));
+        host.profile_invocation_end()?;
+        returned?;
 
         if let Err(e) = res {
             use std::borrow::Cow;
@@ -398,11 +607,24 @@ impl Vm {
                     if let Some(code) = trap.trap_code() {
                         let err = code.into();
                         let mut msg = Cow::Borrowed("VM call trapped");
+                        let mut invocation_stack = None;
                         host.with_debug_mode(|| {
                             msg = Cow::Owned(format!("VM call trapped: {:?}", &code));
+                            invocation_stack = Some(host.contract_invocation_stack()?);
                             Ok(())
                         });
-                        return Err(host.error(err, &msg, &[func_sym.to_val()]));
+                        let mut he = host.error(err, &msg, &[func_sym.to_val()]);
+                        if let Some(invocation_stack) = invocation_stack {
+                            host.log_diagnostics(
+                                &format!(
+                                    "contract invocation stack (newest first, invoked functions only):\n{}",
+                                    invocation_stack
+                                ),
+                                &[],
+                            );
+                            he.set_contract_invocation_stack(invocation_stack);
+                        }
+                        return Err(he);
                     }
                     if let Some(he) = trap.downcast::<HostError>() {
                         host.log_diagnostics(
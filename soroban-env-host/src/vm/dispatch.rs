@@ -112,7 +112,7 @@ macro_rules! generate_dispatch_functions {
                     // pattern-repetition matcher so that it will match all such
                     // descriptions.
                     $(#[$fn_attr:meta])*
-                    { $fn_str:literal, fn $fn_id:ident ($($arg:ident:$type:ty),*) -> $ret:ty }
+                    { $fn_str:literal, $($min_proto:literal)?, fn $fn_id:ident ($($arg:ident:$type:ty),*) -> $ret:ty }
                 )*
             }
         )*
@@ -130,7 +130,9 @@ macro_rules! generate_dispatch_functions {
                 //
                 //  1. Transfers the running "VM fuel" balance from wasmi to the
                 //     host's CPU budget.
-                //  2. Charges the host budget for the call, failing if over.
+                //  2. Charges the host budget for the call, failing if over,
+                //     and checks that the ledger protocol supports the host
+                //     function.
                 //  3. Attempts to convert incoming wasmi i64 args to Vals or
                 //     Val-wrappers expected by host functions, failing if any
                 //     conversions fail. This step also does
@@ -180,21 +182,47 @@ macro_rules! generate_dispatch_functions {
                     // the host maintains control of the budget.
                     FuelRefillable::return_fuel_to_host(&mut caller, &host).map_err(|he| Trap::from(he))?;
 
-                    // Charge for the host function dispatching: conversion between VM fuel and
-                    // host budget, marshalling values. This does not account for the actual work
-                    // being done in those functions, which are metered individually by the implementation.
-                    host.charge_budget(ContractCostType::DispatchHostFunction, None)?;
+                    let profile_checkpoint = host.profile_checkpoint()?;
+                    host.budget_ref().enter_host_function_cost_frame(core::stringify!($fn_id))?;
+
                     let mut vmcaller = VmCaller(Some(caller));
-                    // The odd / seemingly-redundant use of `wasmi::Value` here
-                    // as intermediates -- rather than just passing Vals --
-                    // has to do with the fact that some host functions are
-                    // typed as receiving or returning plain _non-val_ i64 or
-                    // u64 values. So the call here has to be able to massage
-                    // both types into and out of i64, and `wasmi::Value`
-                    // happens to be a natural switching point for that: we have
-                    // conversions to and from both Val and i64 / u64 for
-                    // wasmi::Value.
-                    let res: Result<_, HostError> = host.$fn_id(&mut vmcaller, $(<$type>::check_env_arg(<$type>::try_marshal_from_relative_value(Value::I64($arg), &host)?, &host)?),*);
+                    // The dispatch and marshalling errors are returned only
+                    // after exiting the cost frame, so that the host function
+                    // node doesn't stay current when they are recovered from.
+                    let call_res = (|| -> Result<Result<_, HostError>, Trap> {
+                        // Charge for the host function dispatching: conversion between VM fuel and
+                        // host budget, marshalling values. This does not account for the actual work
+                        // being done in those functions, which are metered individually by the implementation.
+                        host.charge_budget(ContractCostType::DispatchHostFunction, None)?;
+                        // Functions introduced in a later protocol than the
+                        // one the contract has been built against are only
+                        // linked if the contract declares that protocol, but
+                        // the ledger may still be running an older one.
+                        $(
+                            if host.get_ledger_protocol_version()? < $min_proto {
+                                return Ok(Err(host.err(
+                                    ScErrorType::Context,
+                                    ScErrorCode::InvalidAction,
+                                    concat!(stringify!($fn_id), " is not supported by the ledger protocol"),
+                                    &[],
+                                )));
+                            }
+                        )?
+                        // The odd / seemingly-redundant use of `wasmi::Value` here
+                        // as intermediates -- rather than just passing Vals --
+                        // has to do with the fact that some host functions are
+                        // typed as receiving or returning plain _non-val_ i64 or
+                        // u64 values. So the call here has to be able to massage
+                        // both types into and out of i64, and `wasmi::Value`
+                        // happens to be a natural switching point for that: we have
+                        // conversions to and from both Val and i64 / u64 for
+                        // wasmi::Value.
+                        Ok(host.$fn_id(&mut vmcaller, $(<$type>::check_env_arg(<$type>::try_marshal_from_relative_value(Value::I64($arg), &host)?, &host)?),*))
+                    })();
+
+                    host.profile_host_function(core::stringify!($fn_id), profile_checkpoint)?;
+                    host.budget_ref().exit_host_function_cost_frame()?;
+                    let res: Result<_, HostError> = call_res?;
 
                     #[cfg(feature = "testutils")]
                     {
@@ -34,8 +34,22 @@ pub(crate) trait FuelRefillable {
 
     fn return_fuel_to_host(&mut self, host: &Host) -> Result<(), HostError> {
         let fuel = self.fuel_consumed()?;
+        // This happens on every boundary crossing, so the budget is only
+        // inspected for attributing the fuel when the profiling is enabled.
+        if !host.is_vm_profiling_enabled()? {
+            host.as_budget()
+                .bulk_charge(ContractCostType::WasmInsnExec, fuel, None)?;
+            return self.reset_fuel();
+        }
+        let cpu_insns = host.as_budget().get_cpu_insns_consumed()?;
         host.as_budget()
             .bulk_charge(ContractCostType::WasmInsnExec, fuel, None)?;
+        host.profile_wasm_fuel(
+            fuel,
+            host.as_budget()
+                .get_cpu_insns_consumed()?
+                .saturating_sub(cpu_insns),
+        )?;
         self.reset_fuel()
     }
 }
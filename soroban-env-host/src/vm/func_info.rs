@@ -11,6 +11,14 @@ pub(crate) struct HostFuncInfo {
     /// as.
     pub(crate) fn_str: &'static str,
 
+    /// Number of arguments of the function. Every host function takes `arity`
+    /// i64 values and returns a single i64.
+    pub(crate) arity: usize,
+
+    /// The first ledger protocol version in which contracts may import the
+    /// function, if it is newer than the protocols of the other functions.
+    pub(crate) min_proto: Option<u32>,
+
     /// Function that takes a wasmi::Store and _wraps_ a dispatch function
     /// for this host function, with the specific type of the dispatch function,
     /// into a Func in the Store.
@@ -41,7 +49,7 @@ macro_rules! generate_host_function_infos {
                     // pattern-repetition matcher so that it will match all such
                     // descriptions.
                     $(#[$fn_attr:meta])*
-                    { $fn_id:literal, fn $func_id:ident $args:tt -> $ret:ty }
+                    { $fn_id:literal, $($min_proto:literal)?, fn $func_id:ident ($($arg:ident:$type:ty),*) -> $ret:ty }
                 )*
             }
         )*
@@ -66,7 +74,7 @@ macro_rules! generate_host_function_infos {
                 $(
                     // This generates a HostFuncInfo struct directly
                     // for each function matched in the token-tree (invoking
-                    // the arity_helper! macro above to calculate the arity
+                    // counting the matched arguments to calculate the arity
                     // of each function along the way). It is embedded in two
                     // nested `$()*` pattern-repetition expanders that
                     // correspond to the pattern-repetition matchers in the
@@ -77,6 +85,8 @@ macro_rules! generate_host_function_infos {
                     HostFuncInfo {
                         mod_str: $mod_str,
                         fn_str: $fn_id,
+                        arity: <[&str]>::len(&[$(stringify!($arg)),*]),
+                        min_proto: None $(.or(Some($min_proto)))?,
                         wrap: |store| Func::wrap(store, dispatch::$func_id),
                     },
                 )*
@@ -0,0 +1,186 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::xdr::Hash;
+
+const WASM_HEADER_LEN: usize = 8;
+const CUSTOM_SECTION_ID: u8 = 0;
+const EXPORT_SECTION_ID: u8 = 7;
+const EXTERNAL_KIND_FUNC: u8 = 0;
+const NAME_SECTION_NAME: &[u8] = b"name";
+const FUNCTION_NAMES_SUBSECTION_ID: u8 = 1;
+
+/// A single frame of a [ContractInvocationStack]: a Wasm contract function
+/// invocation that was in progress when the guest trapped. This only
+/// identifies the function that has been invoked by the host, which is not
+/// necessarily the function that trapped, as that may have been called from
+/// the invoked one within the contract.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractInvocationFrame {
+    pub contract_id: Hash,
+    /// Name of the invoked contract function, i.e. of the module export.
+    pub export: String,
+    /// Index of the Wasm function backing the export, if known.
+    pub invoked_func_index: Option<u32>,
+    /// Name of the Wasm function backing the export from the module `name`
+    /// section, if the module has one.
+    pub invoked_func_name: Option<String>,
+}
+
+/// The stack of the Wasm contract function invocations that were in progress
+/// when a contract trapped, newest frame first.
+///
+/// This is not a backtrace of the guest, as wasmi doesn't expose the guest
+/// call stack: there is one frame per contract invocation on the host context
+/// stack, i.e. the invoked function of the trapping contract followed by all
+/// the contract functions that have (transitively) called it. The functions
+/// called within a contract, including the one that actually trapped, are not
+/// included.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ContractInvocationStack {
+    pub frames: Vec<ContractInvocationFrame>,
+}
+
+impl ContractInvocationFrame {
+    pub(crate) fn new(contract_id: Hash, export: &str, names: Option<&WasmNames>) -> Self {
+        let invoked_func_index = names.and_then(|n| n.exports.get(export).copied());
+        let invoked_func_name = invoked_func_index.and_then(|i| names?.functions.get(&i).cloned());
+        Self {
+            contract_id,
+            export: export.to_string(),
+            invoked_func_index,
+            invoked_func_name,
+        }
+    }
+}
+
+impl ContractInvocationStack {
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+impl fmt::Display for ContractInvocationFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.export)?;
+        match (self.invoked_func_index, &self.invoked_func_name) {
+            (Some(index), Some(name)) => write!(f, " (invoked func[{index}] <{name}>)")?,
+            (Some(index), None) => write!(f, " (invoked func[{index}])")?,
+            _ => (),
+        }
+        write!(
+            f,
+            " in contract {}",
+            stellar_strkey::Contract(self.contract_id.0)
+        )
+    }
+}
+
+impl fmt::Display for ContractInvocationStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, frame) in self.frames.iter().enumerate() {
+            writeln!(f, "   {}: {}", i, frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// Function names of a module, used for naming the invoked Wasm functions of
+/// the [ContractInvocationFrame]s. These are parsed directly from the module
+/// code, as wasmi doesn't retain them, and only when a contract traps in
+/// debug mode.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct WasmNames {
+    exports: BTreeMap<String, u32>,
+    functions: BTreeMap<u32, String>,
+}
+
+impl WasmNames {
+    /// Parses the function exports and the function names subsection of the
+    /// `name` section of an already validated module. Returns `None` if the
+    /// code can't be parsed.
+    pub(crate) fn parse(code: &[u8]) -> Option<Self> {
+        let mut names = Self::default();
+        let mut reader = Reader(code.get(WASM_HEADER_LEN..)?);
+        while !reader.0.is_empty() {
+            let id = reader.u8()?;
+            let len = reader.u32()? as usize;
+            let mut section = Reader(reader.bytes(len)?);
+            match id {
+                EXPORT_SECTION_ID => names.parse_exports(&mut section)?,
+                CUSTOM_SECTION_ID if section.bytes_vec()? == NAME_SECTION_NAME => {
+                    names.parse_name_section(&mut section)?
+                }
+                _ => (),
+            }
+        }
+        Some(names)
+    }
+
+    fn parse_exports(&mut self, section: &mut Reader<'_>) -> Option<()> {
+        for _ in 0..section.u32()? {
+            let name = section.string()?;
+            let kind = section.u8()?;
+            let index = section.u32()?;
+            if kind == EXTERNAL_KIND_FUNC {
+                self.exports.insert(name, index);
+            }
+        }
+        Some(())
+    }
+
+    fn parse_name_section(&mut self, section: &mut Reader<'_>) -> Option<()> {
+        while !section.0.is_empty() {
+            let id = section.u8()?;
+            let len = section.u32()? as usize;
+            let mut subsection = Reader(section.bytes(len)?);
+            if id == FUNCTION_NAMES_SUBSECTION_ID {
+                for _ in 0..subsection.u32()? {
+                    let index = subsection.u32()?;
+                    let name = subsection.string()?;
+                    self.functions.insert(index, name);
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.0.len() {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    // Reads an unsigned LEB128-encoded u32.
+    fn u32(&mut self) -> Option<u32> {
+        let mut res: u32 = 0;
+        for shift in (0..35).step_by(7) {
+            let b = self.u8()?;
+            res |= ((b & 0x7f) as u32).checked_shl(shift)?;
+            if b & 0x80 == 0 {
+                return Some(res);
+            }
+        }
+        None
+    }
+
+    fn bytes_vec(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.bytes_vec()
+            .map(|b| String::from_utf8_lossy(b).into_owned())
+    }
+}
@@ -0,0 +1,192 @@
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use wasmi::{Engine, Module};
+
+use crate::{budget::FuelConfig, xdr::Hash, HostError};
+
+/// A cache of parsed and validated Wasm modules keyed by the Wasm hash, which
+/// allows skipping the module parsing for the repeated instantiations of the
+/// same contract. This only saves the embedder's CPU time: the instantiations
+/// are charged to the budget the same way with or without the cache.
+///
+/// The cache is a cheaply cloneable handle, so the embedder may share a single
+/// cache across any number of [Host](crate::Host)s (see
+/// [Host::set_module_cache](crate::Host::set_module_cache)). The modules are
+/// only valid for the fuel configuration of the budget they have been created
+/// with, so using the cache with a host with a different fuel configuration
+/// invalidates all the cached modules.
+///
+/// The total size of the Wasm code of the cached modules is bounded by
+/// `max_wasm_bytes`; the least recently used modules are evicted when the
+/// bound is exceeded. The cache bookkeeping is not metered, as it is owned by
+/// the embedder and outlives any single host.
+#[derive(Clone)]
+pub struct ModuleCache(Rc<RefCell<ModuleCacheImpl>>);
+
+struct CachedModule {
+    module: Rc<Module>,
+    wasm_size: usize,
+    last_used: u64,
+}
+
+struct ModuleCacheImpl {
+    // All the cached modules have been created with this engine.
+    engine: Option<(FuelConfig, Engine)>,
+    modules: BTreeMap<Hash, CachedModule>,
+    max_wasm_bytes: usize,
+    wasm_bytes: usize,
+    use_counter: u64,
+}
+
+impl ModuleCache {
+    /// Creates an empty cache that holds at most `max_wasm_bytes` of Wasm
+    /// code. Modules larger than that are never cached.
+    pub fn new(max_wasm_bytes: usize) -> Self {
+        Self(Rc::new(RefCell::new(ModuleCacheImpl {
+            engine: None,
+            modules: BTreeMap::new(),
+            max_wasm_bytes,
+            wasm_bytes: 0,
+            use_counter: 0,
+        })))
+    }
+
+    /// Returns the number of cached modules.
+    pub fn len(&self) -> usize {
+        self.0.borrow().modules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the total size of the Wasm code of the cached modules.
+    pub fn wasm_bytes(&self) -> usize {
+        self.0.borrow().wasm_bytes
+    }
+
+    /// Returns whether a module with the given Wasm hash is cached.
+    pub fn contains(&self, wasm_hash: &Hash) -> bool {
+        self.0.borrow().modules.contains_key(wasm_hash)
+    }
+
+    /// Removes the module with the given Wasm hash from the cache and returns
+    /// whether it has been cached.
+    pub fn invalidate(&self, wasm_hash: &Hash) -> bool {
+        let mut cache = self.0.borrow_mut();
+        match cache.modules.remove(wasm_hash) {
+            Some(removed) => {
+                cache.wasm_bytes -= removed.wasm_size;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes all the modules from the cache.
+    pub fn clear(&self) {
+        let mut cache = self.0.borrow_mut();
+        cache.modules.clear();
+        cache.wasm_bytes = 0;
+    }
+
+    // Returns the engine to create and instantiate the cached modules with.
+    // If the cache engine has been created for a different `fuel_config`, a
+    // new engine is created via `new_engine` and all the modules created with
+    // the old one are dropped.
+    pub(crate) fn engine_for(
+        &self,
+        fuel_config: &FuelConfig,
+        new_engine: impl FnOnce() -> Result<Engine, HostError>,
+    ) -> Result<Engine, HostError> {
+        let mut cache = self.0.borrow_mut();
+        if let Some((config, engine)) = &cache.engine {
+            if config == fuel_config {
+                return Ok(engine.clone());
+            }
+        }
+        let engine = new_engine()?;
+        cache.engine = Some((fuel_config.clone(), engine.clone()));
+        cache.modules.clear();
+        cache.wasm_bytes = 0;
+        Ok(engine)
+    }
+
+    pub(crate) fn get(&self, wasm_hash: &Hash) -> Option<Rc<Module>> {
+        let mut cache = self.0.borrow_mut();
+        cache.use_counter += 1;
+        let use_counter = cache.use_counter;
+        let cached = cache.modules.get_mut(wasm_hash)?;
+        cached.last_used = use_counter;
+        Some(Rc::clone(&cached.module))
+    }
+
+    pub(crate) fn insert(&self, wasm_hash: &Hash, module: Rc<Module>, wasm_size: usize) {
+        let mut cache = self.0.borrow_mut();
+        if wasm_size > cache.max_wasm_bytes || cache.modules.contains_key(wasm_hash) {
+            return;
+        }
+        while cache.wasm_bytes + wasm_size > cache.max_wasm_bytes {
+            let Some(lru) = cache
+                .modules
+                .iter()
+                .min_by_key(|(_, m)| m.last_used)
+                .map(|(hash, _)| hash.clone())
+            else {
+                break;
+            };
+            if let Some(evicted) = cache.modules.remove(&lru) {
+                cache.wasm_bytes -= evicted.wasm_size;
+            }
+        }
+        cache.use_counter += 1;
+        let last_used = cache.use_counter;
+        cache.wasm_bytes += wasm_size;
+        cache.modules.insert(
+            wasm_hash.clone(),
+            CachedModule {
+                module,
+                wasm_size,
+                last_used,
+            },
+        );
+    }
+}
+
+/// The modules instantiated by a single [Host](crate::Host), keyed by the
+/// Wasm hash. Unlike the [ModuleCache], the contents of this only depend on
+/// the contracts the host has instantiated so far, so every replica of the
+/// host agrees on whether a contract has been instantiated before, which is
+/// what allows charging the repeated instantiations as
+/// [VmCachedInstantiation](crate::xdr::ContractCostType::VmCachedInstantiation).
+#[derive(Clone, Default)]
+pub(crate) struct InstantiatedModules(BTreeMap<Hash, (FuelConfig, Engine, Rc<Module>)>);
+
+impl InstantiatedModules {
+    // Returns the module with the given Wasm hash and the engine it has been
+    // created with, unless the module has been created for a different
+    // `fuel_config`.
+    pub(crate) fn get(
+        &self,
+        wasm_hash: &Hash,
+        fuel_config: &FuelConfig,
+    ) -> Option<(Engine, Rc<Module>)> {
+        match self.0.get(wasm_hash) {
+            Some((config, engine, module)) if config == fuel_config => {
+                Some((engine.clone(), Rc::clone(module)))
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        wasm_hash: &Hash,
+        fuel_config: FuelConfig,
+        engine: Engine,
+        module: Rc<Module>,
+    ) {
+        self.0
+            .insert(wasm_hash.clone(), (fuel_config, engine, module));
+    }
+}
@@ -0,0 +1,192 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{budget::AsBudget, xdr::Hash, Host, HostError};
+
+/// Budget charged by calls to a single host function from a contract
+/// function, see [InvocationProfile].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HostFunctionProfile {
+    pub calls: u64,
+    /// CPU instructions charged by the calls, including the dispatch and any
+    /// nested contract invocations they have made.
+    pub cpu_insns: u64,
+    pub mem_bytes: u64,
+}
+
+/// Budget consumed by a single invocation of a Wasm contract function (i.e.
+/// of a module export), recorded when the VM profiling is enabled (see
+/// [Host::enable_vm_profiling]).
+///
+/// The profile is per invoked export, not per Wasm function: the fuel is
+/// attributed at the host/VM boundary crossings, i.e. at the contract
+/// function entry and exit and at every host function call, and wasmi
+/// doesn't expose the guest call stack in between. So the fuel consumed by
+/// all the Wasm functions the export calls internally, and the host functions
+/// called from any of them, are attributed to the export as a whole.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InvocationProfile {
+    pub contract_id: Hash,
+    /// Name of the invoked contract function, i.e. of the module export.
+    pub export: String,
+    /// Number of the enclosing Wasm contract invocations.
+    pub depth: usize,
+    /// Fuel consumed by the Wasm code executed during the invocation,
+    /// excluding the nested contract invocations.
+    pub wasm_fuel: u64,
+    /// CPU instructions charged for `wasm_fuel`.
+    pub wasm_cpu_insns: u64,
+    /// Host functions called during the invocation, keyed by name.
+    pub host_functions: BTreeMap<&'static str, HostFunctionProfile>,
+}
+
+impl InvocationProfile {
+    /// Returns the CPU instructions charged by the invocation, excluding the
+    /// contract instantiation.
+    pub fn total_cpu_insns(&self) -> u64 {
+        self.host_functions
+            .values()
+            .fold(self.wasm_cpu_insns, |acc, hf| {
+                acc.saturating_add(hf.cpu_insns)
+            })
+    }
+}
+
+impl fmt::Display for InvocationProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:indent$}`{}` in contract {}",
+            "",
+            self.export,
+            stellar_strkey::Contract(self.contract_id.0),
+            indent = 2 * self.depth
+        )?;
+        writeln!(
+            f,
+            "{:<32} {:>8} {:>14} {:>14}",
+            "function", "calls", "cpu_insns", "mem_bytes"
+        )?;
+        writeln!(
+            f,
+            "{:<32} {:>8} {:>14} {:>14}",
+            "<wasm>", "", self.wasm_cpu_insns, ""
+        )?;
+        for (name, hf) in self.host_functions.iter() {
+            writeln!(
+                f,
+                "{:<32} {:>8} {:>14} {:>14}",
+                name, hf.calls, hf.cpu_insns, hf.mem_bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct VmProfiler {
+    // All the recorded invocations in the order they have started.
+    invocations: Vec<InvocationProfile>,
+    // Indices of the invocations that are in progress, innermost last.
+    active: Vec<usize>,
+}
+
+impl VmProfiler {
+    pub(crate) fn invocations(&self) -> &[InvocationProfile] {
+        &self.invocations
+    }
+}
+
+/// Budget consumption at the start of a profiled host function call.
+pub(crate) struct ProfileCheckpoint {
+    cpu_insns: u64,
+    mem_bytes: u64,
+}
+
+impl Host {
+    pub(crate) fn is_vm_profiling_enabled(&self) -> Result<bool, HostError> {
+        Ok(self.try_borrow_vm_profiler()?.is_some())
+    }
+
+    pub(crate) fn profile_invocation_start(
+        &self,
+        contract_id: &Hash,
+        export: &str,
+    ) -> Result<(), HostError> {
+        if let Some(profiler) = self.try_borrow_vm_profiler_mut()?.as_mut() {
+            profiler.active.push(profiler.invocations.len());
+            profiler.invocations.push(InvocationProfile {
+                contract_id: contract_id.clone(),
+                export: export.to_string(),
+                depth: profiler.active.len() - 1,
+                wasm_fuel: 0,
+                wasm_cpu_insns: 0,
+                host_functions: BTreeMap::new(),
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn profile_invocation_end(&self) -> Result<(), HostError> {
+        if let Some(profiler) = self.try_borrow_vm_profiler_mut()?.as_mut() {
+            profiler.active.pop();
+        }
+        Ok(())
+    }
+
+    // Attributes the fuel consumed by the VM since the last boundary crossing
+    // to the innermost active invocation.
+    pub(crate) fn profile_wasm_fuel(&self, fuel: u64, cpu_insns: u64) -> Result<(), HostError> {
+        if let Some(profiler) = self.try_borrow_vm_profiler_mut()?.as_mut() {
+            if let Some(&i) = profiler.active.last() {
+                let invocation = &mut profiler.invocations[i];
+                invocation.wasm_fuel = invocation.wasm_fuel.saturating_add(fuel);
+                invocation.wasm_cpu_insns = invocation.wasm_cpu_insns.saturating_add(cpu_insns);
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the checkpoint to pass to `profile_host_function` when the
+    // profiling is enabled.
+    pub(crate) fn profile_checkpoint(&self) -> Result<Option<ProfileCheckpoint>, HostError> {
+        if !self.is_vm_profiling_enabled()? {
+            return Ok(None);
+        }
+        Ok(Some(ProfileCheckpoint {
+            cpu_insns: self.as_budget().get_cpu_insns_consumed()?,
+            mem_bytes: self.as_budget().get_mem_bytes_consumed()?,
+        }))
+    }
+
+    // Attributes the budget charged since `checkpoint` to the host function
+    // `name` called by the innermost active invocation.
+    pub(crate) fn profile_host_function(
+        &self,
+        name: &'static str,
+        checkpoint: Option<ProfileCheckpoint>,
+    ) -> Result<(), HostError> {
+        let Some(checkpoint) = checkpoint else {
+            return Ok(());
+        };
+        let cpu_insns = self
+            .as_budget()
+            .get_cpu_insns_consumed()?
+            .saturating_sub(checkpoint.cpu_insns);
+        let mem_bytes = self
+            .as_budget()
+            .get_mem_bytes_consumed()?
+            .saturating_sub(checkpoint.mem_bytes);
+        if let Some(profiler) = self.try_borrow_vm_profiler_mut()?.as_mut() {
+            if let Some(&i) = profiler.active.last() {
+                let hf = profiler.invocations[i]
+                    .host_functions
+                    .entry(name)
+                    .or_default();
+                hf.calls = hf.calls.saturating_add(1);
+                hf.cpu_insns = hf.cpu_insns.saturating_add(cpu_insns);
+                hf.mem_bytes = hf.mem_bytes.saturating_add(mem_bytes);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+#![cfg(feature = "cli")]
+
+use std::{
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+use soroban_env_host::{
+    budget::Budget,
+    invocation_bundle::{BudgetConfig, InvocationBundle},
+    meta,
+    xdr::{
+        AccountId, ConfigSettingContractComputeV0, ConfigSettingEntry, Hash, HostFunction,
+        LedgerEntry, LedgerEntryData, LedgerEntryExt, LedgerFootprint, LedgerKey,
+        LedgerKeyContractCode, PublicKey, SorobanResources, Uint256, WriteXdr,
+    },
+    LedgerInfo, DEFAULT_XDR_RW_LIMITS,
+};
+use soroban_test_wasms::ADD_I32;
+
+fn protocol_version() -> u32 {
+    meta::get_ledger_protocol_version(meta::INTERFACE_VERSION)
+}
+
+fn ledger_info_json() -> String {
+    format!(
+        r#"{{
+            "protocol_version": {},
+            "sequence_number": 100,
+            "timestamp": 12345,
+            "network_passphrase": "Test SDF Network ; September 2015",
+            "base_reserve": 5000000,
+            "min_temp_entry_ttl": 16,
+            "min_persistent_entry_ttl": 4096,
+            "max_entry_ttl": 6312000
+        }}"#,
+        protocol_version()
+    )
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "soroban-env-host-cli-{}-{name}",
+        std::process::id()
+    ))
+}
+
+fn write_file(name: &str, contents: impl AsRef<[u8]>) -> String {
+    let path = temp_path(name);
+    std::fs::write(&path, contents).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_soroban-env-host"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn upload_wasm_host_fn() -> HostFunction {
+    HostFunction::UploadContractWasm(ADD_I32.try_into().unwrap())
+}
+
+fn upload_wasm_resources(instructions: u32) -> SorobanResources {
+    let code_key = LedgerKey::ContractCode(LedgerKeyContractCode {
+        hash: Hash(sha256(ADD_I32)),
+    });
+    SorobanResources {
+        footprint: LedgerFootprint {
+            read_only: Default::default(),
+            read_write: vec![code_key].try_into().unwrap(),
+        },
+        instructions,
+        read_bytes: 0,
+        write_bytes: 10_000,
+    }
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(data).into()
+}
+
+fn source_account() -> AccountId {
+    AccountId(PublicKey::PublicKeyTypeEd25519(Uint256([1; 32])))
+}
+
+fn upload_wasm_bundle() -> InvocationBundle {
+    let budget = Budget::default();
+    InvocationBundle::new(
+        true,
+        upload_wasm_host_fn().to_xdr(DEFAULT_XDR_RW_LIMITS).unwrap(),
+        upload_wasm_resources(100_000_000)
+            .to_xdr(DEFAULT_XDR_RW_LIMITS)
+            .unwrap(),
+        source_account().to_xdr(DEFAULT_XDR_RW_LIMITS).unwrap(),
+        vec![],
+        LedgerInfo {
+            protocol_version: protocol_version(),
+            sequence_number: 100,
+            timestamp: 12345,
+            network_id: [5; 32],
+            base_reserve: 5_000_000,
+            min_temp_entry_ttl: 16,
+            min_persistent_entry_ttl: 4096,
+            max_entry_ttl: 6_312_000,
+        },
+        vec![],
+        vec![],
+        [7; 32].to_vec(),
+        BudgetConfig {
+            cpu_limit: 100_000_000,
+            mem_limit: 40 * 1024 * 1024,
+            cpu_cost_params: budget.get_cpu_cost_params().unwrap(),
+            mem_cost_params: budget.get_mem_cost_params().unwrap(),
+        },
+    )
+}
+
+fn write_bundle(name: &str, bundle: &InvocationBundle) -> String {
+    let mut buf = vec![];
+    bundle.write_to(&mut buf).unwrap();
+    write_file(name, buf)
+}
+
+fn config_setting_entry(setting: ConfigSettingEntry) -> String {
+    LedgerEntry {
+        last_modified_ledger_seq: 0,
+        data: LedgerEntryData::ConfigSetting(setting),
+        ext: LedgerEntryExt::V0,
+    }
+    .to_xdr_base64(DEFAULT_XDR_RW_LIMITS)
+    .unwrap()
+}
+
+/// Writes the flag files of the wasm upload invocation and returns the
+/// corresponding arguments.
+fn upload_wasm_args(prefix: &str, instructions: u32) -> Vec<String> {
+    let files = [
+        (
+            "--host-fn",
+            "host-fn",
+            upload_wasm_host_fn().to_xdr_base64(DEFAULT_XDR_RW_LIMITS),
+        ),
+        (
+            "--resources",
+            "resources",
+            upload_wasm_resources(instructions).to_xdr_base64(DEFAULT_XDR_RW_LIMITS),
+        ),
+        (
+            "--source-account",
+            "source-account",
+            source_account().to_xdr_base64(DEFAULT_XDR_RW_LIMITS),
+        ),
+    ];
+    let mut args = vec![];
+    for (flag, name, contents) in files {
+        args.push(flag.to_string());
+        args.push(write_file(&format!("{prefix}-{name}"), contents.unwrap()));
+    }
+    args.push("--ledger-info".to_string());
+    args.push(write_file(
+        &format!("{prefix}-ledger-info.json"),
+        ledger_info_json(),
+    ));
+    args
+}
+
+#[test]
+fn replays_bundle() {
+    let mut bundle = upload_wasm_bundle();
+    bundle.invoke_and_record(&mut vec![]).unwrap();
+    let path = write_bundle("replay-bundle.json", &bundle);
+    let output = run(&["--bundle", &path]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Replay: identical to the recorded outcome"));
+
+    bundle.outcome.as_mut().unwrap().ledger_changes[0].old_entry_size_bytes = 1;
+    let path = write_bundle("replay-tampered-bundle.json", &bundle);
+    let output = run(&["--bundle", &path]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("ledger change 0 differs"));
+}
+
+#[test]
+fn rejects_bundle_combined_with_other_arguments() {
+    let path = write_bundle("combined-bundle.json", &upload_wasm_bundle());
+    let seed = "00".repeat(32);
+    let extra_args: [&[&str]; 3] = [
+        &["--no-diagnostics"],
+        &["--prng-seed", seed.as_str()],
+        &["--snapshot", path.as_str()],
+    ];
+    for extra_args in extra_args {
+        let mut args = vec!["--bundle", path.as_str()];
+        args.extend_from_slice(extra_args);
+        let output = run(&args);
+        assert_eq!(output.status.code(), Some(2));
+        assert!(stderr(&output).contains("'--bundle' can't be combined with other arguments"));
+    }
+}
+
+#[test]
+fn rejects_malformed_prng_seed() {
+    let mut args = upload_wasm_args("seed", 100_000_000);
+    args.push("--prng-seed".to_string());
+    for seed in [
+        "+1".repeat(32),
+        "0".repeat(63),
+        "00".repeat(33),
+        "zz".repeat(32),
+    ] {
+        let mut args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+        args.push(&seed);
+        let output = run(&args);
+        assert_eq!(output.status.code(), Some(2));
+        assert!(stderr(&output).contains("PRNG seed must be 32 hex-encoded bytes"));
+    }
+}
+
+#[test]
+fn budget_is_limited_by_transaction_instructions() {
+    let budget = Budget::default();
+    let config = [
+        ConfigSettingEntry::ContractComputeV0(ConfigSettingContractComputeV0 {
+            ledger_max_instructions: 100_000_000,
+            tx_max_instructions: 100_000_000,
+            fee_rate_per_instructions_increment: 100,
+            tx_memory_limit: 40 * 1024 * 1024,
+        }),
+        ConfigSettingEntry::ContractCostParamsCpuInstructions(
+            budget.get_cpu_cost_params().unwrap(),
+        ),
+        ConfigSettingEntry::ContractCostParamsMemoryBytes(budget.get_mem_cost_params().unwrap()),
+    ]
+    .into_iter()
+    .map(config_setting_entry)
+    .collect::<Vec<_>>()
+    .join("\n");
+    let config_path = write_file("budget-config", config);
+
+    let mut args = upload_wasm_args("budget", 100_000_000);
+    args.extend(["--config".to_string(), config_path.clone()]);
+    let output = run(&args.iter().map(|s| s.as_str()).collect::<Vec<_>>());
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("warning"));
+
+    // The invocation doesn't fit into the declared instructions.
+    let mut args = upload_wasm_args("budget-low", 1000);
+    args.extend(["--config".to_string(), config_path]);
+    let output = run(&args.iter().map(|s| s.as_str()).collect::<Vec<_>>());
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("ExceededLimit"));
+
+    // Without the configuration the default cost parameters are used.
+    let args = upload_wasm_args("budget-default", 100_000_000);
+    let output = run(&args.iter().map(|s| s.as_str()).collect::<Vec<_>>());
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("warning: no network configuration settings provided"));
+}
+
+#[test]
+fn config_without_cost_params_is_rejected() {
+    let config = config_setting_entry(ConfigSettingEntry::ContractComputeV0(
+        ConfigSettingContractComputeV0 {
+            ledger_max_instructions: 100_000_000,
+            tx_max_instructions: 100_000_000,
+            fee_rate_per_instructions_increment: 100,
+            tx_memory_limit: 40 * 1024 * 1024,
+        },
+    ));
+    let mut args = upload_wasm_args("partial-config", 100_000_000);
+    args.extend(["--config".to_string(), write_file("partial-config", config)]);
+    let output = run(&args.iter().map(|s| s.as_str()).collect::<Vec<_>>());
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("failed to load the network configuration settings"));
+}
@@ -90,10 +90,11 @@ pub fn generate(file_lit: LitStr) -> Result<TokenStream, Error> {
             });
 
             let r#return = format_ident!("{}", &f.r#return);
+            let min_proto = f.min_supported_protocol;
 
             quote! {
                 #[doc = #docs]
-                { #export, fn #name(#(#args),*) -> #r#return }
+                { #export, #min_proto, fn #name(#(#args),*) -> #r#return }
             }
         });
 
@@ -120,12 +121,14 @@ pub fn generate(file_lit: LitStr) -> Result<TokenStream, Error> {
                     //
                     //  mod $mod_id:ident $mod_str:literal {
                     //     ...
-                    //     { $fn_str:literal, fn $fn_id:ident $args:tt -> $ret:ty }
+                    //     { $fn_str:literal, $($min_proto:literal)?, fn $fn_id:ident $args:tt -> $ret:ty }
                     //     ...
                     //  }
                     //
                     // Where the sub token-tree $args:tt is a normal parenthesized
-                    // argument list of comma-separated arg:type pairs
+                    // argument list of comma-separated arg:type pairs, and the
+                    // optional $min_proto is the first ledger protocol version
+                    // in which contracts may import the function
 
                     #(#modules)*
                 }
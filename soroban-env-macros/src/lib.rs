@@ -99,6 +99,13 @@ pub(crate) struct Function {
     pub(crate) args: Vec<Arg>,
     pub(crate) r#return: String,
     pub(crate) docs: Option<String>,
+    /// Whether the function is only available with the `next` feature
+    /// enabled.
+    #[serde(default)]
+    pub(crate) next: bool,
+    /// The first ledger protocol version in which contracts may import the
+    /// function, if it is newer than the protocols of the other functions.
+    pub(crate) min_supported_protocol: Option<u32>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -119,12 +126,25 @@ fn load_env_file(file_lit: LitStr) -> Result<Root, syn::Error> {
         )
     })?;
 
-    serde_json::from_reader(file).map_err(|e| {
+    let root: Root = serde_json::from_reader(file).map_err(|e| {
         syn::Error::new(
             file_lit.span(),
             format!("error parsing file '{file_str}': {e}"),
         )
-    })
+    })?;
+
+    // The functions only available in the `next` builds are dropped from the
+    // interface entirely otherwise. These have to come last in their modules
+    // in order to keep the export names of the remaining functions stable.
+    #[cfg(not(feature = "next"))]
+    let root = {
+        let mut root = root;
+        for m in root.modules.iter_mut() {
+            m.functions.retain(|f| !f.next);
+        }
+        root
+    };
+    Ok(root)
 }
 
 #[proc_macro]